@@ -0,0 +1,406 @@
+use anyhow::{Context, Result, bail};
+use clap::{Arg, ArgAction, Command};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Project configuration file, looked up in the current folder and its parents.
+pub const PROJECT_FILE: &str = ".gpto.toml";
+
+/// Prefix of the environment variable of every option (`GPTO_THREADS`, `GPTO_TARGET_FS`, ...).
+pub const ENV_PREFIX: &str = "GPTO_";
+
+/// Command run when none is given, whose section also configures the flag-only invocation.
+const DEFAULT_COMMAND: &str = "organize";
+
+/// A configured value and where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub values: Vec<String>,
+    pub origin: String,
+}
+
+/// Settings of the configuration files per command, with the selected profile applied.
+///
+/// Files hold a table per command (`[organize]`, `[gallery]`, ...) whose keys are option
+/// names, and named profiles below `[profiles.<name>.<command>]`. The user file is read first,
+/// then the project file; a profile overrides the plain sections of both.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub files: Vec<PathBuf>,
+    pub profile: Option<String>,
+    sections: BTreeMap<String, BTreeMap<String, Setting>>,
+}
+
+impl Config {
+    /// Loads the user and project configuration files, or only `explicit` when given.
+    pub fn load(explicit: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let files: Vec<PathBuf> = match explicit {
+            Some(path) => vec![path.to_path_buf()],
+            None => [user_config_file(), project_config_file()]
+                .into_iter()
+                .flatten()
+                .filter(|path| path.is_file())
+                .collect(),
+        };
+
+        let mut tables = Vec::new();
+        for file in &files {
+            let content = fs::read_to_string(file)
+                .with_context(|| format!("Failed to read configuration {:?}", file))?;
+            let table: Table = content
+                .parse()
+                .with_context(|| format!("Invalid configuration {:?}", file))?;
+            tables.push((file.display().to_string(), table));
+        }
+
+        let mut config = Self::from_tables(&tables, profile)?;
+        config.files = files;
+        Ok(config)
+    }
+
+    /// Builds the settings from parsed files (origin, content), in increasing priority.
+    fn from_tables(tables: &[(String, Table)], profile: Option<&str>) -> Result<Self> {
+        let mut config = Self {
+            profile: profile.map(str::to_string),
+            ..Default::default()
+        };
+        for (origin, table) in tables {
+            config.merge(table, origin)?;
+        }
+
+        if let Some(name) = profile {
+            let mut found = false;
+            for (origin, table) in tables {
+                let Some(profile) = table.get("profiles").and_then(|p| p.get(name)) else {
+                    continue;
+                };
+                let profile = profile
+                    .as_table()
+                    .with_context(|| format!("Profile '{}' in {} is not a table", name, origin))?;
+                config.merge(profile, &format!("profile {} ({})", name, origin))?;
+                found = true;
+            }
+            if !found {
+                bail!("Unknown profile '{}'", name);
+            }
+        }
+        Ok(config)
+    }
+
+    fn merge(&mut self, table: &Table, origin: &str) -> Result<()> {
+        for (section, values) in table {
+            if section == "profiles" {
+                continue;
+            }
+            let values = values.as_table().with_context(|| {
+                format!("[{}] in {} must be a table of settings", section, origin)
+            })?;
+            for (key, value) in values {
+                let setting = Setting {
+                    values: setting_values(value)
+                        .with_context(|| format!("Invalid value of {} in {}", key, origin))?,
+                    origin: origin.to_string(),
+                };
+                self.sections
+                    .entry(section.clone())
+                    .or_default()
+                    .insert(key.replace('-', "_"), setting);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `--config`, `--profile` and an environment variable for every option, and makes
+    /// the configured settings the defaults, so clap ranks them: command line > environment >
+    /// profile > configuration files > built-in defaults.
+    pub fn apply(&self, cmd: Command) -> Result<Command> {
+        let mut cmd = with_env(cmd)
+            .arg(
+                Arg::new("config")
+                    .long("config")
+                    .global(true)
+                    .env(format!("{}CONFIG", ENV_PREFIX))
+                    .help("Configuration file to use instead of the user and project ones"),
+            )
+            .arg(
+                Arg::new("profile")
+                    .long("profile")
+                    .global(true)
+                    .env(format!("{}PROFILE", ENV_PREFIX))
+                    .help("Named profile of the configuration files to apply"),
+            );
+
+        for (section, settings) in &self.sections {
+            let Some(subcommand) = cmd.find_subcommand(section) else {
+                bail!("Unknown section [{}] in the configuration", section);
+            };
+            for (key, setting) in settings {
+                if !subcommand.get_arguments().any(|arg| arg.get_id() == key) {
+                    bail!(
+                        "Unknown setting '{}' in [{}] ({})",
+                        key,
+                        section,
+                        setting.origin
+                    );
+                }
+            }
+            cmd = cmd.mut_subcommand(section, |sub| with_defaults(sub, settings));
+            if section == DEFAULT_COMMAND {
+                cmd = with_defaults(cmd, settings);
+            }
+        }
+        Ok(cmd)
+    }
+
+    /// The effective value of every option of `command` (as configured by [`Config::apply`])
+    /// and where it comes from, leaving out command-line flags.
+    pub fn effective(&self, cmd: &Command, command: &str) -> Result<Vec<(String, String, String)>> {
+        let Some(subcommand) = cmd.find_subcommand(command) else {
+            bail!("Unknown command '{}'", command);
+        };
+        let configured = self.sections.get(command);
+
+        let mut lines = Vec::new();
+        for arg in subcommand.get_arguments() {
+            let id = arg.get_id().as_str();
+            if arg.is_positional() || matches!(id, "help" | "version" | "config" | "profile") {
+                continue;
+            }
+            let env_value = arg.get_env().and_then(|name| {
+                let value = env::var(name).ok()?;
+                Some((name.to_string_lossy().to_string(), value))
+            });
+            let (values, origin) = match (env_value, configured.and_then(|s| s.get(id))) {
+                (Some((name, value)), _) => (vec![value], format!("env {}", name)),
+                (None, Some(setting)) => (setting.values.clone(), setting.origin.clone()),
+                (None, None) if !arg.get_default_values().is_empty() => (
+                    arg.get_default_values()
+                        .iter()
+                        .map(|v| v.to_string_lossy().to_string())
+                        .collect(),
+                    "default".to_string(),
+                ),
+                (None, None) => continue,
+            };
+            let multiple = matches!(arg.get_action(), ArgAction::Append);
+            lines.push((
+                id.replace('_', "-"),
+                render_values(&values, multiple),
+                origin,
+            ));
+        }
+        Ok(lines)
+    }
+
+    /// Prints the effective configuration of `command` as a TOML section.
+    pub fn print(&self, cmd: &Command, command: &str) -> Result<()> {
+        let lines = self.effective(cmd, command)?;
+        if self.files.is_empty() {
+            println!("# No configuration file");
+        }
+        for file in &self.files {
+            println!("# Configuration file: {}", file.display());
+        }
+        if let Some(profile) = &self.profile {
+            println!("# Profile: {}", profile);
+        }
+        println!("[{}]", command);
+        let width = lines
+            .iter()
+            .map(|(key, value, _)| key.len() + value.len() + 3)
+            .max()
+            .unwrap_or(0);
+        for (key, value, origin) in lines {
+            let line = format!("{} = {}", key, value);
+            println!("{:width$}  # {}", line, origin, width = width);
+        }
+        Ok(())
+    }
+}
+
+/// `--config` and `--profile` decide the defaults the command line is parsed with, so they
+/// are read from the raw arguments (or their environment variables) first.
+pub fn early_option(args: &[OsString], name: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let mut args = args.iter().map(|a| a.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == flag {
+            return args.next().map(|value| value.to_string());
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{}=", flag)) {
+            return Some(value.to_string());
+        }
+    }
+    env::var(format!("{}{}", ENV_PREFIX, name.to_uppercase())).ok()
+}
+
+fn user_config_file() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("gpto").join("config.toml"))
+}
+
+fn project_config_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+fn with_env(cmd: Command) -> Command {
+    cmd.mut_args(add_env)
+        .mut_subcommands(|sub| sub.mut_args(add_env))
+}
+
+fn add_env(arg: Arg) -> Arg {
+    if arg.is_positional() {
+        return arg;
+    }
+    let name = format!("{}{}", ENV_PREFIX, arg.get_id().as_str().to_uppercase());
+    arg.env(name)
+}
+
+fn with_defaults(mut cmd: Command, settings: &BTreeMap<String, Setting>) -> Command {
+    for (key, setting) in settings {
+        if !cmd.get_arguments().any(|arg| arg.get_id() == key) {
+            continue;
+        }
+        let values = setting.values.clone();
+        cmd = cmd.mut_arg(key, |arg| arg.default_values(values).required(false));
+    }
+    cmd
+}
+
+fn setting_values(value: &Value) -> Result<Vec<String>> {
+    match value {
+        Value::Array(items) => items.iter().map(setting_value).collect(),
+        value => Ok(vec![setting_value(value)?]),
+    }
+}
+
+fn setting_value(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        other => bail!("unsupported value {}", other),
+    })
+}
+
+fn render_values(values: &[String], multiple: bool) -> String {
+    let rendered: Vec<String> = values
+        .iter()
+        .map(|value| {
+            if value.parse::<i64>().is_ok() || value.parse::<bool>().is_ok() {
+                value.clone()
+            } else {
+                Value::String(value.clone()).to_string()
+            }
+        })
+        .collect();
+    if multiple {
+        format!("[{}]", rendered.join(", "))
+    } else {
+        rendered.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+    #[derive(Parser, Debug)]
+    struct TestCli {
+        #[command(subcommand)]
+        command: TestCommand,
+    }
+
+    #[derive(Subcommand, Debug)]
+    enum TestCommand {
+        Organize {
+            #[arg(short, long)]
+            output: String,
+            #[arg(short = 'j', long, default_value_t = 4)]
+            threads: usize,
+            #[arg(long, default_value_t = false)]
+            verify_copies: bool,
+        },
+    }
+
+    fn tables(files: &[&str]) -> Vec<(String, Table)> {
+        files
+            .iter()
+            .enumerate()
+            .map(|(i, content)| (format!("file{}", i), content.parse().unwrap()))
+            .collect()
+    }
+
+    fn parse(config: &Config, args: &[&str]) -> (String, usize, bool) {
+        let matches = config
+            .apply(TestCli::command())
+            .unwrap()
+            .try_get_matches_from(args)
+            .unwrap();
+        let TestCommand::Organize {
+            output,
+            threads,
+            verify_copies,
+        } = TestCli::from_arg_matches(&matches).unwrap().command;
+        (output, threads, verify_copies)
+    }
+
+    #[test]
+    fn test_profile_overrides_files_and_cli_overrides_profile() {
+        let files = tables(&[
+            "[organize]\noutput = \"/user\"\nthreads = 2\n",
+            "[organize]\nverify-copies = true\n\n[profiles.nas.organize]\nthreads = 1\noutput = \"/mnt/nas\"\n",
+        ]);
+
+        let config = Config::from_tables(&files, None).unwrap();
+        assert_eq!(
+            parse(&config, &["gpto", "organize"]),
+            ("/user".to_string(), 2, true)
+        );
+
+        let config = Config::from_tables(&files, Some("nas")).unwrap();
+        assert_eq!(
+            parse(&config, &["gpto", "organize"]),
+            ("/mnt/nas".to_string(), 1, true)
+        );
+        assert_eq!(
+            parse(&config, &["gpto", "organize", "-j", "8", "-o", "/tmp"]),
+            ("/tmp".to_string(), 8, true)
+        );
+
+        assert!(Config::from_tables(&files, Some("laptop")).is_err());
+    }
+
+    #[test]
+    fn test_unknown_settings_are_rejected() {
+        let config = Config::from_tables(&tables(&["[organize]\nthreds = 2\n"]), None).unwrap();
+        assert!(config.apply(TestCli::command()).is_err());
+        let config = Config::from_tables(&tables(&["[stats]\noutput = \"x\"\n"]), None).unwrap();
+        assert!(config.apply(TestCli::command()).is_err());
+    }
+
+    #[test]
+    fn test_early_option() {
+        let args: Vec<OsString> = ["gpto", "--profile=nas", "organize", "--config", "a.toml"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(early_option(&args, "profile"), Some("nas".to_string()));
+        assert_eq!(early_option(&args, "config"), Some("a.toml".to_string()));
+    }
+}
@@ -1,14 +1,16 @@
 use anyhow::Result;
 use std::path::{Path, PathBuf};
 
-use super::utils::{get_date_from_path, is_video};
+use super::library::Library;
+use super::utils::{MediaKind, escape_html, get_date_from_path};
 
 pub fn build_html(
     current_dir: &Path,
     root_path: &Path,
     subdirs: &[PathBuf],
-    images: &[PathBuf],
-    flattened_images: &[PathBuf],
+    images: &[(PathBuf, MediaKind)],
+    flattened_images: &[(PathBuf, MediaKind)],
+    library: &Library,
 ) -> Result<String> {
     let relative_path = current_dir.strip_prefix(root_path).unwrap_or(Path::new(""));
     let title = if relative_path.as_os_str().is_empty() {
@@ -35,7 +37,8 @@ pub fn build_html(
         &header_tmpl
             .replace("{title}", &title)
             .replace("{styles}", css)
-            .replace("{toggle_btn}", toggle_btn),
+            .replace("{toggle_btn}", toggle_btn)
+            .replace("{owner_filter}", &library.owners.filter_html()),
     );
 
     // Breadcrumb
@@ -90,15 +93,20 @@ pub fn build_html(
             r#"    <div id="flattened-gallery" class="gallery">
 "#,
         );
-        html.push_str(&generate_images_html(images, current_dir, root_path));
+        html.push_str(&generate_images_html(
+            images,
+            current_dir,
+            root_path,
+            library,
+        ));
 
-        for image in flattened_images {
+        for (image, kind) in flattened_images {
             let path_str = image.to_string_lossy();
             let filename = image.file_name().unwrap().to_string_lossy();
 
             let full_path = current_dir.join(image);
             let date_str = get_date_from_path(&full_path, root_path).unwrap_or_default();
-            let is_vid = is_video(&full_path);
+            let is_vid = *kind == MediaKind::Video;
 
             let thumb_rel_path = image
                 .parent()
@@ -124,12 +132,14 @@ pub fn build_html(
                 }
             }
 
-            html.push_str(&generate_photo_html(
-                &src_url,
-                &display_src,
+            html.push_str(&generate_card_html(
+                (&src_url, &display_src),
                 &filename,
                 &date_str,
                 is_vid,
+                &full_path,
+                current_dir,
+                library,
             ));
         }
         html.push_str("    </div>\n");
@@ -170,7 +180,12 @@ pub fn build_html(
             r#"    <div class="gallery">
 "#,
         );
-        html.push_str(&generate_images_html(images, current_dir, root_path));
+        html.push_str(&generate_images_html(
+            images,
+            current_dir,
+            root_path,
+            library,
+        ));
         html.push_str("    </div>\n");
     }
 
@@ -196,6 +211,8 @@ fn generate_photo_html(
     alt: &str,
     date: &str,
     is_video: bool,
+    owners: &[String],
+    original: Option<(&str, &str)>,
 ) -> String {
     let tmpl = include_str!("templates/photo_card.html");
     let type_str = if is_video { "video" } else { "image" };
@@ -211,15 +228,31 @@ fn generate_photo_html(
         .replace("{date}", date)
         .replace("{type}", type_str)
         .replace("{play_icon}", play_icon)
+        .replace("{owners}", &escape_html(&owners.join(" ")))
+        .replace(
+            "{edit_toggle}",
+            &original
+                .map(|(src, display_src)| {
+                    include_str!("templates/edit_toggle.html")
+                        .replace("{alt_src}", src)
+                        .replace("{alt_display}", display_src)
+                })
+                .unwrap_or_default(),
+        )
 }
 
-fn generate_images_html(images: &[PathBuf], current_dir: &Path, root_path: &Path) -> String {
+fn generate_images_html(
+    images: &[(PathBuf, MediaKind)],
+    current_dir: &Path,
+    root_path: &Path,
+    library: &Library,
+) -> String {
     let mut html = String::new();
-    for image in images {
+    for (image, kind) in images {
         let filename = image.file_name().unwrap().to_string_lossy();
         let full_path = current_dir.join(image);
         let date_str = get_date_from_path(&full_path, root_path).unwrap_or_default();
-        let is_vid = is_video(&full_path);
+        let is_vid = *kind == MediaKind::Video;
 
         let thumb_path = Path::new(".thumbnails").join(format!("{}.jpg", filename));
         let display_src = if thumb_path.exists() || is_vid {
@@ -237,24 +270,90 @@ fn generate_images_html(images: &[PathBuf], current_dir: &Path, root_path: &Path
             }
         }
 
-        html.push_str(&generate_photo_html(
-            &src_url,
-            &display_src,
+        html.push_str(&generate_card_html(
+            (&src_url, &display_src),
             &filename,
             &date_str,
             is_vid,
+            &full_path,
+            current_dir,
+            library,
         ));
     }
     html
 }
 
+/// Card of one library file. Originals with a linked edited copy show the edited version
+/// first, with a button to flip back to the original.
+fn generate_card_html(
+    (src, display_src): (&str, &str),
+    alt: &str,
+    date: &str,
+    is_video: bool,
+    full_path: &Path,
+    current_dir: &Path,
+    library: &Library,
+) -> String {
+    let owners = library.owners.get(full_path);
+    let Some(edited) = library.edits.edited_version(full_path) else {
+        return generate_photo_html(src, display_src, alt, date, is_video, owners, None);
+    };
+
+    let edited_src = relative_href(current_dir, edited);
+    let edited_thumb = edited
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(".thumbnails")
+        .join(format!(
+            "{}.jpg",
+            edited.file_name().unwrap_or_default().to_string_lossy()
+        ));
+    let edited_display = if edited_thumb.exists() {
+        relative_href(current_dir, &edited_thumb)
+    } else {
+        edited_src.clone()
+    };
+
+    generate_photo_html(
+        &edited_src,
+        &edited_display,
+        alt,
+        date,
+        is_video,
+        owners,
+        Some((src, display_src)),
+    )
+}
+
+/// Link from pages in `from_dir` to `target`, both inside the library.
+fn relative_href(from_dir: &Path, target: &Path) -> String {
+    let common = from_dir
+        .ancestors()
+        .find(|ancestor| target.starts_with(ancestor))
+        .unwrap_or(Path::new(""));
+    let ups = from_dir
+        .strip_prefix(common)
+        .map(|rest| rest.components().count())
+        .unwrap_or_default();
+    let rest = target.strip_prefix(common).unwrap_or(target);
+    "../".repeat(ups) + &rest.to_string_lossy()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_generate_photo_html() {
-        let html = generate_photo_html("img.jpg", "img.jpg", "img.jpg", "2023-01-01", false);
+        let html = generate_photo_html(
+            "img.jpg",
+            "img.jpg",
+            "img.jpg",
+            "2023-01-01",
+            false,
+            &[],
+            None,
+        );
         assert!(html.contains("href=\"img.jpg\""));
         assert!(html.contains("src=\"img.jpg\""));
         assert!(html.contains("data-type=\"image\""));
@@ -266,11 +365,28 @@ mod tests {
             "vid.mp4",
             "2023-01-01",
             true,
+            &["alice".to_string()],
+            None,
         );
         assert!(html_vid.contains("href=\"vid.mp4\""));
         assert!(html_vid.contains("src=\".thumbnails/vid.mp4.jpg\""));
         assert!(html_vid.contains("data-type=\"video\""));
         assert!(html_vid.contains("play-icon"));
+        assert!(html_vid.contains("data-owners=\"alice\""));
+        assert!(!html_vid.contains("edit-toggle"));
+
+        let html_edit = generate_photo_html(
+            "IMG_1-edited.jpg",
+            "IMG_1-edited.jpg",
+            "IMG_1.jpg",
+            "2023-01-01",
+            false,
+            &[],
+            Some(("IMG_1.jpg", ".thumbnails/IMG_1.jpg.jpg")),
+        );
+        assert!(html_edit.contains("href=\"IMG_1-edited.jpg\""));
+        assert!(html_edit.contains("data-alt-src=\"IMG_1.jpg\""));
+        assert!(html_edit.contains("data-alt-display=\".thumbnails/IMG_1.jpg.jpg\""));
     }
 
     #[test]
@@ -280,7 +396,15 @@ mod tests {
         let images = vec![];
         let flattened = vec![];
 
-        let html = build_html(root, root, &subdirs, &images, &flattened).unwrap();
+        let html = build_html(
+            root,
+            root,
+            &subdirs,
+            &images,
+            &flattened,
+            &Library::default(),
+        )
+        .unwrap();
 
         assert!(html.contains("<h1>Photo Collection</h1>"));
         assert!(!html.contains("id=\"toggle-btn\"")); // No toggle button
@@ -293,9 +417,17 @@ mod tests {
         let current = root.join("2023/01");
         let subdirs = vec![current.join("01")]; // One subdir
         let images = vec![];
-        let flattened = vec![PathBuf::from("01/img.jpg")];
-
-        let html = build_html(&current, root, &subdirs, &images, &flattened).unwrap();
+        let flattened = vec![(PathBuf::from("01/img.jpg"), MediaKind::Image)];
+
+        let html = build_html(
+            &current,
+            root,
+            &subdirs,
+            &images,
+            &flattened,
+            &Library::default(),
+        )
+        .unwrap();
 
         assert!(html.contains("<title>2023/01 - Photo Organizer</title>"));
         assert!(html.contains("id=\"toggle-btn\"")); // Toggle present
@@ -308,10 +440,23 @@ mod tests {
     fn test_build_html_breadcrumbs() {
         let root = Path::new("/tmp/root");
         let current = root.join("2023/01/01");
-        let html = build_html(&current, root, &[], &[], &[]).unwrap();
+        let html = build_html(&current, root, &[], &[], &[], &Library::default()).unwrap();
 
         assert!(html.contains("href=\"../../../collection.html\"")); // 3 levels up
         assert!(html.contains("2023"));
         assert!(html.contains("01"));
     }
+
+    #[test]
+    fn test_relative_href() {
+        let root = Path::new("/tmp/root");
+        assert_eq!(
+            relative_href(&root.join("2023/May"), &root.join("2023/May/01/a.jpg")),
+            "01/a.jpg"
+        );
+        assert_eq!(
+            relative_href(&root.join("2023/May/01"), &root.join("2023/May/02/a.jpg")),
+            "../02/a.jpg"
+        );
+    }
 }
@@ -0,0 +1,79 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::organizer::catalog::Catalog;
+
+/// Originals and their edited copies, linked by the organizer in the catalog, so the gallery
+/// shows one card per photo that flips between both versions.
+#[derive(Debug, Default)]
+pub struct EditPairs {
+    edited_of: HashMap<PathBuf, PathBuf>,
+    edited: HashSet<PathBuf>,
+}
+
+impl EditPairs {
+    pub fn from_catalog(root_path: &Path, catalog: &Catalog) -> Self {
+        let mut pairs = Self::default();
+        for (key, entry) in &catalog.entries {
+            let Some(original) = entry
+                .edit_of
+                .as_deref()
+                .filter(|original| catalog.entries.contains_key(*original))
+            else {
+                continue;
+            };
+            let edited = root_path.join(key);
+            pairs
+                .edited_of
+                .insert(root_path.join(original), edited.clone());
+            pairs.edited.insert(edited);
+        }
+        pairs
+    }
+
+    /// The edited copy shown on the card of `original`.
+    pub fn edited_version(&self, original: &Path) -> Option<&Path> {
+        self.edited_of.get(original).map(PathBuf::as_path)
+    }
+
+    /// Edited copies get no card of their own; they are reached from their original.
+    pub fn is_linked_edit(&self, path: &Path) -> bool {
+        self.edited.contains(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CatalogEntry;
+
+    #[test]
+    fn test_edit_pairs_from_catalog() {
+        let root = Path::new("/library");
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2020/May/01/IMG_1.jpg".to_string(),
+            CatalogEntry {
+                source: "Trip/IMG_1.jpg".to_string(),
+                ..Default::default()
+            },
+        );
+        catalog.insert(
+            "2020/May/01/IMG_1-edited.jpg".to_string(),
+            CatalogEntry {
+                source: "Trip/IMG_1-edited.jpg".to_string(),
+                edit_of: Some("2020/May/01/IMG_1.jpg".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let pairs = EditPairs::from_catalog(root, &catalog);
+        let edited = root.join("2020/May/01/IMG_1-edited.jpg");
+        assert_eq!(
+            pairs.edited_version(&root.join("2020/May/01/IMG_1.jpg")),
+            Some(edited.as_path())
+        );
+        assert!(pairs.is_linked_edit(&edited));
+        assert!(!pairs.is_linked_edit(&root.join("2020/May/01/IMG_1.jpg")));
+    }
+}
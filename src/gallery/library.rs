@@ -0,0 +1,56 @@
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::edits::EditPairs;
+use super::owners::OwnerTags;
+use super::utils::{self, MediaKind};
+use crate::organizer::catalog::Catalog;
+
+/// What the gallery knows about the library beyond its folders, read from the catalog.
+#[derive(Debug, Default)]
+pub struct Library {
+    pub owners: OwnerTags,
+    pub edits: EditPairs,
+    /// Kinds of the files already sniffed in this run, so pages don't read them again
+    media: Mutex<HashMap<PathBuf, Option<MediaKind>>>,
+}
+
+impl Library {
+    /// Libraries without a catalog get plain galleries.
+    pub fn load(root_path: &Path) -> Self {
+        match Catalog::load(root_path) {
+            Ok(catalog) => Self {
+                owners: OwnerTags::from_catalog(root_path, &catalog),
+                edits: EditPairs::from_catalog(root_path, &catalog),
+                media: Mutex::default(),
+            },
+            Err(e) => {
+                warn!("Gallery ignores the library catalog: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn with_media(self, media: &[(PathBuf, MediaKind)]) -> Self {
+        if let Ok(mut known) = self.media.lock() {
+            known.extend(media.iter().map(|(path, kind)| (path.clone(), Some(*kind))));
+        }
+        self
+    }
+
+    /// Kind of a gallery file, sniffing only files this run has not seen yet.
+    pub fn media_kind(&self, path: &Path) -> Option<MediaKind> {
+        if let Ok(known) = self.media.lock()
+            && let Some(kind) = known.get(path)
+        {
+            return *kind;
+        }
+        let kind = utils::media_kind(path);
+        if let Ok(mut known) = self.media.lock() {
+            known.insert(path.to_path_buf(), kind);
+        }
+        kind
+    }
+}
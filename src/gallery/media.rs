@@ -3,6 +3,8 @@ use log::warn;
 use std::path::Path;
 use std::process::Command;
 
+use crate::organizer::cancel::{self, PartialFile};
+
 pub fn check_ffmpeg_available() -> bool {
     Command::new("ffmpeg")
         .arg("-version")
@@ -65,25 +67,27 @@ pub fn transcode_video_to_h264(input_path: &Path, output_path: &Path) -> Result<
     }
 
     // ffmpeg -threads 1 -i input.mp4 -c:v libx264 -crf 23 -preset fast -c:a aac -b:a 128k output.mp4
-    let output = Command::new("ffmpeg")
-        .arg("-threads")
-        .arg("1")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-c:v")
-        .arg("libx264")
-        .arg("-crf")
-        .arg("23") // Good quality/size balance
-        .arg("-preset")
-        .arg("fast") // Faster encoding
-        .arg("-c:a")
-        .arg("aac")
-        .arg("-b:a")
-        .arg("128k")
-        .arg("-y") // Overwrite
-        .arg(output_path)
-        .output()
-        .context("Failed to run ffmpeg for transcoding")?;
+    let partial = PartialFile::new(output_path);
+    let output = cancel::run_child(
+        Command::new("ffmpeg")
+            .arg("-threads")
+            .arg("1")
+            .arg("-i")
+            .arg(input_path)
+            .arg("-c:v")
+            .arg("libx264")
+            .arg("-crf")
+            .arg("23") // Good quality/size balance
+            .arg("-preset")
+            .arg("fast") // Faster encoding
+            .arg("-c:a")
+            .arg("aac")
+            .arg("-b:a")
+            .arg("128k")
+            .arg("-y") // Overwrite
+            .arg(partial.path()),
+    )
+    .context("Failed to run ffmpeg for transcoding")?;
 
     if !output.status.success() {
         warn!(
@@ -94,7 +98,7 @@ pub fn transcode_video_to_h264(input_path: &Path, output_path: &Path) -> Result<
         return Err(anyhow::anyhow!("Transcoding failed"));
     }
 
-    Ok(())
+    partial.commit()
 }
 
 pub fn generate_thumbnail(video_path: &Path, thumb_path: &Path, time_pos: f64) -> Result<()> {
@@ -106,21 +110,23 @@ pub fn generate_thumbnail(video_path: &Path, thumb_path: &Path, time_pos: f64) -
         std::fs::create_dir_all(parent)?;
     }
 
-    let output = Command::new("ffmpeg")
-        .arg("-threads")
-        .arg("1")
-        .arg("-ss")
-        .arg(format!("{:.3}", time_pos))
-        .arg("-i")
-        .arg(video_path)
-        .arg("-vframes")
-        .arg("1")
-        .arg("-q:v")
-        .arg("2") // High quality jpeg
-        .arg("-y") // Overwrite
-        .arg(thumb_path)
-        .output()
-        .context("Failed to run ffmpeg")?;
+    let partial = PartialFile::new(thumb_path);
+    let output = cancel::run_child(
+        Command::new("ffmpeg")
+            .arg("-threads")
+            .arg("1")
+            .arg("-ss")
+            .arg(format!("{:.3}", time_pos))
+            .arg("-i")
+            .arg(video_path)
+            .arg("-vframes")
+            .arg("1")
+            .arg("-q:v")
+            .arg("2") // High quality jpeg
+            .arg("-y") // Overwrite
+            .arg(partial.path()),
+    )
+    .context("Failed to run ffmpeg")?;
 
     if !output.status.success() {
         warn!(
@@ -128,9 +134,10 @@ pub fn generate_thumbnail(video_path: &Path, thumb_path: &Path, time_pos: f64) -
             video_path,
             String::from_utf8_lossy(&output.stderr)
         );
+        return Ok(());
     }
 
-    Ok(())
+    partial.commit()
 }
 
 pub fn generate_image_thumbnail(image_path: &Path, thumb_path: &Path) -> Result<()> {
@@ -139,13 +146,19 @@ pub fn generate_image_thumbnail(image_path: &Path, thumb_path: &Path) -> Result<
         std::fs::create_dir_all(parent)?;
     }
 
-    let img = image::open(image_path).context("Failed to open image")?;
+    // Guess the format from the content so files with a wrong extension still get a thumbnail
+    let img = image::ImageReader::open(image_path)
+        .context("Failed to open image")?
+        .with_guessed_format()?
+        .decode()
+        .context("Failed to decode image")?;
     let thumbnail = img.thumbnail(400, 400);
+    let partial = PartialFile::new(thumb_path);
     thumbnail
-        .save(thumb_path)
+        .save(partial.path())
         .context("Failed to save thumbnail")?;
 
-    Ok(())
+    partial.commit()
 }
 
 #[cfg(test)]
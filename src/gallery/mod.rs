@@ -1,6 +1,10 @@
 mod builder;
+mod edits;
 mod images;
+mod library;
 mod media;
+mod owners;
+pub mod serve;
 mod throttle;
 mod traversal;
 mod utils;
@@ -9,12 +13,23 @@ mod videos;
 use anyhow::Result;
 use indicatif::ProgressBar;
 use log::{info, warn};
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
-pub use self::utils::{is_image, is_video};
+use self::library::Library;
+use self::utils::MediaKind;
+use crate::organizer::cancel;
+use crate::organizer::progress::{self, Event, FileResult, Phase};
 
-pub fn generate_gallery(root_path: &Path, threads: usize, transcode_videos: bool) -> Result<()> {
+pub use self::throttle::ThrottleSettings;
+
+pub fn generate_gallery(
+    root_path: &Path,
+    threads: usize,
+    transcode_videos: bool,
+    throttle: &ThrottleSettings,
+) -> Result<()> {
     info!("Generating HTML gallery in {:?}", root_path);
 
     let has_ffmpeg = media::check_ffmpeg_available();
@@ -27,11 +42,11 @@ pub fn generate_gallery(root_path: &Path, threads: usize, transcode_videos: bool
         threads, transcode_videos
     );
 
-    let media_paths = collect_media_paths(root_path);
-    let total_files = media_paths.len();
+    let media = collect_media(root_path);
+    let total_files = media.len();
 
-    use indicatif::{MultiProgress, ProgressStyle};
-    let multi_progress_pb = MultiProgress::new();
+    use indicatif::ProgressStyle;
+    let multi_progress_pb = crate::organizer::ui::multi_progress();
 
     let pb_style = ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({msg})")?
@@ -42,18 +57,106 @@ pub fn generate_gallery(root_path: &Path, threads: usize, transcode_videos: bool
     pb.set_message("Processing Media");
     crate::organizer::ui::set_global_progress_bar(pb.clone());
 
-    let to_transcode = process_thumbnails(media_paths, has_ffmpeg, transcode_videos, &pb);
+    let to_transcode = process_thumbnails(&media, has_ffmpeg, transcode_videos, &pb);
+
+    if transcode_videos {
+        transcode_videos_parallel(to_transcode, &multi_progress_pb, &pb_style, throttle);
+    }
+
+    if cancel::is_cancelled() {
+        warn!("Gallery cancelled; thumbnails done so far are kept");
+        return Ok(());
+    }
+    generate_html_gallery(root_path, &media, &multi_progress_pb, pb_style)?;
+
+    Ok(())
+}
+
+/// Thumbnails and pages for `new_files` added to the library since the gallery was last
+/// generated, leaving the rest of the gallery alone.
+pub fn update_gallery(
+    root_path: &Path,
+    new_files: &[PathBuf],
+    transcode_videos: bool,
+    throttle: &ThrottleSettings,
+) -> Result<()> {
+    let media: Vec<(PathBuf, MediaKind)> = new_files
+        .iter()
+        .filter_map(|p| Some((p.clone(), utils::media_kind(p)?)))
+        .collect();
+    if media.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Adding {} files to the gallery in {:?}",
+        media.len(),
+        root_path
+    );
+
+    let has_ffmpeg = media::check_ffmpeg_available();
+    let dirs: BTreeSet<PathBuf> = media
+        .iter()
+        .filter_map(|(p, _)| p.parent())
+        .map(Path::to_path_buf)
+        .collect();
+
+    use indicatif::ProgressStyle;
+    let multi_progress_pb = crate::organizer::ui::multi_progress();
+    let pb_style = ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({msg})")?
+        .progress_chars("#>-");
+    let pb = multi_progress_pb.add(ProgressBar::new(media.len() as u64));
+    pb.set_style(pb_style.clone());
+    pb.set_message("Processing Media");
+    crate::organizer::ui::set_global_progress_bar(pb.clone());
 
+    let to_transcode = process_thumbnails(&media, has_ffmpeg, transcode_videos, &pb);
     if transcode_videos {
-        transcode_videos_parallel(to_transcode, &multi_progress_pb, &pb_style);
+        transcode_videos_parallel(to_transcode, &multi_progress_pb, &pb_style, throttle);
+    }
+    if cancel::is_cancelled() {
+        return Ok(());
     }
 
-    generate_html_gallery(root_path, total_files, &multi_progress_pb, pb_style)?;
+    let phase = Phase::start("html", None);
+    let library = Library::load(root_path).with_media(&media);
+    write_pages(root_path, &dirs, &library)?;
+    phase.finish();
+    Ok(())
+}
+
+/// Rewrites the gallery pages of `dirs` and of every folder above them, after files moved
+/// between them. Other pages are left untouched.
+pub fn regenerate_pages(root_path: &Path, dirs: &BTreeSet<PathBuf>) -> Result<()> {
+    write_pages(root_path, dirs, &Library::load(root_path))
+}
 
+fn write_pages(root_path: &Path, dirs: &BTreeSet<PathBuf>, library: &Library) -> Result<()> {
+    let mut pages = BTreeSet::new();
+    for dir in dirs {
+        pages.extend(
+            dir.ancestors()
+                .take_while(|d| d.starts_with(root_path))
+                .filter(|d| d.is_dir())
+                .map(Path::to_path_buf),
+        );
+    }
+    info!(
+        "Regenerating {} gallery pages in {:?}",
+        pages.len(),
+        root_path
+    );
+
+    let pb = ProgressBar::hidden();
+    // Deepest first, like a full run, so parents see their final subfolders
+    for dir in pages.iter().rev() {
+        traversal::write_dir_page(dir, root_path, library, &pb)?;
+    }
     Ok(())
 }
 
-fn collect_media_paths(root_path: &Path) -> Vec<std::path::PathBuf> {
+/// Every gallery file under `root_path` with its kind.
+fn collect_media(root_path: &Path) -> Vec<(PathBuf, MediaKind)> {
     walkdir::WalkDir::new(root_path)
         .into_iter()
         .filter_entry(|e| {
@@ -65,35 +168,43 @@ fn collect_media_paths(root_path: &Path) -> Vec<std::path::PathBuf> {
         })
         .filter_map(|e| e.ok())
         .filter(|e| e.file_type().is_file())
-        .filter(|e| is_image(e.path()) || is_video(e.path()))
-        .map(|e| e.path().to_path_buf())
+        .filter_map(|e| Some((e.path().to_path_buf(), utils::media_kind(e.path())?)))
         .collect()
 }
 
 fn process_thumbnails(
-    media_paths: Vec<std::path::PathBuf>,
+    media: &[(PathBuf, MediaKind)],
     has_ffmpeg: bool,
     transcode_videos: bool,
     pb: &ProgressBar,
 ) -> Vec<std::path::PathBuf> {
     use rayon::prelude::*;
     let to_transcode = Mutex::new(Vec::new());
+    let phase = Phase::start("thumbnails", Some(media.len() as u64));
 
-    media_paths.into_par_iter().for_each(|path| {
-        match videos::ensure_thumbnail_fast(&path, has_ffmpeg) {
+    media.par_iter().for_each(|(path, kind)| {
+        if cancel::is_cancelled() {
+            return;
+        }
+        match videos::ensure_thumbnail_fast(path, *kind, has_ffmpeg) {
             Ok(needs_transcode) => {
+                progress::file(&path.display().to_string(), FileResult::Thumbnail, None);
                 if needs_transcode
                     && transcode_videos
                     && let Ok(mut list) = to_transcode.lock()
                 {
-                    list.push(path);
+                    list.push(path.clone());
                 }
             }
-            Err(e) => warn!("Failed to process {:?}: {}", path, e),
+            Err(e) => {
+                warn!("Failed to process {:?}: {}", path, e);
+                progress::failed(&path.display().to_string(), &e.to_string());
+            }
         }
         pb.inc(1);
     });
     pb.finish_with_message("Thumbnails Done");
+    phase.finish();
 
     to_transcode.into_inner().unwrap_or_default()
 }
@@ -102,27 +213,35 @@ fn transcode_videos_parallel(
     videos: Vec<std::path::PathBuf>,
     multi_progress_pb: &indicatif::MultiProgress,
     pb_style: &indicatif::ProgressStyle,
+    settings: &ThrottleSettings,
 ) {
     if videos.is_empty() {
         return;
     }
 
     use rayon::prelude::*;
+    let phase = Phase::start("transcode", Some(videos.len() as u64));
     let transcoding_videos_pb = multi_progress_pb.add(ProgressBar::new(videos.len() as u64));
     transcoding_videos_pb.set_style(pb_style.clone());
     transcoding_videos_pb.set_message("Transcoding Videos (Smart Parallel)");
     crate::organizer::ui::set_global_progress_bar(transcoding_videos_pb.clone());
 
     // Process in parallel, throttling based on free system memory and time
-    let throttle = throttle::Throttle::new(
-        std::time::Duration::from_secs(3),
-        std::time::Duration::from_secs(1),
-    );
+    let throttle = throttle::Throttle::with_settings(settings, std::time::Duration::from_secs(1));
 
     videos.into_par_iter().for_each(|path| {
+        if cancel::is_cancelled() {
+            return;
+        }
         let mut sys = sysinfo::System::new();
 
         let (active, percent_available, limit, fallback) = throttle.wait_for_slot(&mut sys);
+        let (running, allowed) = throttle.get_stats();
+        progress::emit(&Event::Throttle {
+            active: running,
+            limit: allowed,
+            free_memory_percent: percent_available,
+        });
 
         if fallback {
             transcoding_videos_pb.set_message(format!(
@@ -136,8 +255,12 @@ fn transcode_videos_parallel(
             ));
         }
 
-        if let Err(e) = videos::transcode_sequential(&path) {
-            warn!("Failed to transcode {:?}: {}", path, e);
+        match videos::transcode_sequential(&path) {
+            Ok(()) => progress::file(&path.display().to_string(), FileResult::Transcoded, None),
+            Err(e) => {
+                warn!("Failed to transcode {:?}: {}", path, e);
+                progress::failed(&path.display().to_string(), &e.to_string());
+            }
         }
 
         let (active_now, current_limit) = throttle.release_slot();
@@ -155,21 +278,25 @@ fn transcode_videos_parallel(
         transcoding_videos_pb.inc(1);
     });
     transcoding_videos_pb.finish_with_message("Transcoding Done");
+    phase.finish();
 }
 
 fn generate_html_gallery(
     root_path: &Path,
-    total_files: usize,
+    media: &[(PathBuf, MediaKind)],
     multi_progress_pb: &indicatif::MultiProgress,
     pb_style: indicatif::ProgressStyle,
 ) -> Result<()> {
-    let pb_html = multi_progress_pb.add(ProgressBar::new(total_files as u64));
+    let pb_html = multi_progress_pb.add(ProgressBar::new(media.len() as u64));
     pb_html.set_style(pb_style);
     pb_html.set_message("Generating HTML");
     crate::organizer::ui::set_global_progress_bar(pb_html.clone());
 
-    traversal::visit_dir(root_path, root_path, &pb_html)?;
+    let phase = Phase::start("html", None);
+    let library = Library::load(root_path).with_media(media);
+    traversal::visit_dir(root_path, root_path, &library, &pb_html)?;
     pb_html.finish_with_message("Gallery Done");
+    phase.finish();
     Ok(())
 }
 
@@ -191,7 +318,7 @@ mod tests {
         fs::write(root.join("2020/01/01/img2.jpg"), "").unwrap();
 
         // Run generation
-        let result = generate_gallery(root, 1, false);
+        let result = generate_gallery(root, 1, false, &ThrottleSettings::default());
         assert!(result.is_ok());
 
         // Check root HTML
@@ -236,7 +363,7 @@ mod tests {
 
         // Should return false (not HEVC).
         // We ignore the error from image library since we are testing path logic
-        let _ = videos::ensure_thumbnail_fast(&img_path, false);
+        let _ = videos::ensure_thumbnail_fast(&img_path, MediaKind::Image, false);
 
         // Check if .thumbnails was created
         assert!(root.join(".thumbnails").exists());
@@ -0,0 +1,96 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::utils::escape_html;
+use crate::organizer::catalog::Catalog;
+
+/// Account owners of the library files, read from the catalog so the gallery can filter
+/// merged Takeouts by account.
+#[derive(Debug, Default)]
+pub struct OwnerTags {
+    by_path: HashMap<PathBuf, Vec<String>>,
+    all: BTreeSet<String>,
+}
+
+impl OwnerTags {
+    /// Libraries without labelled inputs have no owners.
+    pub fn from_catalog(root_path: &Path, catalog: &Catalog) -> Self {
+        let mut tags = Self::default();
+        for (key, entry) in &catalog.entries {
+            if entry.owners.is_empty() {
+                continue;
+            }
+            tags.all.extend(entry.owners.iter().cloned());
+            tags.by_path
+                .insert(root_path.join(key), entry.owners.clone());
+        }
+        tags
+    }
+
+    pub fn get(&self, path: &Path) -> &[String] {
+        self.by_path
+            .get(path)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Owner drop-down for the gallery header, empty when there is a single account.
+    pub fn filter_html(&self) -> String {
+        if self.all.len() < 2 {
+            return String::new();
+        }
+
+        let mut html = String::from(
+            r#"<select id="owner-filter" class="owner-filter" onchange="applyFilters()">
+                <option value="all">All accounts</option>
+"#,
+        );
+        for owner in &self.all {
+            html.push_str(&format!(
+                "                <option value=\"{0}\">{0}</option>\n",
+                escape_html(owner)
+            ));
+        }
+        html.push_str("            </select>");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CatalogEntry;
+
+    #[test]
+    fn test_owner_tags_from_catalog() {
+        let root = Path::new("/library");
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2020/May/01/shared.jpg".to_string(),
+            CatalogEntry {
+                owners: vec!["alice".to_string(), "bob".to_string()],
+                ..Default::default()
+            },
+        );
+        catalog.insert("2020/May/01/mine.jpg".to_string(), CatalogEntry::default());
+
+        let tags = OwnerTags::from_catalog(root, &catalog);
+        assert_eq!(
+            tags.get(&root.join("2020/May/01/shared.jpg")),
+            ["alice", "bob"]
+        );
+        assert!(tags.get(&root.join("2020/May/01/mine.jpg")).is_empty());
+        let filter = tags.filter_html();
+        assert!(filter.contains("<option value=\"bob\">bob</option>"));
+
+        catalog.insert(
+            "2020/May/02/party.jpg".to_string(),
+            CatalogEntry {
+                owners: vec!["Tom & \"Jerry\"".to_string()],
+                ..Default::default()
+            },
+        );
+        let filter = OwnerTags::from_catalog(root, &catalog).filter_html();
+        assert!(filter.contains("value=\"Tom &amp; &quot;Jerry&quot;\""));
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+/// Serves the gallery of the library at `root` over HTTP until the process is stopped.
+pub fn serve(root: &Path, listen: &str) -> Result<()> {
+    let listener =
+        TcpListener::bind(listen).with_context(|| format!("Failed to listen on {}", listen))?;
+    info!(
+        "Serving {:?} at http://{}/ (Ctrl-C to stop)",
+        root,
+        listener.local_addr()?
+    );
+
+    for stream in listener.incoming().flatten() {
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &root) {
+                debug!("Request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, root: &Path) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("range")
+        {
+            range = parse_range(value.trim());
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    if method != "GET" && method != "HEAD" {
+        return respond_status(&mut stream, "405 Method Not Allowed");
+    }
+    let Some(path) = resolve(root, target) else {
+        return respond_status(&mut stream, "404 Not Found");
+    };
+
+    let mut file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    let (status, start, end) = match range {
+        Some((start, end)) if start < len && end.is_none_or(|end| end >= start) => (
+            "206 Partial Content",
+            start,
+            end.unwrap_or(len - 1).min(len - 1),
+        ),
+        _ => ("200 OK", 0, len.saturating_sub(1)),
+    };
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+
+    let mut header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n",
+        status,
+        content_type(&path),
+        body_len
+    );
+    if status.starts_with("206") {
+        header.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start, end, len
+        ));
+    }
+    header.push_str("Connection: close\r\n\r\n");
+    stream.write_all(header.as_bytes())?;
+
+    if method == "GET" {
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut file.take(body_len), &mut stream)?;
+    }
+    Ok(())
+}
+
+fn respond_status(stream: &mut TcpStream, status: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )?;
+    Ok(())
+}
+
+/// Maps a request target to a file below `root`: `/` is the collection page and folders
+/// serve their `index.html`. Anything escaping `root` is refused.
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let decoded = percent_decode(path)?;
+
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if resolved == root {
+        resolved.push("collection.html");
+    } else if resolved.is_dir() {
+        resolved.push("index.html");
+    }
+    resolved.is_file().then_some(resolved)
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// `bytes=START-[END]`, the only range form browsers send for media.
+fn parse_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+    Some((start.trim().parse().ok()?, end))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve_request_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("2019/July")).unwrap();
+        fs::write(root.join("collection.html"), "").unwrap();
+        fs::write(root.join("2019/July/index.html"), "").unwrap();
+        fs::write(root.join("2019/July/my photo.jpg"), "").unwrap();
+
+        assert_eq!(resolve(root, "/"), Some(root.join("collection.html")));
+        assert_eq!(
+            resolve(root, "/2019/July/"),
+            Some(root.join("2019/July/index.html"))
+        );
+        assert_eq!(
+            resolve(root, "/2019/July/my%20photo.jpg?v=1"),
+            Some(root.join("2019/July/my photo.jpg"))
+        );
+        assert_eq!(resolve(root, "/../etc/passwd"), None);
+        assert_eq!(resolve(root, "/2019/%2e%2e/%2e%2e/x"), None);
+        assert_eq!(resolve(root, "/missing.jpg"), None);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-"), Some((0, None)));
+        assert_eq!(parse_range("bytes=100-199"), Some((100, Some(199))));
+        assert_eq!(parse_range("items=0-1"), None);
+    }
+}
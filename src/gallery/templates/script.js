@@ -2,24 +2,48 @@ var currentContext = [];
         var currentIndex = 0;
         var slideshowInterval = null;
 
+        var currentType = 'all';
+
         function filterGallery(type, btn) {
             // Update active state of buttons
             document.querySelectorAll('.filter-btn').forEach(function(b) {
                 b.classList.remove('active');
             });
             btn.classList.add('active');
+            currentType = type;
+            applyFilters();
+        }
+
+        // Combines the media type buttons with the account drop-down, when present
+        function applyFilters() {
+            var ownerSelect = document.getElementById('owner-filter');
+            var owner = ownerSelect ? ownerSelect.value : 'all';
 
             // Filter photos in both galleries
             document.querySelectorAll('.photo').forEach(function(p) {
                 var pType = p.querySelector('a').getAttribute('data-type');
-                if (type === 'all' || pType === type) {
-                    p.style.display = 'block';
-                } else {
-                    p.style.display = 'none';
-                }
+                var pOwners = (p.getAttribute('data-owners') || '').split(' ');
+                var typeMatch = currentType === 'all' || pType === currentType;
+                var ownerMatch = owner === 'all' || pOwners.indexOf(owner) !== -1;
+                p.style.display = typeMatch && ownerMatch ? 'block' : 'none';
             });
         }
 
+        // Swaps a card between the edited copy and its original
+        function flipVersion(event, btn) {
+            event.stopPropagation();
+            var card = btn.closest('.photo');
+            var link = card.querySelector('a');
+            var img = card.querySelector('img');
+            var altSrc = btn.getAttribute('data-alt-src');
+            var altDisplay = btn.getAttribute('data-alt-display');
+            btn.setAttribute('data-alt-src', link.getAttribute('href'));
+            btn.setAttribute('data-alt-display', img.getAttribute('src'));
+            link.setAttribute('href', altSrc);
+            img.setAttribute('src', altDisplay);
+            btn.innerText = btn.innerText === 'Edited' ? 'Original' : 'Edited';
+        }
+
         function toggleView() {
             var flat = document.getElementById('flattened-gallery');
             var dirs = document.getElementById('directory-view');
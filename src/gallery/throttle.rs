@@ -8,11 +8,38 @@ pub struct ThrottleState {
     pub last_increase: Instant,
 }
 
+/// When parallel transcodes may be added or must be given up.
+#[derive(Debug, Clone)]
+pub struct ThrottleSettings {
+    /// Free memory (percent) needed to allow one more parallel job
+    pub scale_up_free_percent: u64,
+    /// Free memory (percent) below which one job slot is given up
+    pub scale_down_free_percent: u64,
+    /// Time to wait between two increases of the limit
+    pub ramp_interval: Duration,
+    /// Most jobs at once, 0 to let free memory alone decide
+    pub max_jobs: usize,
+}
+
+impl Default for ThrottleSettings {
+    fn default() -> Self {
+        Self {
+            scale_up_free_percent: 30,
+            scale_down_free_percent: 20,
+            ramp_interval: Duration::from_secs(3),
+            max_jobs: 0,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Throttle {
     state: Arc<Mutex<ThrottleState>>,
     increase_interval: Duration,
     check_interval: Duration,
+    scale_up_free_percent: u64,
+    scale_down_free_percent: u64,
+    max_jobs: usize,
 }
 
 impl Throttle {
@@ -29,6 +56,19 @@ impl Throttle {
             })),
             increase_interval,
             check_interval,
+            scale_up_free_percent: ThrottleSettings::default().scale_up_free_percent,
+            scale_down_free_percent: ThrottleSettings::default().scale_down_free_percent,
+            max_jobs: 0,
+        }
+    }
+
+    /// A throttle following `settings`, checking memory every `check_interval` while waiting.
+    pub fn with_settings(settings: &ThrottleSettings, check_interval: Duration) -> Self {
+        Self {
+            scale_up_free_percent: settings.scale_up_free_percent,
+            scale_down_free_percent: settings.scale_down_free_percent,
+            max_jobs: settings.max_jobs,
+            ..Self::new(settings.ramp_interval, check_interval)
         }
     }
 
@@ -48,14 +88,15 @@ impl Throttle {
             if let Ok(mut state) = self.state.lock() {
                 current_active = state.active;
 
-                // Gradual up-scaling: If free memory is >= 30% and we've waited enough time
+                // Gradual up-scaling: If enough memory is free and we've waited enough time
                 // since the last increase, bump the concurrency limit up by 1.
-                if percent_available >= 30 {
-                    if state.last_increase.elapsed() >= self.increase_interval {
+                if percent_available >= self.scale_up_free_percent {
+                    let below_max = self.max_jobs == 0 || state.limit < self.max_jobs;
+                    if below_max && state.last_increase.elapsed() >= self.increase_interval {
                         state.limit += 1;
                         state.last_increase = Instant::now();
                     }
-                } else if percent_available < 20 && state.limit > 1 {
+                } else if percent_available < self.scale_down_free_percent && state.limit > 1 {
                     // Downward pressure if memory is running short
                     state.limit -= 1;
                 }
@@ -101,8 +142,7 @@ impl Throttle {
         (active_now, current_limit)
     }
 
-    /// Read-only snapshot of limit and active.
-    #[allow(dead_code)]
+    /// Read-only snapshot of active and limit.
     pub fn get_stats(&self) -> (usize, usize) {
         if let Ok(state) = self.state.lock() {
             (state.active, state.limit)
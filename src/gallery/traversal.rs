@@ -1,16 +1,49 @@
 use super::builder;
-use super::utils::{is_image, is_video};
+use super::library::Library;
+use super::utils::{is_year_dir, month_number};
 use anyhow::{Context, Result};
 use indicatif::ProgressBar;
 use std::fs;
 use std::path::Path;
 
 /// Recursively visits directories to generate gallery HTML files.
-pub fn visit_dir(dir: &Path, root_path: &Path, pb: &ProgressBar) -> Result<()> {
+pub fn visit_dir(dir: &Path, root_path: &Path, library: &Library, pb: &ProgressBar) -> Result<()> {
     if !dir.is_dir() {
         return Ok(());
     }
 
+    // Recurse first
+    for subdir in &list_subdirs(dir)? {
+        visit_dir(subdir, root_path, library, pb)?;
+    }
+    write_dir_page(dir, root_path, library, pb)
+}
+
+fn list_subdirs(dir: &Path) -> Result<Vec<std::path::PathBuf>> {
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // Ignore hidden directories like .thumbnails
+        if path.is_dir()
+            && !path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .starts_with('.')
+        {
+            subdirs.push(path);
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Writes the gallery page of `dir` alone, leaving its subfolders' pages as they are.
+pub fn write_dir_page(
+    dir: &Path,
+    root_path: &Path,
+    library: &Library,
+    pb: &ProgressBar,
+) -> Result<()> {
     let entries = fs::read_dir(dir)?;
     let mut subdirs = Vec::new();
     let mut media_files = Vec::new();
@@ -28,45 +61,38 @@ pub fn visit_dir(dir: &Path, root_path: &Path, pb: &ProgressBar) -> Result<()> {
             {
                 subdirs.push(path);
             }
-        } else if is_image(&path) || is_video(&path) {
-            media_files.push(path);
+        } else if let Some(kind) = library.media_kind(&path) {
+            // Linked edited copies appear on their original's card
+            if !library.edits.is_linked_edit(&path) {
+                media_files.push((path, kind));
+            }
             pb.inc(1);
         }
     }
 
     subdirs.sort();
-    media_files.sort();
+    media_files.sort_by(|a, b| a.0.cmp(&b.0));
 
     // Custom sort for months if we are at the year level
     let relative_path = dir.strip_prefix(root_path).unwrap_or(Path::new(""));
-    let depth = relative_path.components().count();
+    let mut depth = relative_path.components().count();
+    // Libraries organized per owner have one more level above the years
+    let mut components = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy());
+    if let (Some(first), Some(second)) = (components.next(), components.next())
+        && !is_year_dir(&first)
+        && is_year_dir(&second)
+    {
+        depth -= 1;
+    }
 
     if depth == 1 {
         subdirs.sort_by_key(|p| {
-            let month_name = p.file_name().unwrap_or_default().to_string_lossy();
-            match month_name.as_ref() {
-                "January" => 1,
-                "February" => 2,
-                "March" => 3,
-                "April" => 4,
-                "May" => 5,
-                "June" => 6,
-                "July" => 7,
-                "August" => 8,
-                "September" => 9,
-                "October" => 10,
-                "November" => 11,
-                "December" => 12,
-                _ => 13,
-            }
+            month_number(&p.file_name().unwrap_or_default().to_string_lossy()).unwrap_or(13)
         });
     }
 
-    // Recurse first
-    for subdir in &subdirs {
-        visit_dir(subdir, root_path, pb)?;
-    }
-
     // Check if we should generate a flattened view (Depth 2 = Month level)
     let mut flattened_media = Vec::new();
 
@@ -78,14 +104,17 @@ pub fn visit_dir(dir: &Path, root_path: &Path, pb: &ProgressBar) -> Result<()> {
                 let mut dir_media = Vec::new();
                 for entry in entries.flatten() {
                     let path = entry.path();
-                    if !path.is_dir() && (is_image(&path) || is_video(&path)) {
-                        // Get path relative to current 'dir' (Month dir)
-                        if let Ok(rel) = path.strip_prefix(dir) {
-                            dir_media.push(rel.to_path_buf());
-                        }
+                    if path.is_dir() || library.edits.is_linked_edit(&path) {
+                        continue;
+                    }
+                    // Get path relative to current 'dir' (Month dir)
+                    if let Some(kind) = library.media_kind(&path)
+                        && let Ok(rel) = path.strip_prefix(dir)
+                    {
+                        dir_media.push((rel.to_path_buf(), kind));
                     }
                 }
-                dir_media.sort();
+                dir_media.sort_by(|a, b| a.0.cmp(&b.0));
                 flattened_media.extend(dir_media);
             }
         }
@@ -94,8 +123,14 @@ pub fn visit_dir(dir: &Path, root_path: &Path, pb: &ProgressBar) -> Result<()> {
     // Generate HTML for current dir
     // Only generate if there are contents, or it's the root
     if !subdirs.is_empty() || !media_files.is_empty() || dir == root_path {
-        let content =
-            builder::build_html(dir, root_path, &subdirs, &media_files, &flattened_media)?;
+        let content = builder::build_html(
+            dir,
+            root_path,
+            &subdirs,
+            &media_files,
+            &flattened_media,
+            library,
+        )?;
 
         let filename = if dir == root_path {
             "collection.html"
@@ -123,7 +158,7 @@ mod tests {
         // Crear una imagen para que se genere el HTML
         fs::write(root.path().join("image.jpg"), "").unwrap();
 
-        visit_dir(root.path(), root.path(), &pb).unwrap();
+        visit_dir(root.path(), root.path(), &Library::default(), &pb).unwrap();
 
         assert!(root.path().join("collection.html").exists());
     }
@@ -143,7 +178,7 @@ mod tests {
         fs::write(root.path().join("img.jpg"), "").unwrap();
 
         let pb = ProgressBar::hidden();
-        visit_dir(root.path(), root.path(), &pb).unwrap();
+        visit_dir(root.path(), root.path(), &Library::default(), &pb).unwrap();
 
         // Verificamos que se generó el index del año
         let year_html = fs::read_to_string(year_dir.join("index.html")).unwrap();
@@ -163,10 +198,33 @@ mod tests {
         fs::write(day_dir.join("photo.jpg"), "").unwrap();
 
         let pb = ProgressBar::hidden();
-        visit_dir(root.path(), root.path(), &pb).unwrap();
+        visit_dir(root.path(), root.path(), &Library::default(), &pb).unwrap();
 
         // El index del mes (January) debería contener la foto del día (01)
         let month_html = fs::read_to_string(root.path().join("2023/January/index.html")).unwrap();
         assert!(month_html.contains("01/photo.jpg"));
     }
+
+    #[test]
+    fn test_flattened_view_below_owner_folder() {
+        let root = tempdir().unwrap();
+        let day_dir = root.path().join("alice/2023/January/01");
+        fs::create_dir_all(&day_dir).unwrap();
+        fs::write(day_dir.join("photo.jpg"), "").unwrap();
+
+        let pb = ProgressBar::hidden();
+        visit_dir(root.path(), root.path(), &Library::default(), &pb).unwrap();
+
+        let month_html =
+            fs::read_to_string(root.path().join("alice/2023/January/index.html")).unwrap();
+        assert!(month_html.contains("01/photo.jpg"));
+
+        // The unknown folder is not an owner, so its subfolders keep their depth
+        let unknown_dir = root.path().join("unknown/Trip/scans");
+        fs::create_dir_all(&unknown_dir).unwrap();
+        fs::write(unknown_dir.join("scan.jpg"), "").unwrap();
+        visit_dir(root.path(), root.path(), &Library::default(), &pb).unwrap();
+        let trip_html = fs::read_to_string(root.path().join("unknown/Trip/index.html")).unwrap();
+        assert!(trip_html.contains("scans/scan.jpg"));
+    }
 }
@@ -1,61 +1,93 @@
+use crate::organizer::sniff::{self, ContentType};
 use std::path::Path;
 
-pub fn is_image(path: &Path) -> bool {
-    let ext = path
-        .extension()
-        .and_then(|s| s.to_str())
-        .map(|s| s.to_lowercase())
-        .unwrap_or_default();
+const IMAGE_EXTENSIONS: [&str; 9] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "webp", "heic", "heif", "tiff",
+];
+const VIDEO_EXTENSIONS: [&str; 6] = ["mp4", "mov", "avi", "mkv", "webm", "m4v"];
 
-    matches!(
-        ext.as_str(),
-        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "heic" | "heif" | "tiff"
-    )
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
 }
 
-pub fn is_video(path: &Path) -> bool {
+/// Files with a gallery extension are confirmed by their content, so a PNG named `.mp4` or
+/// an HTML page named `.jpg` is classified by what it really is. `None` for anything else.
+pub fn media_kind(path: &Path) -> Option<MediaKind> {
     let ext = path
         .extension()
         .and_then(|s| s.to_str())
         .map(|s| s.to_lowercase())
         .unwrap_or_default();
 
-    matches!(ext.as_str(), "mp4" | "mov" | "avi" | "mkv" | "webm" | "m4v")
+    let by_extension = if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+        MediaKind::Image
+    } else if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        MediaKind::Video
+    } else {
+        return None;
+    };
+
+    match sniff::sniff(path) {
+        Some(ContentType::Image(_)) => Some(MediaKind::Image),
+        Some(ContentType::Video(_)) => Some(MediaKind::Video),
+        Some(ContentType::NonMedia(_)) => None,
+        None => Some(by_extension),
+    }
+}
+
+/// Number of an English month folder name, as written by the organizer.
+pub fn month_number(name: &str) -> Option<u32> {
+    let number = match name {
+        "January" => 1,
+        "February" => 2,
+        "March" => 3,
+        "April" => 4,
+        "May" => 5,
+        "June" => 6,
+        "July" => 7,
+        "August" => 8,
+        "September" => 9,
+        "October" => 10,
+        "November" => 11,
+        "December" => 12,
+        _ => return None,
+    };
+    Some(number)
+}
+
+/// Escapes text, such as an owner name taken from a folder, for HTML content and attributes.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub fn is_year_dir(name: &str) -> bool {
+    name.len() == 4 && name.chars().all(|c| c.is_ascii_digit())
 }
 
 pub fn get_date_from_path(image_path: &Path, root_path: &Path) -> Option<String> {
     let relative = image_path.strip_prefix(root_path).ok()?;
-    let components: Vec<_> = relative.components().collect();
-
-    // Expect structure: Year/MonthName/Day/Image.jpg
-    if components.len() >= 4 {
-        let year_str = components[0].as_os_str().to_string_lossy();
-        let month_name = components[1].as_os_str().to_string_lossy();
-        let day_str = components[2].as_os_str().to_string_lossy();
-
-        let month_num = match month_name.as_ref() {
-            "January" => "01",
-            "February" => "02",
-            "March" => "03",
-            "April" => "04",
-            "May" => "05",
-            "June" => "06",
-            "July" => "07",
-            "August" => "08",
-            "September" => "09",
-            "October" => "10",
-            "November" => "11",
-            "December" => "12",
-            _ => return None,
-        };
-
-        // Simple validation: check if year and day look like numbers
-        if year_str.chars().all(char::is_numeric) && day_str.chars().all(char::is_numeric) {
+    let components: Vec<_> = relative
+        .parent()?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+
+    // Expect structure: [Owner/]Year/MonthName/Day/Image.jpg
+    components.windows(3).find_map(|window| {
+        let (year_str, month_name, day_str) = (&window[0], &window[1], &window[2]);
+        let month_num = month_number(month_name)?;
+        if is_year_dir(year_str) && !day_str.is_empty() && day_str.chars().all(char::is_numeric) {
             let day_val = day_str.parse::<u32>().unwrap_or(0);
-            return Some(format!("{}-{}-{:02}", year_str, month_num, day_val));
+            return Some(format!("{}-{:02}-{:02}", year_str, month_num, day_val));
         }
-    }
-    None
+        None
+    })
 }
 
 #[cfg(test)]
@@ -63,15 +95,29 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_is_image() {
-        assert!(is_image(Path::new("photo.jpg")));
-        assert!(is_image(Path::new("photo.JPG")));
-        assert!(is_image(Path::new("image.png")));
-        assert!(is_image(Path::new("image.heic")));
-
-        assert!(!is_image(Path::new("video.mp4")));
-        assert!(!is_image(Path::new("text.txt")));
-        assert!(!is_image(Path::new("no_ext")));
+    fn test_media_kind() {
+        let image = Some(MediaKind::Image);
+        assert_eq!(media_kind(Path::new("photo.jpg")), image);
+        assert_eq!(media_kind(Path::new("photo.JPG")), image);
+        assert_eq!(media_kind(Path::new("image.png")), image);
+        assert_eq!(media_kind(Path::new("image.heic")), image);
+
+        assert_eq!(media_kind(Path::new("video.mp4")), Some(MediaKind::Video));
+        assert_eq!(media_kind(Path::new("text.txt")), None);
+        assert_eq!(media_kind(Path::new("no_ext")), None);
+    }
+
+    #[test]
+    fn test_media_kind_follows_content() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let png_as_mp4 = dir.path().join("clip.mp4");
+        std::fs::write(&png_as_mp4, b"\x89PNG\r\n\x1a\n\x00\x00").unwrap();
+        assert_eq!(media_kind(&png_as_mp4), Some(MediaKind::Image));
+
+        let html_as_jpg = dir.path().join("page.jpg");
+        std::fs::write(&html_as_jpg, "<!DOCTYPE html>").unwrap();
+        assert_eq!(media_kind(&html_as_jpg), None);
     }
 
     #[test]
@@ -92,6 +138,13 @@ mod tests {
             Some("2023-01-01".to_string())
         );
 
+        // Below an owner folder
+        let path = root.join("alice/2023/May/20/img.jpg");
+        assert_eq!(
+            get_date_from_path(&path, root),
+            Some("2023-05-20".to_string())
+        );
+
         // Too short
         let path = root.join("2023/May/img.jpg");
         assert_eq!(get_date_from_path(&path, root), None);
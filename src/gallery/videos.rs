@@ -1,20 +1,19 @@
 use super::images;
 use super::media;
-use super::utils::{is_image, is_video};
+use super::utils::MediaKind;
 use anyhow::Result;
 use log::debug;
 use std::fs;
 use std::path::Path;
 
 /// Dispatcher to process media fast (thumbnails and transcode check).
-pub fn ensure_thumbnail_fast(media_path: &Path, has_ffmpeg: bool) -> Result<bool> {
-    if is_image(media_path) {
-        images::ensure_thumbnail(media_path)?;
-        Ok(false)
-    } else if is_video(media_path) {
-        ensure_thumbnail_and_check_transcode(media_path, has_ffmpeg)
-    } else {
-        Ok(false)
+pub fn ensure_thumbnail_fast(media_path: &Path, kind: MediaKind, has_ffmpeg: bool) -> Result<bool> {
+    match kind {
+        MediaKind::Image => {
+            images::ensure_thumbnail(media_path)?;
+            Ok(false)
+        }
+        MediaKind::Video => ensure_thumbnail_and_check_transcode(media_path, has_ffmpeg),
     }
 }
 
@@ -78,11 +77,11 @@ mod tests {
         let vid_path = dir.path().join("test.mp4");
 
         // Images should try to thumbnail (and fail here because file is missing)
-        let result_img = ensure_thumbnail_fast(&img_path, false);
+        let result_img = ensure_thumbnail_fast(&img_path, MediaKind::Image, false);
         assert!(result_img.is_err());
 
         // Videos with has_ffmpeg=false should return false immediately after dir creation
-        let result_vid = ensure_thumbnail_fast(&vid_path, false);
+        let result_vid = ensure_thumbnail_fast(&vid_path, MediaKind::Video, false);
         assert!(result_vid.is_ok());
         assert!(!result_vid.unwrap());
     }
@@ -1,26 +1,305 @@
+mod config;
 mod gallery;
 mod organizer;
 
-use anyhow::Result;
-use clap::Parser;
-use log::{info, warn};
-use std::path::Path;
+use anyhow::{Result, bail};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::Config;
+use gallery::ThrottleSettings;
+use log::{error, info, warn};
+use organizer::cancel;
+use organizer::clock_skew::ClockSkewRules;
+use organizer::edits::EditPolicy;
+use organizer::export::ExportFormat;
+use organizer::filters::{FileFilter, MediaFilter, parse_filter_date};
+use organizer::metadata::DateFallback;
+use organizer::name_template::NameTemplate;
+use organizer::overrides::Overrides;
+use organizer::owners;
+use organizer::progress::{Event, Phase, ProgressMode};
+use organizer::target_fs::TargetFs;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Path to the source directory or archive files (Google Takeout). Multiple inputs allowed.
-    #[arg(short, long, num_args = 1..)]
-    input: Vec<String>,
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    /// Path to the destination directory (Required)
+/// The command line: without a subcommand, the options of `organize` are accepted directly.
+fn cli_command() -> clap::Command {
+    // Set after flattening, which replaces the crate description with the struct's doc comment
+    <LegacyArgs as clap::Args>::augment_args(Cli::command()).about(env!("CARGO_PKG_DESCRIPTION"))
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Organize Takeout archives or folders into a library (the default)
+    Organize(Box<OrganizeArgs>),
+    /// Watch inbox folders and organize every archive or folder dropped into them (runs until stopped)
+    Watch(Box<WatchArgs>),
+    /// Move the files of a library to the current layout and naming settings
+    Reorganize(Box<ReorganizeArgs>),
+    /// Interactively date undated or conflicting files of a library, recording answers in --date-overrides
+    Triage(OverridesArgs),
+    /// Move already-organized files whose override date changed
+    ApplyOverrides(OverridesArgs),
+    /// Generate the thumbnails and HTML gallery of a library
+    Gallery(GalleryArgs),
+    /// Verify a library against its stored checksums
+    Verify(LibraryArgs),
+    /// Show counts per year, date source and owner of a library
+    Stats(LibraryArgs),
+    /// Find files with identical content in a library, and optionally remove the extra copies
+    Dedupe(DedupeArgs),
+    /// Serve the gallery of a library over HTTP
+    Serve(ServeArgs),
+    /// Export the catalog of a library as CSV or JSON
+    Export(ExportArgs),
+    /// Print the settings a command would run with, and where each comes from
+    PrintConfig(PrintConfigArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct LibraryArgs {
+    /// Path to the organized library
     #[arg(short, long)]
     output: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct GalleryArgs {
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Transcode HEVC videos to H.264 for better web compatibility
+    #[arg(short, long, default_value_t = false)]
+    transcode_videos: bool,
+
+    /// Number of parallel thumbnail generation tasks (Default: Total cores - 1)
+    #[arg(short = 'j', long, default_value_t = default_threads())]
+    threads: usize,
+
+    #[command(flatten)]
+    transcode: TranscodeArgs,
+
+    #[command(flatten)]
+    progress: ProgressArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct DedupeArgs {
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Delete every copy but the best-dated one (and its thumbnails) instead of only listing them
+    #[arg(long, default_value_t = false)]
+    remove: bool,
+}
 
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8000")]
+    listen: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Output format: csv or json
+    #[arg(long, default_value = "csv")]
+    format: ExportFormat,
+
+    /// File to write (Default: standard output)
+    #[arg(long)]
+    to: Option<String>,
+}
+
+/// Memory-based throttling of parallel video transcodes.
+#[derive(clap::Args, Debug)]
+struct TranscodeArgs {
+    /// Free memory (%) needed before another video is transcoded in parallel
+    #[arg(long, default_value_t = 30)]
+    transcode_free_memory: u64,
+
+    /// Free memory (%) below which one parallel transcode slot is given up
+    #[arg(long, default_value_t = 20)]
+    transcode_min_free_memory: u64,
+
+    /// Seconds between two increases of the number of parallel transcodes
+    #[arg(long, default_value_t = 3)]
+    transcode_ramp_seconds: u64,
+
+    /// Most videos transcoded at once (0: limited by free memory only)
+    #[arg(long, default_value_t = 0)]
+    max_transcodes: usize,
+}
+
+impl TranscodeArgs {
+    fn settings(&self) -> ThrottleSettings {
+        ThrottleSettings {
+            scale_up_free_percent: self.transcode_free_memory,
+            scale_down_free_percent: self.transcode_min_free_memory,
+            ramp_interval: std::time::Duration::from_secs(self.transcode_ramp_seconds),
+            max_jobs: self.max_transcodes,
+        }
+    }
+}
+
+/// How progress is reported.
+#[derive(clap::Args, Debug)]
+struct ProgressArgs {
+    /// Progress display: `bar`, or `json` for one JSON event per line on standard output
+    #[arg(long, default_value = "bar")]
+    progress: ProgressMode,
+
+    /// Send JSON progress events to this TCP address (host:port) instead of standard output
+    #[arg(long)]
+    progress_socket: Option<String>,
+}
+
+impl ProgressArgs {
+    fn init(&self) -> Result<()> {
+        organizer::progress::init(self.progress, self.progress_socket.as_deref())
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct PrintConfigArgs {
+    /// Command whose settings to print
+    #[arg(default_value = "organize")]
+    command: String,
+}
+
+fn default_threads() -> usize {
+    (num_cpus::get() - 1).max(1)
+}
+
+/// Where and how files are organized, shared by `organize`, `watch` and `reorganize`.
+#[derive(clap::Args, Debug)]
+struct OrganizeSettings {
     /// Name of the folder for files with no date
     #[arg(short, long, default_value = "unknown")]
     unknown_dir: String,
 
+    /// Hash source and destination after every copy and record the checksum in the catalog
+    #[arg(long, default_value_t = false)]
+    verify_copies: bool,
+
+    /// Test every entry of the input archives (ZIP CRCs, gzip streams) before extracting
+    #[arg(long, default_value_t = false)]
+    check_archives: bool,
+
+    /// Refuse to organize when Takeout parts are missing or archives come from different exports
+    #[arg(long, default_value_t = false)]
+    require_complete_takeout: bool,
+
+    /// Folder to extract archives into (Default: system temp directory)
+    #[arg(long)]
+    staging_dir: Option<String>,
+
+    /// Skip the free disk space check before extraction
+    #[arg(long, default_value_t = false)]
+    skip_space_check: bool,
+
+    /// Rename files whose extension does not match their content (e.g. HEIC data named .jpg)
+    #[arg(long, default_value_t = false)]
+    fix_extensions: bool,
+
+    /// Copy non-media files (HTML, text, ...) into this output folder instead of only reporting them
+    #[arg(long)]
+    non_media_dir: Option<String>,
+
+    /// Only organize files whose Takeout-relative path matches one of these globs
+    #[arg(long, num_args = 1..)]
+    include: Vec<String>,
+
+    /// Skip files whose Takeout-relative path matches one of these globs (e.g. "*.gif", "**/Hangout:*/**")
+    #[arg(long, num_args = 1..)]
+    exclude: Vec<String>,
+
+    /// Only organize this kind of media: all, images or videos
+    #[arg(long, default_value = "all")]
+    media_type: MediaFilter,
+
+    /// Only organize files dated on or after this date (YYYY, YYYY-MM or YYYY-MM-DD)
+    #[arg(long)]
+    date_from: Option<String>,
+
+    /// Only organize files dated on or before this date (YYYY, YYYY-MM or YYYY-MM-DD)
+    #[arg(long)]
+    date_to: Option<String>,
+
+    /// Date sources to try, in order, for files without JSON, EXIF or filename dates:
+    /// folder (Takeout "Photos from YYYY"), archive-mtime, file-mtime, or none
+    #[arg(long, value_delimiter = ',', default_value = "folder")]
+    date_fallback: Vec<DateFallback>,
+
+    /// Keep the Takeout folder structure below the unknown folder for undated files
+    #[arg(long, default_value_t = false)]
+    preserve_unknown_paths: bool,
+
+    /// CSV or TOML file of hand-fixed dates (by hash, Takeout path or glob) that win over all other sources
+    #[arg(long)]
+    date_overrides: Option<String>,
+
+    /// TOML file of per-camera clock skew rules (EXIF model, optional date range, shift like "+3y 2m 4h")
+    #[arg(long)]
+    clock_skew_rules: Option<String>,
+
+    /// Also write clock skew corrections into the EXIF dates of the organized copies
+    #[arg(long, default_value_t = false, requires = "clock_skew_rules")]
+    write_corrected_exif: bool,
+
+    /// Date undated files of a numbered sequence (IMG_4512 between IMG_4511 and IMG_4513) from their neighbours
+    #[arg(long, default_value_t = false)]
+    infer_sequence_dates: bool,
+
+    /// Only infer a date when the two dated neighbours are at most this many hours apart
+    #[arg(long, default_value_t = 24)]
+    sequence_window_hours: u32,
+
+    /// Photos exported with an edited copy (IMG_1-edited.jpg): keep both (linked), only the edited copy, or only the original
+    #[arg(long, default_value = "both")]
+    edit_policy: EditPolicy,
+
+    /// Name output files after the original name in their JSON sidecar (title) instead of the truncated or numbered Takeout name
+    #[arg(long, default_value_t = false)]
+    restore_titles: bool,
+
+    /// Rename output files with a template, e.g. "{date:%Y%m%d_%H%M%S}_{camera}_{orig_stem}.{ext_lower}".
+    /// Placeholders: date[:format] (UTC), camera, make, model, title, orig_stem, ext, ext_lower, counter[:width]
+    #[arg(long)]
+    rename_template: Option<NameTemplate>,
+
+    /// Filesystem of the output, to adapt file names to: native, exfat (FAT32/exFAT), smb or hfs (macOS)
+    #[arg(long, default_value = "native")]
+    target_fs: TargetFs,
+
+    /// Organize labelled inputs below a folder per owner (<owner>/YYYY/Month/DD)
+    #[arg(long, default_value_t = false)]
+    owner_layout: bool,
+}
+
+/// Gallery refresh after a command changed the library.
+#[derive(clap::Args, Debug)]
+struct HtmlArgs {
     /// Generate an HTML gallery of the organized photos
     #[arg(short, long, default_value_t = true, action = clap::ArgAction::Set)]
     generate_html: bool,
@@ -30,54 +309,535 @@ struct Args {
     transcode_videos: bool,
 
     /// Number of parallel thumbnail generation tasks (Default: Total cores - 1)
-    #[arg(short = 'j', long, default_value_t = (num_cpus::get() - 1).max(1))]
+    #[arg(short = 'j', long, default_value_t = default_threads())]
     threads: usize,
+
+    #[command(flatten)]
+    transcode: TranscodeArgs,
+}
+
+impl HtmlArgs {
+    fn init_threads(&self) -> Result<()> {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build_global()?;
+        Ok(())
+    }
+
+    fn generate(&self, root: &Path) -> Result<()> {
+        gallery::generate_gallery(
+            root,
+            self.threads,
+            self.transcode_videos,
+            &self.transcode.settings(),
+        )
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct OrganizeArgs {
+    /// Path to the source directory or archive files (Google Takeout). Multiple inputs allowed.
+    /// Prefix with an account label (owner=path) to merge Takeouts of several accounts.
+    #[arg(short, long, num_args = 1.., required = true)]
+    input: Vec<String>,
+
+    /// Path to the destination directory (Required)
+    #[arg(short, long)]
+    output: String,
+
+    #[command(flatten)]
+    settings: OrganizeSettings,
+
+    #[command(flatten)]
+    html: HtmlArgs,
+
+    #[command(flatten)]
+    progress: ProgressArgs,
+
+    /// Write a JSON report of the run (counts, failures, date sources, durations) to this file
+    #[arg(long)]
+    report: Option<String>,
+}
+
+/// How inboxes are watched, shared by `watch` and the `--watch` flag.
+#[derive(clap::Args, Debug)]
+struct WatchSettings {
+    /// Seconds an inbox item must stop changing before it is taken to be completely written
+    #[arg(long, default_value_t = 10)]
+    settle_seconds: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Inbox folders to watch; every archive or folder dropped into them is organized, then moved to <inbox>/done
+    #[arg(long, num_args = 1.., required = true)]
+    inbox: Vec<String>,
+
+    /// Path to the organized library
+    #[arg(short, long)]
+    output: String,
+
+    #[command(flatten)]
+    watching: WatchSettings,
+
+    #[command(flatten)]
+    settings: OrganizeSettings,
+
+    #[command(flatten)]
+    html: HtmlArgs,
+
+    #[command(flatten)]
+    progress: ProgressArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReorganizeArgs {
+    /// Path to the organized library
+    #[arg(short, long)]
+    output: String,
+
+    #[command(flatten)]
+    settings: OrganizeSettings,
+
+    #[command(flatten)]
+    html: HtmlArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct OverridesArgs {
+    /// Path to the organized library
+    #[arg(short, long)]
+    output: String,
+
+    /// CSV or TOML file of hand-fixed dates
+    #[arg(long)]
+    date_overrides: String,
+
+    #[command(flatten)]
+    html: HtmlArgs,
+}
+
+/// The options accepted without a subcommand: those of `organize`, plus the flags that chose
+/// another command before there were subcommands.
+#[derive(clap::Args, Debug)]
+struct LegacyArgs {
+    /// Path to the source directory or archive files (Google Takeout). Multiple inputs allowed.
+    /// Prefix with an account label (owner=path) to merge Takeouts of several accounts.
+    #[arg(short, long, num_args = 1..)]
+    input: Vec<String>,
+
+    /// Path to the destination directory (Required)
+    #[arg(short, long)]
+    output: String,
+
+    #[command(flatten)]
+    settings: OrganizeSettings,
+
+    #[command(flatten)]
+    html: HtmlArgs,
+
+    #[command(flatten)]
+    progress: ProgressArgs,
+
+    /// Write a JSON report of the run (counts, failures, date sources, durations) to this file
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Same as the apply-overrides command
+    #[arg(long, default_value_t = false, requires = "date_overrides")]
+    apply_overrides: bool,
+
+    /// Same as the triage command
+    #[arg(long, default_value_t = false, requires = "date_overrides")]
+    triage: bool,
+
+    /// Same as the reorganize command
+    #[arg(long, default_value_t = false)]
+    reorganize: bool,
+
+    /// Same as the watch command with these inboxes
+    #[arg(long, num_args = 1..)]
+    watch: Vec<String>,
+
+    #[command(flatten)]
+    watching: WatchSettings,
+
+    /// Same as the verify command
+    #[arg(long, default_value_t = false)]
+    verify: bool,
+}
+
+impl LegacyArgs {
+    /// The command these flags stand for; `None` when there is nothing to do. Like before
+    /// there were subcommands, missing inputs and a missing library are only warned about.
+    fn into_command(self) -> Option<Command> {
+        let LegacyArgs {
+            input,
+            output,
+            settings,
+            html,
+            progress,
+            report,
+            apply_overrides,
+            triage,
+            reorganize,
+            watch,
+            watching,
+            verify,
+        } = self;
+
+        let command = if verify {
+            Command::Verify(LibraryArgs { output })
+        } else if triage || apply_overrides {
+            let args = OverridesArgs {
+                output,
+                date_overrides: settings.date_overrides.unwrap_or_default(),
+                html,
+            };
+            if triage {
+                Command::Triage(args)
+            } else {
+                Command::ApplyOverrides(args)
+            }
+        } else if reorganize {
+            Command::Reorganize(Box::new(ReorganizeArgs {
+                output,
+                settings,
+                html,
+            }))
+        } else if !watch.is_empty() {
+            Command::Watch(Box::new(WatchArgs {
+                inbox: watch,
+                output,
+                watching,
+                settings,
+                html,
+                progress,
+            }))
+        } else if input
+            .iter()
+            .any(|input| owners::parse_owned_input(input).is_ok_and(|(_, path)| path.exists()))
+        {
+            Command::Organize(Box::new(OrganizeArgs {
+                input,
+                output,
+                settings,
+                html,
+                progress,
+                report,
+            }))
+        } else if html.generate_html {
+            for input in &input {
+                warn!("Input path does not exist, skipping: {:?}", input);
+            }
+            if !Path::new(&output).exists() {
+                warn!(
+                    "Output directory {:?} does not exist. Cannot generate HTML.",
+                    output
+                );
+                return None;
+            }
+            Command::Gallery(GalleryArgs {
+                library: LibraryArgs { output },
+                transcode_videos: html.transcode_videos,
+                threads: html.threads,
+                transcode: html.transcode,
+                progress,
+            })
+        } else {
+            for input in &input {
+                warn!("Input path does not exist, skipping: {:?}", input);
+            }
+            if input.is_empty() {
+                info!("No input provided and HTML generation disabled. Nothing to do.");
+            }
+            return None;
+        };
+        Some(command)
+    }
 }
 
 // Main entry point for the organizer
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     organizer::ui::init_logger();
 
-    let args = Args::parse();
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let config = Config::load(
+        config::early_option(&argv, "config")
+            .as_deref()
+            .map(Path::new),
+        config::early_option(&argv, "profile").as_deref(),
+    )?;
+    let command = config.apply(cli_command())?;
+    let matches = command.clone().get_matches_from(argv);
+    let cli = Cli::from_arg_matches(&matches).map_err(|e| e.exit())?;
 
-    // Configure Rayon thread pool globally
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(args.threads)
-        .build_global()?;
+    let command_to_run = match cli.command {
+        Some(command) => command,
+        None => {
+            let args = LegacyArgs::from_arg_matches(&matches).map_err(|e| e.exit())?;
+            match args.into_command() {
+                Some(command) => command,
+                None => return Ok(ExitCode::SUCCESS),
+            }
+        }
+    };
 
-    let output_path = Path::new(&args.output);
+    run_command(command_to_run, &config, &command)
+}
 
-    if !args.input.is_empty() {
-        let mut input_paths = Vec::new();
-        for input_str in &args.input {
-            let p = Path::new(input_str);
-            if !p.exists() {
-                warn!("Input path does not exist, skipping: {:?}", p);
-                continue;
+/// Runs a command; only organizing reports an outcome of its own.
+fn run_command(command: Command, config: &Config, cli: &clap::Command) -> Result<ExitCode> {
+    match command {
+        Command::Organize(args) => return organize(*args),
+        Command::Gallery(args) => {
+            args.progress.init()?;
+            cancel::install_handler()?;
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(args.threads)
+                .build_global()?;
+            let root = Path::new(&args.library.output);
+            if !root.exists() {
+                bail!("Library {:?} does not exist", root);
+            }
+            gallery::generate_gallery(
+                root,
+                args.threads,
+                args.transcode_videos,
+                &args.transcode.settings(),
+            )
+        }
+        Command::Watch(args) => {
+            args.progress.init()?;
+            cancel::install_handler()?;
+            args.html.init_threads()?;
+            let output_path = Path::new(&args.output);
+            let inboxes: Vec<PathBuf> = args.inbox.iter().map(PathBuf::from).collect();
+            let options = organize_options(&args.settings, BTreeMap::new())?;
+            organizer::watch::watch(
+                &inboxes,
+                output_path,
+                &options,
+                std::time::Duration::from_secs(args.watching.settle_seconds),
+                |added| {
+                    if args.html.generate_html {
+                        gallery::update_gallery(
+                            output_path,
+                            added,
+                            args.html.transcode_videos,
+                            &args.html.transcode.settings(),
+                        )?;
+                    }
+                    Ok(())
+                },
+            )
+        }
+        Command::Reorganize(args) => {
+            cancel::install_handler()?;
+            args.html.init_threads()?;
+            let output_path = Path::new(&args.output);
+            let options = organize_options(&args.settings, BTreeMap::new())?;
+            let report = organizer::reorganize::reorganize(output_path, &options)?;
+            info!(
+                "Reorganized: {} moved, {} already in place, {} errors",
+                report.moved, report.unchanged, report.errors
+            );
+            if args.html.generate_html && report.moved > 0 {
+                gallery::regenerate_pages(output_path, &report.affected_dirs)?;
+            }
+            if report.errors > 0 {
+                bail!("Some files could not be moved");
+            }
+            Ok(())
+        }
+        Command::Triage(args) => {
+            cancel::install_handler()?;
+            args.html.init_threads()?;
+            let output_path = Path::new(&args.output);
+            let report = organizer::triage::run_triage(
+                output_path,
+                Path::new(&args.date_overrides),
+                &mut std::io::stdin().lock(),
+                &mut std::io::stdout(),
+            )?;
+            info!(
+                "Triage finished: {} dated, {} skipped",
+                report.resolved, report.skipped
+            );
+            if args.html.generate_html && report.resolved > 0 {
+                args.html.generate(output_path)?;
+            }
+            Ok(())
+        }
+        Command::ApplyOverrides(args) => {
+            cancel::install_handler()?;
+            args.html.init_threads()?;
+            let output_path = Path::new(&args.output);
+            let overrides = Overrides::load(Path::new(&args.date_overrides))?;
+            let report = organizer::overrides::apply_overrides(output_path, &overrides)?;
+            info!(
+                "Overrides applied: {} moved, {} re-dated in place, {} errors",
+                report.moved, report.retagged, report.errors
+            );
+            if report.errors > 0 {
+                bail!("Some files could not be moved");
+            }
+            if args.html.generate_html && report.moved > 0 {
+                args.html.generate(output_path)?;
             }
-            input_paths.push(p);
+            Ok(())
         }
+        Command::Verify(args) => verify(Path::new(&args.output)),
+        Command::Stats(args) => {
+            let stats = organizer::stats::library_stats(Path::new(&args.output))?;
+            organizer::stats::log_stats(&stats);
+            Ok(())
+        }
+        Command::Dedupe(args) => {
+            let root = Path::new(&args.library.output);
+            let report = organizer::dedupe::dedupe(root, args.remove)?;
+            organizer::dedupe::log_report(&report);
+            // Only refresh a gallery that was generated before
+            if report.removed > 0 && root.join("collection.html").exists() {
+                gallery::regenerate_pages(root, &report.affected_dirs)?;
+            }
+            Ok(())
+        }
+        Command::Serve(args) => {
+            gallery::serve::serve(Path::new(&args.library.output), &args.listen)
+        }
+        Command::Export(args) => {
+            let root = Path::new(&args.library.output);
+            let count = match &args.to {
+                Some(file) => {
+                    let mut out = std::io::BufWriter::new(std::fs::File::create(file)?);
+                    organizer::export::export_catalog(root, args.format, &mut out)?
+                }
+                None => organizer::export::export_catalog(
+                    root,
+                    args.format,
+                    &mut std::io::stdout().lock(),
+                )?,
+            };
+            info!("Exported {} files", count);
+            Ok(())
+        }
+        Command::PrintConfig(args) => config.print(cli, &args.command),
+    }?;
+    if cancel::is_cancelled() {
+        return Ok(ExitCode::from(cancel::CANCELLED_EXIT_CODE));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(root: &Path) -> Result<()> {
+    let report = organizer::verify::verify_library(root)?;
+    organizer::verify::log_report(&report);
+    if !report.is_clean() {
+        bail!("Library verification failed");
+    }
+    Ok(())
+}
 
-        if !input_paths.is_empty() {
-            organizer::organize_files(&input_paths, output_path, &args.unknown_dir)?;
+fn organize(args: OrganizeArgs) -> Result<ExitCode> {
+    args.progress.init()?;
+    cancel::install_handler()?;
+    args.html.init_threads()?;
+
+    let output_path = Path::new(&args.output);
+
+    let mut input_paths = Vec::new();
+    let mut owners = BTreeMap::new();
+    for input_str in &args.input {
+        let (owner, p) = owners::parse_owned_input(input_str)?;
+        if !p.exists() {
+            warn!("Input path does not exist, skipping: {:?}", p);
+            continue;
         }
-    } else {
-        info!("No input provided. Skipping organization.");
+        if let Some(owner) = owner {
+            owners.insert(p.clone(), owner);
+        }
+        input_paths.push(p);
+    }
+    if input_paths.is_empty() {
+        bail!("None of the input paths exist");
     }
+    let input_paths: Vec<&Path> = input_paths.iter().map(PathBuf::as_path).collect();
 
-    if args.generate_html {
-        if output_path.exists() {
-            gallery::generate_gallery(output_path, args.threads, args.transcode_videos)?;
-        } else if args.input.is_empty() {
-            warn!(
-                "Output directory {:?} does not exist. Cannot generate HTML.",
-                output_path
-            );
+    let options = organize_options(&args.settings, owners)?;
+    let mut report = organizer::organize_files(&input_paths, output_path, &options)?;
+
+    if args.html.generate_html && !cancel::is_cancelled() {
+        let phase = Phase::start("gallery", None);
+        // The files are organized either way, so a failed gallery only makes the run partial
+        if let Err(e) = args.html.generate(output_path) {
+            error!("Failed to generate the gallery: {:#}", e);
+            report.gallery_error = Some(format!("{:#}", e));
         }
-    } else if args.input.is_empty() {
-        info!("No input provided and HTML generation disabled. Nothing to do.");
+        report.add_phase("gallery", phase.finish());
     }
+    // Ctrl-C during the gallery leaves thumbnails and transcodes to the next run
+    report.cancelled |= cancel::is_cancelled();
 
-    Ok(())
+    if let Some(path) = &args.report {
+        report.write(Path::new(path))?;
+        info!("Run report written to {:?}", path);
+    }
+    organizer::report::log_summary(&report);
+    organizer::progress::emit(&Event::Totals {
+        outcome: report.outcome(),
+        counts: &report.counts,
+    });
+    Ok(ExitCode::from(report.outcome().exit_code()))
+}
+
+/// Organizing options shared by `organize`, `watch` and `reorganize`.
+fn organize_options(
+    args: &OrganizeSettings,
+    owners: BTreeMap<PathBuf, String>,
+) -> Result<organizer::OrganizeOptions> {
+    let overrides = match &args.date_overrides {
+        Some(path) => Overrides::load(Path::new(path))?,
+        None => Overrides::default(),
+    };
+    Ok(organizer::OrganizeOptions {
+        unknown_dir: args.unknown_dir.clone(),
+        verify_copies: args.verify_copies,
+        check_archives: args.check_archives,
+        require_complete_takeout: args.require_complete_takeout,
+        staging_dir: args.staging_dir.as_ref().map(PathBuf::from),
+        check_disk_space: !args.skip_space_check,
+        fix_extensions: args.fix_extensions,
+        non_media_dir: args.non_media_dir.clone(),
+        filter: FileFilter::new(
+            &args.include,
+            &args.exclude,
+            args.media_type,
+            args.date_from
+                .as_deref()
+                .map(|d| parse_filter_date(d, false))
+                .transpose()?,
+            args.date_to
+                .as_deref()
+                .map(|d| parse_filter_date(d, true))
+                .transpose()?,
+        )?,
+        date_fallbacks: args.date_fallback.clone(),
+        preserve_unknown_paths: args.preserve_unknown_paths,
+        overrides,
+        clock_skew: match &args.clock_skew_rules {
+            Some(path) => ClockSkewRules::load(Path::new(path))?,
+            None => ClockSkewRules::default(),
+        },
+        write_corrected_exif: args.write_corrected_exif,
+        infer_sequence_dates: args.infer_sequence_dates,
+        sequence_window: chrono::Duration::hours(args.sequence_window_hours.into()),
+        owners,
+        owner_layout: args.owner_layout,
+        edit_policy: args.edit_policy,
+        restore_titles: args.restore_titles,
+        rename_template: args.rename_template.clone(),
+        target_fs: args.target_fs,
+    })
 }
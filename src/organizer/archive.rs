@@ -0,0 +1,919 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// An archive entry that could not be read back intact.
+#[derive(Debug, Clone)]
+pub struct DamagedEntry {
+    pub name: String,
+    pub error: String,
+}
+
+/// Outcome of reading every entry of an archive, either to check or to extract it.
+#[derive(Debug)]
+pub struct ArchiveHealth {
+    pub archive: PathBuf,
+    pub entries_ok: usize,
+    pub damaged: Vec<DamagedEntry>,
+    /// The archive ended early (or lost its index), so trailing entries may be missing.
+    pub truncated: bool,
+    /// Modification times from the headers of the extracted files, by extracted path. Files
+    /// whose entry carries no timestamp are left out.
+    pub entry_dates: HashMap<PathBuf, DateTime<Utc>>,
+}
+
+impl ArchiveHealth {
+    fn new(archive: &Path) -> Self {
+        Self {
+            archive: archive.to_path_buf(),
+            entries_ok: 0,
+            damaged: Vec::new(),
+            truncated: false,
+            entry_dates: HashMap::new(),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.damaged.is_empty() && !self.truncated
+    }
+}
+
+/// Entries that mark a ZIP as an office document or application package rather than a Takeout.
+const PACKAGE_MARKERS: [&str; 4] = [
+    "[Content_Types].xml",
+    "mimetype",
+    "META-INF/MANIFEST.MF",
+    "AndroidManifest.xml",
+];
+
+/// Archive container formats understood by the organizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarBz2,
+    TarXz,
+    TarZst,
+    SevenZip,
+}
+
+impl ArchiveFormat {
+    /// Detects the format from the file's magic bytes, so mis-named downloads still work.
+    pub fn detect(path: &Path) -> Option<Self> {
+        if !path.is_file() {
+            return None;
+        }
+        let mut header = [0u8; 262];
+        let mut file = fs::File::open(path).ok()?;
+        let mut len = 0;
+        while len < header.len() {
+            match file.read(&mut header[len..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+        }
+        let format = Self::from_magic(&header[..len])?;
+        format.has_archive_contents(path).then_some(format)
+    }
+
+    /// Looks past the magic bytes, which documents, packages and lone compressed files share:
+    /// a ZIP must list an entry (or, having lost its index, start with one) and not be a
+    /// document or package, and a compressed tar must decompress to a tar header.
+    fn has_archive_contents(self, path: &Path) -> bool {
+        let Ok(file) = fs::File::open(path) else {
+            return false;
+        };
+        match self {
+            ArchiveFormat::Zip => match zip::ZipArchive::new(file) {
+                Ok(archive) => {
+                    !archive.is_empty()
+                        && !archive
+                            .file_names()
+                            .any(|name| PACKAGE_MARKERS.contains(&name))
+                }
+                Err(_) => {
+                    let Ok(file) = fs::File::open(path) else {
+                        return false;
+                    };
+                    let mut reader = io::BufReader::new(file);
+                    matches!(
+                        zip::read::read_zipfile_from_stream(&mut reader),
+                        Ok(Some(entry)) if !PACKAGE_MARKERS.contains(&entry.name())
+                    )
+                }
+            },
+            // The tar header and the 7z signature are checked with the magic bytes
+            ArchiveFormat::Tar | ArchiveFormat::SevenZip => true,
+            _ => {
+                let Ok(Some(mut stream)) = self.tar_stream(file) else {
+                    return false;
+                };
+                let mut header = [0u8; 262];
+                stream.read_exact(&mut header).is_ok() && &header[257..262] == b"ustar"
+            }
+        }
+    }
+
+    pub fn from_magic(header: &[u8]) -> Option<Self> {
+        const MAGICS: [(&[u8], ArchiveFormat); 8] = [
+            (b"PK\x03\x04", ArchiveFormat::Zip),
+            (b"PK\x05\x06", ArchiveFormat::Zip),
+            (b"PK\x07\x08", ArchiveFormat::Zip),
+            (b"\x1f\x8b", ArchiveFormat::TarGz),
+            (b"BZh", ArchiveFormat::TarBz2),
+            (b"\xfd7zXZ\x00", ArchiveFormat::TarXz),
+            (b"\x28\xb5\x2f\xfd", ArchiveFormat::TarZst),
+            (b"7z\xbc\xaf\x27\x1c", ArchiveFormat::SevenZip),
+        ];
+
+        if let Some((_, format)) = MAGICS.iter().find(|(magic, _)| header.starts_with(magic)) {
+            return Some(*format);
+        }
+
+        // POSIX and GNU tar headers carry "ustar" at offset 257
+        if header.len() >= 262 && &header[257..262] == b"ustar" {
+            return Some(ArchiveFormat::Tar);
+        }
+
+        None
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "ZIP",
+            ArchiveFormat::Tar => "TAR",
+            ArchiveFormat::TarGz => "TAR.GZ",
+            ArchiveFormat::TarBz2 => "TAR.BZ2",
+            ArchiveFormat::TarXz => "TAR.XZ",
+            ArchiveFormat::TarZst => "TAR.ZST",
+            ArchiveFormat::SevenZip => "7Z",
+        }
+    }
+
+    /// Wraps `file` in the decompressor of a tar-based format. `None` for ZIP and 7z.
+    fn tar_stream(self, file: fs::File) -> Result<Option<Box<dyn Read>>> {
+        let reader = io::BufReader::new(file);
+        let stream: Box<dyn Read> = match self {
+            ArchiveFormat::Tar => Box::new(reader),
+            ArchiveFormat::TarGz => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            ArchiveFormat::TarBz2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            ArchiveFormat::TarXz => Box::new(lzma_rust2::XzReader::new(reader, true)),
+            ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            ArchiveFormat::Zip | ArchiveFormat::SevenZip => return Ok(None),
+        };
+        Ok(Some(stream))
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    ArchiveFormat::detect(path).is_some()
+}
+
+/// Pre-flight pass: reads every entry (testing checksums and compressed streams) without writing anything.
+pub fn check_archive(archive_path: &Path) -> Result<ArchiveHealth> {
+    info!("Checking archive integrity: {:?}", archive_path);
+    read_archive(archive_path, None, &|| false)
+}
+
+/// Extracts every readable entry, skipping over damaged ones instead of aborting the whole archive.
+/// Stops between two entries once `stop` returns true, keeping the entries extracted so far.
+pub fn extract_archive(
+    archive_path: &Path,
+    extract_to: &Path,
+    stop: &dyn Fn() -> bool,
+) -> Result<ArchiveHealth> {
+    read_archive(archive_path, Some(extract_to), stop)
+}
+
+/// Names and uncompressed sizes of the files in an archive, read from its index (or, for a
+/// plain tar, by seeking from header to header) without decompressing any data. `None` for
+/// compressed tars, whose headers can only be reached by decompressing the whole stream.
+pub fn indexed_entries(archive_path: &Path) -> Result<Option<Vec<(String, u64)>>> {
+    let format = ArchiveFormat::detect(archive_path)
+        .with_context(|| format!("Unrecognised archive format: {:?}", archive_path))?;
+
+    let mut entries = Vec::new();
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(fs::File::open(archive_path)?)?;
+            for i in 0..archive.len() {
+                let entry = archive.by_index_raw(i)?;
+                if !entry.is_dir() {
+                    entries.push((entry.name().to_string(), entry.size()));
+                }
+            }
+        }
+        ArchiveFormat::SevenZip => {
+            let archive =
+                sevenz_rust2::ArchiveReader::open(archive_path, sevenz_rust2::Password::empty())?;
+            for entry in &archive.archive().files {
+                if !entry.is_directory() {
+                    entries.push((entry.name().to_string(), entry.size()));
+                }
+            }
+        }
+        ArchiveFormat::Tar => {
+            let mut archive = tar::Archive::new(io::BufReader::new(fs::File::open(archive_path)?));
+            for entry in archive.entries_with_seek()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let name = entry.path()?.to_string_lossy().to_string();
+                    entries.push((name, entry.header().size()?));
+                }
+            }
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(entries))
+}
+
+/// Total uncompressed size of all entries, without extracting. Compressed tars have no index,
+/// so their size on disk stands in: photos and videos barely compress.
+pub fn uncompressed_size(archive_path: &Path) -> Result<u64> {
+    match indexed_entries(archive_path)? {
+        Some(entries) => Ok(entries.iter().map(|(_, size)| size).sum()),
+        None => Ok(fs::metadata(archive_path)?.len()),
+    }
+}
+
+fn read_archive(
+    archive_path: &Path,
+    extract_to: Option<&Path>,
+    stop: &dyn Fn() -> bool,
+) -> Result<ArchiveHealth> {
+    let format = ArchiveFormat::detect(archive_path)
+        .with_context(|| format!("Unrecognised archive format: {:?}", archive_path))?;
+
+    let mut health = ArchiveHealth::new(archive_path);
+
+    if extract_to.is_some() {
+        info!("Extracting {} archive: {:?}", format.label(), archive_path);
+    }
+
+    match format {
+        ArchiveFormat::Zip => read_zip(archive_path, extract_to, stop, &mut health)?,
+        ArchiveFormat::SevenZip => read_7z(archive_path, extract_to, stop, &mut health)?,
+        _ => {
+            let file = fs::File::open(archive_path)?;
+            if let Some(stream) = format.tar_stream(file)? {
+                read_tar(stream, extract_to, stop, &mut health)?;
+            }
+        }
+    }
+
+    Ok(health)
+}
+
+fn read_zip(
+    archive_path: &Path,
+    extract_to: Option<&Path>,
+    stop: &dyn Fn() -> bool,
+    health: &mut ArchiveHealth,
+) -> Result<()> {
+    let file = fs::File::open(archive_path)?;
+    let mut archive = match zip::ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(e) => {
+            // A truncated download loses the central directory at the end of the file.
+            // Fall back to walking the local headers from the start.
+            warn!(
+                "Cannot read ZIP index of {:?} ({}), recovering entries sequentially",
+                archive_path, e
+            );
+            health.truncated = true;
+            let mut reader = io::BufReader::new(fs::File::open(archive_path)?);
+            while !stop() {
+                match zip::read::read_zipfile_from_stream(&mut reader) {
+                    Ok(Some(mut entry)) => {
+                        let name = entry.name().to_string();
+                        let is_dir = entry.is_dir();
+                        let outpath = entry.enclosed_name();
+                        let modified = zip_mtime(entry.last_modified());
+                        let result =
+                            read_entry(&mut entry, is_dir, outpath.clone(), modified, extract_to);
+                        if !is_dir {
+                            record_date(health, &result, extract_to, outpath, modified);
+                        }
+                        record(health, name, result);
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        health.damaged.push(DamagedEntry {
+                            name: "<remaining entries>".to_string(),
+                            error: e.to_string(),
+                        });
+                        break;
+                    }
+                }
+            }
+            return Ok(());
+        }
+    };
+
+    for i in 0..archive.len() {
+        if stop() {
+            break;
+        }
+        let name = archive
+            .name_for_index(i)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("entry #{}", i));
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(e) => {
+                health.damaged.push(DamagedEntry {
+                    name,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let is_dir = entry.is_dir();
+        let outpath = entry.enclosed_name();
+        let modified = zip_mtime(entry.last_modified());
+        let result = read_entry(&mut entry, is_dir, outpath.clone(), modified, extract_to);
+        if !is_dir {
+            record_date(health, &result, extract_to, outpath, modified);
+        }
+        record(health, name, result);
+    }
+
+    Ok(())
+}
+
+fn read_tar<R: Read>(
+    reader: R,
+    extract_to: Option<&Path>,
+    stop: &dyn Fn() -> bool,
+    health: &mut ArchiveHealth,
+) -> Result<()> {
+    if let Some(dir) = extract_to {
+        fs::create_dir_all(dir)?;
+    }
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        if stop() {
+            break;
+        }
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                // Compressed tar streams cannot be resynchronised after an error.
+                health.truncated = true;
+                health.damaged.push(DamagedEntry {
+                    name: "<remaining entries>".to_string(),
+                    error: e.to_string(),
+                });
+                break;
+            }
+        };
+
+        let name = entry
+            .path()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let result = match extract_to {
+            Some(dir) => entry.unpack_in(dir).map(|_| ()).inspect_err(|_| {
+                let _ = fs::remove_file(dir.join(&name));
+            }),
+            None => io::copy(&mut entry, &mut io::sink()).map(|_| ()),
+        };
+
+        if let Err(e) = result {
+            health.truncated = true;
+            record(health, name, Err(e.into()));
+            break;
+        }
+        if entry.header().entry_type().is_file() {
+            let modified = entry
+                .header()
+                .mtime()
+                .ok()
+                .filter(|&seconds| seconds > 0)
+                .map(|seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+            record_date(
+                health,
+                &Ok(()),
+                extract_to,
+                Some(PathBuf::from(&name)),
+                modified,
+            );
+        }
+        record(health, name, Ok(()));
+    }
+
+    Ok(())
+}
+
+fn read_7z(
+    archive_path: &Path,
+    extract_to: Option<&Path>,
+    stop: &dyn Fn() -> bool,
+    health: &mut ArchiveHealth,
+) -> Result<()> {
+    let mut archive =
+        sevenz_rust2::ArchiveReader::open(archive_path, sevenz_rust2::Password::empty())
+            .with_context(|| format!("Cannot read 7z index of {:?}", archive_path))?;
+
+    let result = archive.for_each_entries(|entry, reader| {
+        if stop() {
+            return Ok(false);
+        }
+        let outpath = enclosed_name(entry.name());
+        let modified = entry
+            .has_last_modified_date
+            .then(|| entry.last_modified_date().into());
+        let is_dir = entry.is_directory();
+        let result = read_entry(reader, is_dir, outpath.clone(), modified, extract_to);
+        if !is_dir {
+            record_date(health, &result, extract_to, outpath, modified);
+        }
+        record(health, entry.name().to_string(), result);
+        Ok(true)
+    });
+
+    if let Err(e) = result {
+        // Solid blocks cannot be resumed once their stream breaks
+        health.truncated = true;
+        health.damaged.push(DamagedEntry {
+            name: "<remaining entries>".to_string(),
+            error: e.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Rejects absolute paths and `..` components so entries cannot escape the extraction folder.
+fn enclosed_name(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().all(|c| {
+        matches!(
+            c,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    }) {
+        Some(path.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// Converts a ZIP timestamp (local time without zone, read as UTC) for the extracted file.
+/// The DOS epoch is what writers store when they have no time, so it counts as none.
+fn zip_mtime(datetime: Option<zip::DateTime>) -> Option<SystemTime> {
+    let datetime = datetime.filter(|d| *d != zip::DateTime::default())?;
+    let date = NaiveDate::from_ymd_opt(
+        datetime.year().into(),
+        datetime.month().into(),
+        datetime.day().into(),
+    )?;
+    let naive = date.and_hms_opt(
+        datetime.hour().into(),
+        datetime.minute().into(),
+        datetime.second().into(),
+    )?;
+    Some(naive.and_utc().into())
+}
+
+/// Reads a single ZIP or 7z entry to `extract_to`, or just through its CRC check when only checking.
+/// The entry's modification time is kept so it can serve as a date fallback.
+fn read_entry<R: Read + ?Sized>(
+    entry: &mut R,
+    is_dir: bool,
+    outpath: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    extract_to: Option<&Path>,
+) -> Result<()> {
+    let Some(extract_to) = extract_to else {
+        io::copy(entry, &mut io::sink())?;
+        return Ok(());
+    };
+
+    let Some(outpath) = outpath.map(|p| extract_to.join(p)) else {
+        return Ok(());
+    };
+
+    if is_dir {
+        fs::create_dir_all(&outpath)?;
+        return Ok(());
+    }
+
+    if let Some(p) = outpath.parent()
+        && !p.exists()
+    {
+        fs::create_dir_all(p)?;
+    }
+    let mut outfile = fs::File::create(&outpath)?;
+    if let Err(e) = io::copy(entry, &mut outfile) {
+        drop(outfile);
+        let _ = fs::remove_file(&outpath);
+        return Err(e.into());
+    }
+    if let Some(modified) = modified {
+        outfile.set_modified(modified)?;
+    }
+    Ok(())
+}
+
+/// Keeps the header timestamp of a file extracted to `extract_to`, under the path it was
+/// written to.
+fn record_date(
+    health: &mut ArchiveHealth,
+    result: &Result<()>,
+    extract_to: Option<&Path>,
+    outpath: Option<PathBuf>,
+    modified: Option<SystemTime>,
+) {
+    if let (Ok(()), Some(dir), Some(outpath), Some(modified)) =
+        (result, extract_to, outpath, modified)
+    {
+        let relative: PathBuf = outpath
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        health
+            .entry_dates
+            .insert(dir.join(relative), modified.into());
+    }
+}
+
+fn record(health: &mut ArchiveHealth, name: String, result: Result<()>) {
+    match result {
+        Ok(()) => health.entries_ok += 1,
+        Err(e) => {
+            warn!("Damaged entry {:?} in {:?}: {}", name, health.archive, e);
+            health.damaged.push(DamagedEntry {
+                name,
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// Logs every damaged archive with its bad entries and which Takeout parts need downloading again.
+pub fn log_damaged_archives(reports: &[ArchiveHealth]) {
+    let damaged: Vec<_> = reports.iter().filter(|r| !r.is_healthy()).collect();
+    if damaged.is_empty() {
+        return;
+    }
+
+    warn!("--- Damaged Archives ---");
+    for report in &damaged {
+        warn!(
+            "{:?}: {} entries OK, {} damaged{}",
+            report.archive,
+            report.entries_ok,
+            report.damaged.len(),
+            if report.truncated { " (truncated)" } else { "" }
+        );
+        for entry in &report.damaged {
+            warn!("  - {}: {}", entry.name, entry.error);
+        }
+    }
+    warn!("Please re-download these Takeout parts:");
+    for report in &damaged {
+        warn!(
+            "  - {}",
+            report
+                .archive
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+    }
+    warn!("------------------------");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let file = fs::File::create(path).unwrap();
+        let mut zip = zip::ZipWriter::new(file);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, content) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_extract_archive_zip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("test.zip");
+        let extract_to = temp_dir.path().join("extracted_zip");
+
+        write_zip(&archive_path, &[("test.txt", b"hello zip world")]);
+
+        let health = extract_archive(&archive_path, &extract_to, &|| false).unwrap();
+        assert!(health.is_healthy());
+
+        let extracted_file = extract_to.join("test.txt");
+        assert!(extracted_file.exists());
+        assert_eq!(
+            std::fs::read_to_string(extracted_file).unwrap(),
+            "hello zip world"
+        );
+    }
+
+    #[test]
+    fn test_extract_archive_stops_between_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("test.zip");
+        let extract_to = temp_dir.path().join("extracted");
+        write_zip(&archive_path, &[("a.jpg", b"first"), ("b.jpg", b"second")]);
+
+        let checks = std::cell::Cell::new(0);
+        let stop = || {
+            checks.set(checks.get() + 1);
+            checks.get() > 1
+        };
+        let health = extract_archive(&archive_path, &extract_to, &stop).unwrap();
+        assert_eq!(health.entries_ok, 1);
+        assert!(extract_to.join("a.jpg").exists());
+        assert!(!extract_to.join("b.jpg").exists());
+    }
+
+    #[test]
+    fn test_extract_archive_keeps_zip_mtime() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("test.zip");
+        let extract_to = temp_dir.path().join("extracted");
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive_path).unwrap());
+        let modified = zip::DateTime::from_date_and_time(2012, 6, 3, 10, 30, 0).unwrap();
+        let options = zip::write::SimpleFileOptions::default().last_modified_time(modified);
+        zip.start_file("scan.jpg", options).unwrap();
+        zip.write_all(b"pixels").unwrap();
+        zip.finish().unwrap();
+
+        let health = extract_archive(&archive_path, &extract_to, &|| false).unwrap();
+        let mtime: chrono::DateTime<chrono::Utc> = fs::metadata(extract_to.join("scan.jpg"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .into();
+        assert_eq!(mtime.to_rfc3339(), "2012-06-03T10:30:00+00:00");
+        assert_eq!(
+            health.entry_dates[&extract_to.join("scan.jpg")].to_rfc3339(),
+            "2012-06-03T10:30:00+00:00"
+        );
+
+        // A header without a timestamp gives no date, whatever the extracted file's mtime
+        let tar_path = temp_dir.path().join("undated.tar");
+        fs::write(&tar_path, tar_bytes()).unwrap();
+        let health =
+            extract_archive(&tar_path, &temp_dir.path().join("undated"), &|| false).unwrap();
+        assert_eq!(health.entries_ok, 1);
+        assert!(health.entry_dates.is_empty());
+    }
+
+    #[test]
+    fn test_extract_archive_tar_gz() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("test.tar.gz");
+        let extract_to = temp_dir.path().join("extracted_tar_gz");
+
+        // Create a dummy tar.gz file
+        let file = std::fs::File::create(&archive_path).unwrap();
+        let enc = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut tar_builder = tar::Builder::new(enc);
+
+        let mut header = tar::Header::new_gnu();
+        header.set_path("test_tar.txt").unwrap();
+        header.set_size(14);
+        header.set_cksum();
+        tar_builder
+            .append(&header, "hello targz ok".as_bytes())
+            .unwrap();
+        // Fully flush the encoder to disk
+        tar_builder.into_inner().unwrap().finish().unwrap();
+
+        // Extract it
+        extract_archive(&archive_path, &extract_to, &|| false).unwrap();
+
+        // Verify
+        let extracted_file = extract_to.join("test_tar.txt");
+        assert!(extracted_file.exists());
+        assert_eq!(
+            std::fs::read_to_string(extracted_file).unwrap(),
+            "hello targz ok"
+        );
+    }
+
+    #[test]
+    fn test_corrupt_zip_entry_is_skipped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("takeout-001.zip");
+        let extract_to = temp_dir.path().join("out");
+
+        write_zip(
+            &archive_path,
+            &[
+                ("bad.jpg", b"AAAAAAAAAAAAAAAA"),
+                ("good.jpg", b"good content"),
+            ],
+        );
+
+        // Flip a byte in the stored data of the first entry so its CRC no longer matches
+        let mut bytes = fs::read(&archive_path).unwrap();
+        let pos = bytes.windows(4).position(|w| w == b"AAAA").unwrap();
+        bytes[pos] = b'B';
+        fs::write(&archive_path, bytes).unwrap();
+
+        let check = check_archive(&archive_path).unwrap();
+        assert_eq!(check.entries_ok, 1);
+        assert_eq!(check.damaged.len(), 1);
+        assert_eq!(check.damaged[0].name, "bad.jpg");
+
+        let health = extract_archive(&archive_path, &extract_to, &|| false).unwrap();
+        assert!(!health.is_healthy());
+        assert!(!extract_to.join("bad.jpg").exists());
+        assert_eq!(
+            fs::read_to_string(extract_to.join("good.jpg")).unwrap(),
+            "good content"
+        );
+    }
+
+    #[test]
+    fn test_truncated_zip_recovers_leading_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("takeout-002.zip");
+        let extract_to = temp_dir.path().join("out");
+
+        write_zip(
+            &archive_path,
+            &[
+                ("first.jpg", b"first content"),
+                ("second.jpg", &[7u8; 4096]),
+            ],
+        );
+
+        // Cut the download in the middle of the second entry
+        let bytes = fs::read(&archive_path).unwrap();
+        fs::write(&archive_path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let health = extract_archive(&archive_path, &extract_to, &|| false).unwrap();
+        assert!(health.truncated);
+        assert_eq!(health.entries_ok, 1);
+        assert_eq!(
+            fs::read_to_string(extract_to.join("first.jpg")).unwrap(),
+            "first content"
+        );
+        assert!(!extract_to.join("second.jpg").exists());
+    }
+
+    fn tar_bytes() -> Vec<u8> {
+        let mut tar_builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path("Takeout/photo.jpg").unwrap();
+        header.set_size(5);
+        header.set_cksum();
+        tar_builder.append(&header, "jpeg!".as_bytes()).unwrap();
+        tar_builder.into_inner().unwrap()
+    }
+
+    fn assert_extracts(archive_path: &Path, expected: ArchiveFormat) {
+        assert_eq!(ArchiveFormat::detect(archive_path), Some(expected));
+
+        let extract_to = archive_path.with_extension("out");
+        let health = extract_archive(archive_path, &extract_to, &|| false).unwrap();
+        assert!(health.is_healthy(), "{:?}", health);
+        assert_eq!(
+            fs::read_to_string(extract_to.join("Takeout/photo.jpg")).unwrap(),
+            "jpeg!"
+        );
+    }
+
+    #[test]
+    fn test_detect_by_magic_ignores_file_name() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let misnamed = temp_dir.path().join("download.bin");
+        write_zip(&misnamed, &[("test.txt", b"hello")]);
+        assert!(is_archive(&misnamed));
+        assert_eq!(ArchiveFormat::detect(&misnamed), Some(ArchiveFormat::Zip));
+
+        let not_archive = temp_dir.path().join("photos.zip");
+        fs::write(&not_archive, b"\xff\xd8\xff\xe0 not a zip").unwrap();
+        assert!(!is_archive(&not_archive));
+        assert!(!is_archive(temp_dir.path()));
+    }
+
+    #[test]
+    fn test_detect_rejects_files_sharing_archive_magic() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        // Office documents and Java/Android packages are ZIPs too
+        let docx = dir.join("letter.docx");
+        write_zip(
+            &docx,
+            &[
+                ("[Content_Types].xml", b"<Types/>"),
+                ("word/document.xml", b""),
+            ],
+        );
+        assert!(!is_archive(&docx));
+        let jar = dir.join("tool.jar");
+        write_zip(&jar, &[("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0")]);
+        assert!(!is_archive(&jar));
+        let empty = dir.join("empty.zip");
+        write_zip(&empty, &[]);
+        assert!(!is_archive(&empty));
+
+        // A compressed file that is not a tar
+        let gz = dir.join("notes.txt.gz");
+        let mut enc = flate2::write::GzEncoder::new(
+            fs::File::create(&gz).unwrap(),
+            flate2::Compression::default(),
+        );
+        enc.write_all(&[b'x'; 1024]).unwrap();
+        enc.finish().unwrap();
+        assert!(!is_archive(&gz));
+
+        let text = dir.join("BZh.txt");
+        fs::write(&text, "BZh is how this note starts").unwrap();
+        assert!(!is_archive(&text));
+    }
+
+    #[test]
+    fn test_extract_tar_variants() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        let plain = dir.join("plain.tar");
+        fs::write(&plain, tar_bytes()).unwrap();
+        assert_extracts(&plain, ArchiveFormat::Tar);
+
+        let bz2 = dir.join("repacked.tar.bz2");
+        let mut enc =
+            bzip2::write::BzEncoder::new(fs::File::create(&bz2).unwrap(), Default::default());
+        enc.write_all(&tar_bytes()).unwrap();
+        enc.finish().unwrap();
+        assert_extracts(&bz2, ArchiveFormat::TarBz2);
+
+        let xz = dir.join("repacked.tar.xz");
+        let mut enc = lzma_rust2::XzWriter::new(
+            fs::File::create(&xz).unwrap(),
+            lzma_rust2::XzOptions::with_preset(1),
+        )
+        .unwrap();
+        enc.write_all(&tar_bytes()).unwrap();
+        enc.finish().unwrap();
+        assert_extracts(&xz, ArchiveFormat::TarXz);
+
+        let zst = dir.join("repacked.tar.zst");
+        fs::write(&zst, zstd::encode_all(&tar_bytes()[..], 3).unwrap()).unwrap();
+        assert_extracts(&zst, ArchiveFormat::TarZst);
+    }
+
+    #[test]
+    fn test_uncompressed_size() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let zip_path = temp_dir.path().join("a.zip");
+        write_zip(&zip_path, &[("a.jpg", b"12345"), ("b.jpg", b"1234567")]);
+        assert_eq!(uncompressed_size(&zip_path).unwrap(), 12);
+
+        let tar_path = temp_dir.path().join("a.tar");
+        fs::write(&tar_path, tar_bytes()).unwrap();
+        assert_eq!(uncompressed_size(&tar_path).unwrap(), 5);
+        assert_eq!(
+            indexed_entries(&tar_path).unwrap(),
+            Some(vec![("Takeout/photo.jpg".to_string(), 5)])
+        );
+
+        // Without an index the compressed size stands in
+        let zst_path = temp_dir.path().join("a.tar.zst");
+        fs::write(&zst_path, zstd::encode_all(&tar_bytes()[..], 3).unwrap()).unwrap();
+        assert_eq!(indexed_entries(&zst_path).unwrap(), None);
+        assert_eq!(
+            uncompressed_size(&zst_path).unwrap(),
+            fs::metadata(&zst_path).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_extract_7z() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir_all(src.join("Takeout")).unwrap();
+        fs::write(src.join("Takeout/photo.jpg"), "jpeg!").unwrap();
+
+        let archive_path = temp_dir.path().join("repacked.7z");
+        sevenz_rust2::compress_to_path(&src, &archive_path).unwrap();
+        assert_extracts(&archive_path, ArchiveFormat::SevenZip);
+    }
+}
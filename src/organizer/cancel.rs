@@ -0,0 +1,230 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Exit code of a run stopped with Ctrl-C, as shells report it (128 + SIGINT).
+pub const CANCELLED_EXIT_CODE: u8 = 130;
+
+/// How often a running child process is checked for cancellation.
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Numbers the temporary names, so writers aiming at the same destination never share one.
+static PARTIAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Files being written under a temporary name, and extraction folders, removed if the process
+/// is stopped for good.
+static PARTIAL_FILES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Makes the first Ctrl-C stop scheduling new work while files in progress finish, and a
+/// second one quit at once after removing the partial files.
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            remove_partial_files();
+            std::process::exit(CANCELLED_EXIT_CODE.into());
+        }
+        log::warn!("Stopping after the files in progress (press Ctrl-C again to quit now)...");
+    })
+    .context("Failed to install the Ctrl-C handler")
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+fn remove_partial_files() {
+    if let Ok(files) = PARTIAL_FILES.lock() {
+        for path in files.iter() {
+            if path.is_dir() {
+                let _ = fs::remove_dir_all(path);
+            } else {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// A file written under a temporary name next to its destination and only renamed into place
+/// by [`PartialFile::commit`], so an interrupted write never looks complete. Dropping it
+/// uncommitted removes what was written.
+pub struct PartialFile {
+    path: PathBuf,
+    dest: PathBuf,
+}
+
+impl PartialFile {
+    pub fn new(dest: &Path) -> Self {
+        // Hidden, unique across threads and processes, and keeping the extension tools such
+        // as ffmpeg pick the format from
+        let name = dest.file_name().unwrap_or_default().to_string_lossy();
+        let path = dest.with_file_name(format!(
+            ".part-{}-{}-{}",
+            std::process::id(),
+            PARTIAL_COUNTER.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        if let Ok(mut files) = PARTIAL_FILES.lock() {
+            files.insert(path.clone());
+        }
+        Self {
+            path,
+            dest: dest.to_path_buf(),
+        }
+    }
+
+    /// Where to write the content.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the finished file to its destination.
+    pub fn commit(self) -> Result<()> {
+        fs::rename(&self.path, &self.dest)
+            .with_context(|| format!("Failed to move {:?} into place", self.dest))
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Ok(mut files) = PARTIAL_FILES.lock() {
+            files.remove(&self.path);
+        }
+        // Already renamed away when committed
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A temporary folder that is removed when dropped, and also when a second Ctrl-C quits
+/// without running destructors.
+pub struct PartialDir {
+    dir: TempDir,
+}
+
+impl PartialDir {
+    /// Creates the folder in `parent`, or in the system temp directory.
+    pub fn new(parent: Option<&Path>) -> Result<Self> {
+        let dir = match parent {
+            Some(parent) => {
+                fs::create_dir_all(parent)?;
+                TempDir::new_in(parent)?
+            }
+            None => TempDir::new()?,
+        };
+        if let Ok(mut files) = PARTIAL_FILES.lock() {
+            files.insert(dir.path().to_path_buf());
+        }
+        Ok(Self { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for PartialDir {
+    fn drop(&mut self) {
+        if let Ok(mut files) = PARTIAL_FILES.lock() {
+            files.remove(self.dir.path());
+        }
+    }
+}
+
+/// Runs `command` to completion like [`Command::output`], but kills it once the run is
+/// cancelled and returns an error instead.
+pub fn run_child(command: &mut Command) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Cancelled");
+        }
+        thread::sleep(CHILD_POLL_INTERVAL);
+    };
+    // Ctrl-C also reaches children directly, which then exit early with partial output
+    if is_cancelled() && !status.success() {
+        bail!("Cancelled");
+    }
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Reads a child's pipe on its own thread, so a chatty child never blocks on a full pipe.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_file_only_appears_when_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("clip.mp4");
+
+        let partial = PartialFile::new(&dest);
+        let name = partial.path().file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with(".part-") && name.ends_with("-clip.mp4"));
+        // A second writer of the same file gets its own temporary name
+        let other = PartialFile::new(&dest);
+        assert_ne!(partial.path(), other.path());
+        drop(other);
+
+        let path = partial.path().to_path_buf();
+        fs::write(&path, "half").unwrap();
+        drop(partial);
+        assert!(!path.exists());
+        assert!(!dest.exists());
+
+        let partial = PartialFile::new(&dest);
+        let path = partial.path().to_path_buf();
+        fs::write(&path, "whole").unwrap();
+        partial.commit().unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "whole");
+        assert!(!PARTIAL_FILES.lock().unwrap().contains(&path));
+    }
+
+    #[test]
+    fn test_partial_dir_is_registered_until_dropped() {
+        let parent = tempfile::tempdir().unwrap();
+        let staging = PartialDir::new(Some(&parent.path().join("staging"))).unwrap();
+        let path = staging.path().to_path_buf();
+        assert!(PARTIAL_FILES.lock().unwrap().contains(&path));
+
+        drop(staging);
+        assert!(!path.exists());
+        assert!(!PARTIAL_FILES.lock().unwrap().contains(&path));
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use super::cancel::PartialFile;
+use super::model::DateSource;
+
+/// Name of the catalog file stored at the root of the organized library.
+pub const CATALOG_FILE: &str = ".catalog.json";
+
+/// A single organized file, keyed in the catalog by its path relative to the library root.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Path of the file relative to the Takeout source it was copied from, with the folder
+    /// names as they were before any sanitising for the target filesystem.
+    pub source: String,
+    /// Resolved capture date (RFC 3339), absent for undated files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Where the date came from, so low-confidence placements can be reviewed later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_source: Option<DateSource>,
+    /// Year of the enclosing Takeout year folder when it contradicts the resolved date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder_year: Option<i32>,
+    /// Camera date before a clock skew rule corrected it (RFC 3339).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_date: Option<String>,
+    /// Date from the Takeout JSON sidecar (RFC 3339), which is not copied to the library.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecar_date: Option<String>,
+    /// Accounts whose Takeout contained this file, when inputs are labelled with owners.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+    /// Library key of the original, when this file is its edited copy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit_of: Option<String>,
+    /// Name of the file in the Takeout, when it was renamed on output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub entries: BTreeMap<String, CatalogEntry>,
+}
+
+impl Catalog {
+    /// Loads the catalog of the library at `root`, or an empty one if none exists yet.
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join(CATALOG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read catalog {:?}", path))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid catalog {:?}", path))
+    }
+
+    /// Loads the catalog of an existing library, failing when the library or its catalog is
+    /// missing instead of starting an empty one.
+    pub fn load_existing(root: &Path) -> Result<Self> {
+        if !root.is_dir() {
+            bail!("Library {:?} does not exist", root);
+        }
+        if !root.join(CATALOG_FILE).is_file() {
+            bail!("{:?} has no catalog; organize files into it first", root);
+        }
+        Self::load(root)
+    }
+
+    pub fn save(&self, root: &Path) -> Result<()> {
+        fs::create_dir_all(root)?;
+        let path = root.join(CATALOG_FILE);
+        let content = serde_json::to_string_pretty(self)?;
+        // Written aside and renamed, so an interrupted save keeps the previous catalog
+        let partial = PartialFile::new(&path);
+        fs::write(partial.path(), content)
+            .with_context(|| format!("Failed to write catalog {:?}", path))?;
+        partial.commit()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&CatalogEntry> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, entry: CatalogEntry) {
+        self.entries.insert(key, entry);
+    }
+
+    /// Points edited copies whose original moved (old key to new key in `moved`) at the
+    /// original's new key.
+    pub fn relink_edits(&mut self, moved: &HashMap<String, String>) {
+        for entry in self.entries.values_mut() {
+            if let Some(new_key) = entry.edit_of.as_ref().and_then(|key| moved.get(key)) {
+                entry.edit_of = Some(new_key.clone());
+            }
+        }
+    }
+}
+
+/// Returns the catalog key (a `/`-separated relative path) for `path` inside `root`.
+pub fn catalog_key(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2023/May/20/img.jpg".to_string(),
+            CatalogEntry {
+                size: 10,
+                sha256: Some("abc".to_string()),
+                source: "Takeout/Google Photos/img.jpg".to_string(),
+                ..Default::default()
+            },
+        );
+        catalog.save(dir.path()).unwrap();
+
+        let loaded = Catalog::load(dir.path()).unwrap();
+        assert_eq!(
+            loaded.get("2023/May/20/img.jpg"),
+            catalog.get("2023/May/20/img.jpg")
+        );
+    }
+
+    #[test]
+    fn test_catalog_load_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = Catalog::load(dir.path()).unwrap();
+        assert!(catalog.entries.is_empty());
+    }
+
+    #[test]
+    fn test_catalog_key() {
+        let root = Path::new("/tmp/output");
+        let path = root.join("2023/May/20/img.jpg");
+        assert_eq!(
+            catalog_key(root, &path),
+            Some("2023/May/20/img.jpg".to_string())
+        );
+        assert_eq!(catalog_key(root, Path::new("/elsewhere/img.jpg")), None);
+    }
+}
@@ -0,0 +1,519 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Duration, Months, NaiveDateTime, Utc};
+use log::{debug, info};
+use serde::Deserialize;
+use std::fs;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use super::cancel::PartialFile;
+use super::filters::parse_filter_date;
+use super::model::{DateSource, ResolvedDate};
+
+/// Name of the report of adjusted files written at the root of the organized library.
+pub const REPORT_FILE: &str = "clock-skew-report.csv";
+
+/// EXIF tags holding dates that are rewritten when corrected dates are written back.
+const EXIF_DATE_TAGS: [exif::Tag; 3] = [
+    exif::Tag::DateTimeOriginal,
+    exif::Tag::DateTimeDigitized,
+    exif::Tag::DateTime,
+];
+
+/// TIFF field type of NUL-terminated strings.
+const EXIF_ASCII: u16 = 2;
+
+/// Length of an EXIF date such as `2000:01:01 00:03:12`.
+const EXIF_DATE_LEN: u32 = 19;
+
+/// A calendar shift such as `+3y 2m 4h`: months are applied first, then the exact duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shift {
+    pub months: i32,
+    pub duration: Duration,
+}
+
+impl Shift {
+    /// Parses space-separated `<n><unit>` terms with units `y`, `m` (months), `w`, `d`, `h`,
+    /// `min` and `s`. A leading sign applies to every term unless a term has its own.
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (default_sign, terms) = match value.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, value.strip_prefix('+').unwrap_or(value)),
+        };
+
+        let mut shift = Shift {
+            months: 0,
+            duration: Duration::zero(),
+        };
+        for term in terms.split_whitespace() {
+            let (sign, term) = match term.strip_prefix('-') {
+                Some(rest) => (-1, rest),
+                None => (default_sign, term.strip_prefix('+').unwrap_or(term)),
+            };
+            let split = term
+                .find(|c: char| !c.is_ascii_digit())
+                .with_context(|| format!("Missing unit in shift term '{}'", term))?;
+            let amount: i64 = term[..split]
+                .parse()
+                .with_context(|| format!("Invalid shift term '{}'", term))?;
+            let amount = amount * sign;
+
+            match &term[split..] {
+                "y" => shift.months += (amount * 12) as i32,
+                "m" => shift.months += amount as i32,
+                "w" => shift.duration += Duration::weeks(amount),
+                "d" => shift.duration += Duration::days(amount),
+                "h" => shift.duration += Duration::hours(amount),
+                "min" => shift.duration += Duration::minutes(amount),
+                "s" => shift.duration += Duration::seconds(amount),
+                unit => bail!(
+                    "Unknown unit '{}' in shift '{}' (expected y, m, w, d, h, min or s)",
+                    unit,
+                    value
+                ),
+            }
+        }
+
+        if shift.months == 0 && shift.duration.is_zero() {
+            bail!("Shift '{}' does not move the date", value);
+        }
+        Ok(shift)
+    }
+
+    pub fn apply(&self, date: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let months = Months::new(self.months.unsigned_abs());
+        let shifted = if self.months >= 0 {
+            date.checked_add_months(months)?
+        } else {
+            date.checked_sub_months(months)?
+        };
+        shifted.checked_add_signed(self.duration)
+    }
+}
+
+/// Shifts the dates of one camera, optionally only within a range of (wrong) dates.
+#[derive(Debug, Clone)]
+pub struct ClockSkewRule {
+    pub make: Option<String>,
+    pub model: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub shift: Shift,
+}
+
+#[derive(Debug, Deserialize)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RawRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    make: Option<String>,
+    model: String,
+    from: Option<String>,
+    to: Option<String>,
+    shift: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClockSkewRules {
+    rules: Vec<ClockSkewRule>,
+}
+
+impl ClockSkewRules {
+    /// Loads a TOML file of `[[rule]]` tables (`model`, `shift`, and optionally `make`,
+    /// `from`, `to`).
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read clock skew rules {:?}", path))?;
+        let rules = Self::parse(&content)
+            .with_context(|| format!("Invalid clock skew rules {:?}", path))?;
+        info!("Loaded {} clock skew rule(s) from {:?}", rules.len(), path);
+        Ok(rules)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let file: RulesFile = toml::from_str(content)?;
+        let mut rules = Vec::new();
+        for raw in file.rules {
+            let bound = |value: Option<String>, end: bool| -> Result<Option<DateTime<Utc>>> {
+                value
+                    .map(|v| {
+                        let day = parse_filter_date(&v, end)?;
+                        let time = if end {
+                            day.and_hms_opt(23, 59, 59)
+                        } else {
+                            day.and_hms_opt(0, 0, 0)
+                        };
+                        Ok(time.context("Invalid rule date")?.and_utc())
+                    })
+                    .transpose()
+            };
+            rules.push(ClockSkewRule {
+                from: bound(raw.from, false)?,
+                to: bound(raw.to, true)?,
+                shift: Shift::parse(&raw.shift)?,
+                make: raw.make,
+                model: raw.model,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// First rule for this camera whose range contains `date`.
+    pub fn find(&self, make: &str, model: &str, date: DateTime<Utc>) -> Option<&ClockSkewRule> {
+        self.rules.iter().find(|rule| {
+            rule.model.trim().eq_ignore_ascii_case(model.trim())
+                && rule
+                    .make
+                    .as_ref()
+                    .is_none_or(|m| m.trim().eq_ignore_ascii_case(make.trim()))
+                && rule.from.is_none_or(|from| date >= from)
+                && rule.to.is_none_or(|to| date <= to)
+        })
+    }
+
+    /// Applies the matching rule to a resolved date. Hand-set and fallback dates are left alone.
+    pub fn adjust(&self, path: &Path, resolved: &ResolvedDate) -> Option<SkewAdjustment> {
+        if self.is_empty()
+            || resolved.source == DateSource::Override
+            || resolved.source.is_low_confidence()
+        {
+            return None;
+        }
+
+        let (make, model) = read_camera(path)?;
+        let rule = self.find(&make, &model, resolved.date)?;
+        let corrected = rule.shift.apply(resolved.date)?;
+        debug!(
+            "Clock skew for {:?} ({}): {} -> {}",
+            path.file_name().unwrap_or_default(),
+            model,
+            resolved.date,
+            corrected
+        );
+
+        Some(SkewAdjustment {
+            model,
+            original: resolved.date,
+            corrected: ResolvedDate {
+                date: corrected,
+                source: resolved.source,
+            },
+            shift: rule.shift,
+        })
+    }
+}
+
+/// A date corrected by a clock skew rule.
+#[derive(Debug, Clone)]
+pub struct SkewAdjustment {
+    pub model: String,
+    pub original: DateTime<Utc>,
+    pub corrected: ResolvedDate,
+    pub shift: Shift,
+}
+
+/// Reads the EXIF `Make` and `Model` of a file.
+pub fn read_camera(path: &Path) -> Option<(String, String)> {
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+
+    let text = |tag| {
+        exif.get_field(tag, exif::In::PRIMARY)
+            .and_then(|f| match &f.value {
+                exif::Value::Ascii(values) => values.first().map(|v| {
+                    String::from_utf8_lossy(v)
+                        .trim_end_matches('\0')
+                        .to_string()
+                }),
+                _ => None,
+            })
+    };
+    Some((
+        text(exif::Tag::Make).unwrap_or_default(),
+        text(exif::Tag::Model)?,
+    ))
+}
+
+/// Rewrites the EXIF date fields of `path` with `shift` applied. The dates are patched where
+/// their IFD entries point to; being fixed-width strings, the file size and layout do not
+/// change. The new content replaces the file only once fully written. Returns whether
+/// anything was rewritten.
+pub fn write_shifted_exif_dates(path: &Path, shift: &Shift) -> Result<bool> {
+    let mut content = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let exif = match exif::Reader::new().read_from_container(&mut Cursor::new(&content)) {
+        Ok(exif) => exif,
+        Err(_) => return Ok(false),
+    };
+
+    // The raw TIFF block is stored verbatim in the container
+    let raw = exif.buf();
+    let Some(start) = content.windows(raw.len()).position(|w| w == raw) else {
+        return Ok(false);
+    };
+    if !shift_tiff_dates(&mut content[start..start + raw.len()], shift) {
+        return Ok(false);
+    }
+
+    let partial = PartialFile::new(path);
+    fs::write(partial.path(), &content).with_context(|| format!("Failed to write {:?}", path))?;
+    partial.commit()?;
+    Ok(true)
+}
+
+/// Shifts the date entries of IFD0 and of the Exif IFD it points to, in a TIFF block.
+fn shift_tiff_dates(tiff: &mut [u8], shift: &Shift) -> bool {
+    let big_endian = match tiff.get(0..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return false,
+    };
+    let Some(ifd0) = tiff_u32(tiff, 4, big_endian) else {
+        return false;
+    };
+
+    let mut changed = false;
+    let mut exif_ifd = None;
+    for entry in ifd_entries(tiff, ifd0 as usize, big_endian) {
+        if entry.tag == exif::Tag::ExifIFDPointer.number() {
+            exif_ifd = Some(entry.value);
+        } else {
+            changed |= shift_date_entry(tiff, &entry, shift);
+        }
+    }
+    if let Some(exif_ifd) = exif_ifd {
+        for entry in ifd_entries(tiff, exif_ifd as usize, big_endian) {
+            changed |= shift_date_entry(tiff, &entry, shift);
+        }
+    }
+    changed
+}
+
+struct IfdEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// The value itself when it fits in four bytes, its offset in the block otherwise
+    value: u32,
+}
+
+fn ifd_entries(tiff: &[u8], offset: usize, big_endian: bool) -> Vec<IfdEntry> {
+    let count = tiff_u16(tiff, offset, big_endian).unwrap_or_default();
+    (0..count as usize)
+        .map_while(|i| {
+            let at = offset + 2 + i * 12;
+            Some(IfdEntry {
+                tag: tiff_u16(tiff, at, big_endian)?,
+                kind: tiff_u16(tiff, at + 2, big_endian)?,
+                count: tiff_u32(tiff, at + 4, big_endian)?,
+                value: tiff_u32(tiff, at + 8, big_endian)?,
+            })
+        })
+        .collect()
+}
+
+fn tiff_u16(tiff: &[u8], at: usize, big_endian: bool) -> Option<u16> {
+    let bytes = tiff.get(at..at + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn tiff_u32(tiff: &[u8], at: usize, big_endian: bool) -> Option<u32> {
+    let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Replaces the value of a date entry with the shifted date. Other entries are left alone.
+fn shift_date_entry(tiff: &mut [u8], entry: &IfdEntry, shift: &Shift) -> bool {
+    if entry.kind != EXIF_ASCII
+        || entry.count != EXIF_DATE_LEN + 1
+        || !EXIF_DATE_TAGS.iter().any(|t| t.number() == entry.tag)
+    {
+        return false;
+    }
+    let offset = entry.value as usize;
+    let Some(field) = tiff.get_mut(offset..offset + EXIF_DATE_LEN as usize) else {
+        return false;
+    };
+    let text = String::from_utf8_lossy(field);
+    let Ok(naive) = NaiveDateTime::parse_from_str(&text, "%Y:%m:%d %H:%M:%S") else {
+        return false;
+    };
+    let Some(shifted) = shift.apply(naive.and_utc()) else {
+        return false;
+    };
+    let replacement = shifted.format("%Y:%m:%d %H:%M:%S").to_string();
+    if replacement.len() != field.len() {
+        return false;
+    }
+    field.copy_from_slice(replacement.as_bytes());
+    true
+}
+
+/// One line of the clock skew report.
+#[derive(Debug)]
+pub struct ReportRow {
+    pub source: String,
+    pub dest: String,
+    pub adjustment: SkewAdjustment,
+}
+
+/// Writes the CSV report of every adjusted file at the root of the library.
+pub fn write_report(root: &Path, rows: &[ReportRow]) -> Result<()> {
+    let mut csv = String::from("source,destination,model,original_date,corrected_date\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            csv_field(&row.source),
+            csv_field(&row.dest),
+            csv_field(&row.adjustment.model),
+            row.adjustment.original.to_rfc3339(),
+            row.adjustment.corrected.date.to_rfc3339()
+        ));
+    }
+    let path = root.join(REPORT_FILE);
+    fs::write(&path, csv).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// Quotes a CSV field when it holds a separator, quote or newline.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::date_utils::naive_to_utc;
+    use crate::organizer::metadata::DateExtractor;
+
+    fn write_jpeg_with_exif(path: &Path, model: &str, date: &str) {
+        let model = exif::Field {
+            tag: exif::Tag::Model,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Ascii(vec![model.as_bytes().to_vec()]),
+        };
+        let original = exif::Field {
+            tag: exif::Tag::DateTimeOriginal,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Ascii(vec![date.as_bytes().to_vec()]),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&model);
+        writer.push_field(&original);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        fs::write(path, jpeg).unwrap();
+    }
+
+    #[test]
+    fn test_parse_shift() {
+        let shift = Shift::parse("+3y 2m 4h").unwrap();
+        assert_eq!(shift.months, 38);
+        assert_eq!(shift.duration, Duration::hours(4));
+
+        let shift = Shift::parse("-1d 30min").unwrap();
+        assert_eq!(shift.duration, -Duration::minutes(24 * 60 + 30));
+
+        assert_eq!(
+            Shift::parse("+1y")
+                .unwrap()
+                .apply(naive_to_utc(2000, 2, 29).unwrap()),
+            naive_to_utc(2001, 2, 28)
+        );
+        assert!(Shift::parse("3 years").is_err());
+        assert!(Shift::parse("+0h").is_err());
+    }
+
+    #[test]
+    fn test_rules_match_camera_and_range() {
+        let rules = ClockSkewRules::parse(
+            r#"
+            [[rule]]
+            model = "DSC-W55"
+            from = "2000-01-01"
+            to = "2000-12-31"
+            shift = "+3y 2m 4h"
+            "#,
+        )
+        .unwrap();
+
+        let in_range = naive_to_utc(2000, 1, 1).unwrap();
+        assert!(rules.find("SONY", "DSC-W55", in_range).is_some());
+        assert!(rules.find("SONY", "DSC-W55 ", in_range).is_some());
+        assert!(rules.find("SONY", "DSC-W80", in_range).is_none());
+        assert!(
+            rules
+                .find("SONY", "DSC-W55", naive_to_utc(2004, 1, 1).unwrap())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_adjust_and_rewrite_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("DSC00001.JPG");
+        write_jpeg_with_exif(&path, "DSC-W55", "2000:01:01 00:03:12");
+
+        let rules =
+            ClockSkewRules::parse("[[rule]]\nmodel = \"DSC-W55\"\nshift = \"+3y 2m 4h\"").unwrap();
+        let resolved = ResolvedDate {
+            date: "2000-01-01T00:03:12Z".parse().unwrap(),
+            source: DateSource::Exif,
+        };
+        let adjustment = rules.adjust(&path, &resolved).unwrap();
+        assert_eq!(
+            adjustment.corrected.date.to_rfc3339(),
+            "2003-03-01T04:03:12+00:00"
+        );
+
+        // Dates are corrected where they are resolved
+        let extractor = DateExtractor::new().unwrap().with_clock_skew(rules);
+        let (date, applied) = extractor.resolve(&path, false);
+        assert_eq!(date, Some(adjustment.corrected));
+        assert_eq!(applied.map(|a| a.original), Some(resolved.date));
+
+        let size = fs::metadata(&path).unwrap().len();
+        assert!(write_shifted_exif_dates(&path, &adjustment.shift).unwrap());
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        let content = fs::read(&path).unwrap();
+        assert!(content.windows(19).any(|w| w == b"2003:03:01 04:03:12"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert_eq!(
+            read_camera(&path),
+            Some((String::new(), "DSC-W55".to_string()))
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::catalog::{Catalog, CatalogEntry};
+use super::verify::hash_file;
+
+/// Catalogued files with the same content. `keep` is the copy worth keeping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub keep: String,
+    pub duplicates: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct DedupeReport {
+    pub groups: Vec<DuplicateGroup>,
+    pub removed: usize,
+    pub freed_bytes: u64,
+    /// Folders that lost files, whose gallery pages are stale.
+    pub affected_dirs: BTreeSet<PathBuf>,
+}
+
+/// Finds catalogued files of the library at `root` with identical content. Only files of equal
+/// size are hashed, using the stored checksum when there is one.
+pub fn find_duplicates(root: &Path, catalog: &Catalog) -> Vec<DuplicateGroup> {
+    let mut by_size: BTreeMap<u64, Vec<(&String, &CatalogEntry)>> = BTreeMap::new();
+    for (key, entry) in &catalog.entries {
+        by_size.entry(entry.size).or_default().push((key, entry));
+    }
+
+    let mut groups = Vec::new();
+    for same_size in by_size.into_values().filter(|files| files.len() > 1) {
+        let mut by_hash: BTreeMap<String, Vec<(&String, &CatalogEntry)>> = BTreeMap::new();
+        for (key, entry) in same_size {
+            let hash = entry
+                .sha256
+                .clone()
+                .or_else(|| hash_file(&root.join(key)).ok());
+            match hash {
+                Some(hash) => by_hash.entry(hash).or_default().push((key, entry)),
+                None => warn!("Cannot read {}, skipping it", key),
+            }
+        }
+
+        for mut files in by_hash.into_values().filter(|files| files.len() > 1) {
+            files.sort_by_key(|(key, entry)| (keep_rank(entry), key.len(), (*key).clone()));
+            let mut keys = files.into_iter().map(|(key, _)| key.clone());
+            if let Some(keep) = keys.next() {
+                groups.push(DuplicateGroup {
+                    keep,
+                    duplicates: keys.collect(),
+                });
+            }
+        }
+    }
+    groups
+}
+
+/// Copies with a trustworthy date are kept before fallback-dated and undated ones.
+fn keep_rank(entry: &CatalogEntry) -> u8 {
+    match (&entry.date, entry.date_source) {
+        (None, _) => 2,
+        (Some(_), Some(source)) if source.is_low_confidence() => 1,
+        _ => 0,
+    }
+}
+
+/// Finds duplicates and, with `remove`, deletes every copy but the kept one (with its
+/// thumbnails), folding the owners of removed copies into the kept entry.
+pub fn dedupe(root: &Path, remove: bool) -> Result<DedupeReport> {
+    let mut catalog = Catalog::load_existing(root)?;
+    let mut report = DedupeReport {
+        groups: find_duplicates(root, &catalog),
+        ..Default::default()
+    };
+    if !remove {
+        return Ok(report);
+    }
+
+    // Removed copies with the copy kept instead, for the edited copies linked to them
+    let mut replaced = HashMap::new();
+    for group in &report.groups {
+        let kept = hash_file(&root.join(&group.keep)).ok();
+        for key in &group.duplicates {
+            let path = root.join(key);
+            // Stored checksums may be stale: only delete a copy that still matches
+            if kept.is_none() || hash_file(&path).ok() != kept {
+                warn!("{} no longer matches {}, keeping it", key, group.keep);
+                continue;
+            }
+            if let Err(e) = remove_with_thumbnails(&path) {
+                warn!("Failed to remove {}: {}", key, e);
+                continue;
+            }
+            info!("Removed {} (same as {})", key, group.keep);
+            let Some(removed) = catalog.entries.remove(key) else {
+                continue;
+            };
+            replaced.insert(key.clone(), group.keep.clone());
+            report.removed += 1;
+            report.freed_bytes += removed.size;
+            report
+                .affected_dirs
+                .extend(path.parent().map(Path::to_path_buf));
+            if let Some(kept) = catalog.entries.get_mut(&group.keep) {
+                for owner in removed.owners {
+                    if !kept.owners.contains(&owner) {
+                        kept.owners.push(owner);
+                    }
+                }
+                kept.owners.sort();
+            }
+        }
+    }
+
+    catalog.relink_edits(&replaced);
+    catalog.save(root)?;
+    Ok(report)
+}
+
+fn remove_with_thumbnails(path: &Path) -> Result<()> {
+    fs::remove_file(path)?;
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+        for suffix in ["jpg", "mp4"] {
+            let thumb =
+                dir.join(".thumbnails")
+                    .join(format!("{}.{}", name.to_string_lossy(), suffix));
+            if thumb.exists() {
+                fs::remove_file(thumb)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn log_report(report: &DedupeReport) {
+    for group in &report.groups {
+        info!("{}", group.keep);
+        for key in &group.duplicates {
+            info!("  = {}", key);
+        }
+    }
+    let copies: usize = report.groups.iter().map(|g| g.duplicates.len()).sum();
+    info!(
+        "{} files with duplicates, {} redundant copies, {} removed ({:.1} MB freed)",
+        report.groups.len(),
+        copies,
+        report.removed,
+        report.freed_bytes as f64 / 1_000_000.0
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::model::DateSource;
+
+    #[test]
+    fn test_dedupe_keeps_best_dated_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let mut catalog = Catalog::default();
+        for (key, content, date_source, owner) in [
+            (
+                "2019/July/04/a.jpg",
+                "same",
+                Some(DateSource::Exif),
+                "alice",
+            ),
+            ("unknown/a.jpg", "same", None, "bob"),
+            (
+                "2019/July/04/b.jpg",
+                "diff",
+                Some(DateSource::Exif),
+                "alice",
+            ),
+        ] {
+            let path = root.join(key);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            catalog.insert(
+                key.to_string(),
+                CatalogEntry {
+                    size: 4,
+                    date: date_source.map(|_| "2019-07-04T12:00:00+00:00".to_string()),
+                    date_source,
+                    owners: vec![owner.to_string()],
+                    ..Default::default()
+                },
+            );
+        }
+        // An edited copy linked to the copy about to be removed
+        fs::write(root.join("unknown/a-edited.jpg"), "edited!").unwrap();
+        catalog.insert(
+            "unknown/a-edited.jpg".to_string(),
+            CatalogEntry {
+                size: 7,
+                edit_of: Some("unknown/a.jpg".to_string()),
+                ..Default::default()
+            },
+        );
+        fs::create_dir_all(root.join("unknown/.thumbnails")).unwrap();
+        fs::write(root.join("unknown/.thumbnails/a.jpg.jpg"), "thumb").unwrap();
+        catalog.save(root).unwrap();
+
+        let report = dedupe(root, false).unwrap();
+        assert_eq!(
+            report.groups,
+            vec![DuplicateGroup {
+                keep: "2019/July/04/a.jpg".to_string(),
+                duplicates: vec!["unknown/a.jpg".to_string()],
+            }]
+        );
+        assert!(root.join("unknown/a.jpg").exists());
+
+        // Stale checksums claim all three are the same; only the real copy is removed
+        for entry in catalog.entries.values_mut() {
+            entry.sha256 = Some("0".repeat(64));
+        }
+        catalog.save(root).unwrap();
+        let report = dedupe(root, true).unwrap();
+        assert_eq!(report.removed, 1);
+        assert!(root.join("2019/July/04/b.jpg").exists());
+        assert!(!root.join("unknown/a.jpg").exists());
+        assert!(!root.join("unknown/.thumbnails/a.jpg.jpg").exists());
+        let catalog = Catalog::load(root).unwrap();
+        assert_eq!(
+            catalog.get("2019/July/04/a.jpg").unwrap().owners,
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+        assert!(catalog.get("unknown/a.jpg").is_none());
+        assert_eq!(
+            catalog
+                .get("unknown/a-edited.jpg")
+                .unwrap()
+                .edit_of
+                .as_deref(),
+            Some("2019/July/04/a.jpg")
+        );
+    }
+}
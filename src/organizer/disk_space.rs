@@ -0,0 +1,131 @@
+use anyhow::{Result, bail};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use sysinfo::Disks;
+
+/// Bytes that must fit on the filesystem holding `path`.
+#[derive(Debug)]
+pub struct SpaceRequirement {
+    pub label: &'static str,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Returns the mount point and free space of the filesystem that holds `path`.
+/// `path` does not need to exist yet; its closest existing ancestor is used.
+pub fn filesystem_of(disks: &Disks, path: &Path) -> Option<(PathBuf, u64)> {
+    let mut existing = std::path::absolute(path).ok()?;
+    while !existing.exists() {
+        existing = existing.parent()?.to_path_buf();
+    }
+    let existing = existing.canonicalize().unwrap_or(existing);
+
+    disks
+        .list()
+        .iter()
+        .filter(|d| existing.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().components().count())
+        .map(|d| (d.mount_point().to_path_buf(), d.available_space()))
+}
+
+/// Fails with a readable message when the requirements (summed per filesystem, so a staging
+/// folder on the same disk as the output counts twice) exceed the free space.
+pub fn ensure_space(requirements: &[SpaceRequirement]) -> Result<()> {
+    let disks = Disks::new_with_refreshed_list();
+    let mut per_filesystem: BTreeMap<PathBuf, (u64, u64, Vec<String>)> = BTreeMap::new();
+
+    for req in requirements {
+        let Some((mount, available)) = filesystem_of(&disks, &req.path) else {
+            warn!(
+                "Cannot determine free space for {:?}, skipping check",
+                req.path
+            );
+            continue;
+        };
+        let slot = per_filesystem
+            .entry(mount)
+            .or_insert((0, available, Vec::new()));
+        slot.0 += req.bytes;
+        slot.2.push(format!(
+            "{} {:?} ({})",
+            req.label,
+            req.path,
+            human_size(req.bytes)
+        ));
+    }
+
+    for (mount, (needed, available, labels)) in &per_filesystem {
+        info!(
+            "Filesystem {:?}: {} needed, {} available",
+            mount,
+            human_size(*needed),
+            human_size(*available)
+        );
+        if needed > available {
+            bail!(
+                "Not enough free space on {:?}: {} needed for {} but only {} available. \
+                 Use --staging-dir to extract elsewhere or free some space.",
+                mount,
+                human_size(*needed),
+                labels.join(" + "),
+                human_size(*available)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn test_ensure_space_rejects_impossible_requirement() {
+        let dir = tempfile::tempdir().unwrap();
+        let disks = Disks::new_with_refreshed_list();
+        if filesystem_of(&disks, dir.path()).is_none() {
+            // Sandboxes without a visible mount table cannot run this check
+            return;
+        }
+
+        let result = ensure_space(&[SpaceRequirement {
+            label: "output",
+            path: dir.path().join("not/created/yet"),
+            bytes: u64::MAX / 2,
+        }]);
+        assert!(result.is_err());
+
+        assert!(
+            ensure_space(&[SpaceRequirement {
+                label: "output",
+                path: dir.path().to_path_buf(),
+                bytes: 1,
+            }])
+            .is_ok()
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Suffixes Google Photos appends to the edited copy of a photo, per export language.
+const EDITED_SUFFIXES: [&str; 16] = [
+    "edited",
+    "editado",
+    "bearbeitet",
+    "modifié",
+    "modificato",
+    "bewerkt",
+    "editat",
+    "edytowane",
+    "redigeret",
+    "redigert",
+    "redigerad",
+    "muokattu",
+    "upravené",
+    "szerkesztett",
+    "изменено",
+    "編集済み",
+];
+
+/// What to do with a photo exported both as `IMG_1.jpg` and `IMG_1-edited.jpg`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditPolicy {
+    /// Organize both and link the edited copy to its original
+    #[default]
+    Both,
+    /// Organize only the edited copy when there is one
+    Edited,
+    /// Organize only the original
+    Original,
+}
+
+impl FromStr for EditPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "both" => Ok(EditPolicy::Both),
+            "edited" => Ok(EditPolicy::Edited),
+            "original" => Ok(EditPolicy::Original),
+            _ => Err(format!(
+                "invalid edit policy '{}' (expected both, edited or original)",
+                s
+            )),
+        }
+    }
+}
+
+impl EditPolicy {
+    /// Whether `path` is organized under this policy, given the files next to it.
+    pub fn keeps(self, path: &Path) -> bool {
+        match self {
+            EditPolicy::Both => true,
+            EditPolicy::Edited => edited_variant(path).is_none(),
+            EditPolicy::Original => original_of(path).is_none(),
+        }
+    }
+}
+
+/// Splits the `(1)` Google appends to repeated names off a file stem.
+fn split_counter(stem: &str) -> (&str, &str) {
+    if let Some(open) = stem.rfind('(')
+        && let Some(digits) = stem[open + 1..].strip_suffix(')')
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+    {
+        stem.split_at(open)
+    } else {
+        (stem, "")
+    }
+}
+
+/// Name the original of an edited copy would have (`IMG_1-edited.jpg` -> `IMG_1.jpg`,
+/// `IMG_1-edited(1).jpg` -> `IMG_1(1).jpg`), whether or not it exists.
+pub fn unedited_name(path: &Path) -> Option<PathBuf> {
+    let (stem, counter) = split_counter(path.file_stem()?.to_str()?);
+    let (base, suffix) = stem.rsplit_once('-')?;
+    if base.is_empty()
+        || !EDITED_SUFFIXES
+            .iter()
+            .any(|s| s.to_lowercase() == suffix.to_lowercase())
+    {
+        return None;
+    }
+
+    let mut name = format!("{}{}", base, counter);
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    Some(path.with_file_name(name))
+}
+
+/// The original next to an edited copy, if both were exported.
+pub fn original_of(path: &Path) -> Option<PathBuf> {
+    unedited_name(path).filter(|original| original.is_file())
+}
+
+/// The edited copy next to an original, if both were exported.
+pub fn edited_variant(path: &Path) -> Option<PathBuf> {
+    let (stem, counter) = split_counter(path.file_stem()?.to_str()?);
+    let ext = path.extension().map(|e| e.to_string_lossy());
+    EDITED_SUFFIXES.iter().find_map(|suffix| {
+        let name = match &ext {
+            Some(ext) => format!("{}-{}{}.{}", stem, suffix, counter, ext),
+            None => format!("{}-{}{}", stem, suffix, counter),
+        };
+        let candidate = path.with_file_name(name);
+        candidate.is_file().then_some(candidate)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_unedited_name() {
+        assert_eq!(
+            unedited_name(Path::new("Trip/IMG_1-edited.jpg")),
+            Some(PathBuf::from("Trip/IMG_1.jpg"))
+        );
+        assert_eq!(
+            unedited_name(Path::new("IMG_1-Bearbeitet.JPG")),
+            Some(PathBuf::from("IMG_1.JPG"))
+        );
+        assert_eq!(
+            unedited_name(Path::new("IMG_1-edited(1).jpg")),
+            Some(PathBuf::from("IMG_1(1).jpg"))
+        );
+        assert_eq!(unedited_name(Path::new("IMG_1(1).jpg")), None);
+        assert_eq!(unedited_name(Path::new("2019-07-04.jpg")), None);
+        assert_eq!(unedited_name(Path::new("-edited.jpg")), None);
+    }
+
+    #[test]
+    fn test_edit_policy_keeps() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("IMG_1.jpg");
+        let edited = dir.path().join("IMG_1-editado.jpg");
+        let alone = dir.path().join("IMG_2-edited.jpg");
+        for path in [&original, &edited, &alone] {
+            fs::write(path, "pixels").unwrap();
+        }
+
+        assert_eq!(edited_variant(&original), Some(edited.clone()));
+        assert_eq!(original_of(&edited), Some(original.clone()));
+
+        let repeated = dir.path().join("IMG_1(1).jpg");
+        let repeated_edit = dir.path().join("IMG_1-edited(1).jpg");
+        fs::write(&repeated, "pixels").unwrap();
+        fs::write(&repeated_edit, "pixels").unwrap();
+        assert_eq!(edited_variant(&repeated), Some(repeated_edit.clone()));
+        assert_eq!(original_of(&repeated_edit), Some(repeated));
+
+        assert!(EditPolicy::Both.keeps(&original) && EditPolicy::Both.keeps(&edited));
+        assert!(!EditPolicy::Edited.keeps(&original) && EditPolicy::Edited.keeps(&edited));
+        assert!(EditPolicy::Original.keeps(&original) && !EditPolicy::Original.keeps(&edited));
+        // An edited copy without its original is always kept
+        assert!(EditPolicy::Original.keeps(&alone));
+    }
+}
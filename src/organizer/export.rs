@@ -0,0 +1,130 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::catalog::Catalog;
+use super::clock_skew::csv_field;
+use super::model::DateSource;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!(
+                "invalid export format '{}' (expected csv or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// One library file as exported: where it is, where it came from and how it was dated.
+#[derive(Debug, Serialize)]
+struct ExportRow<'a> {
+    path: &'a str,
+    source: &'a str,
+    date: Option<&'a str>,
+    date_source: Option<DateSource>,
+    size: u64,
+    sha256: Option<&'a str>,
+    owners: &'a [String],
+}
+
+/// Writes the catalog of the library at `root` to `out`. Returns the number of files written.
+pub fn export_catalog(root: &Path, format: ExportFormat, out: &mut impl Write) -> Result<usize> {
+    let catalog = Catalog::load_existing(root)?;
+    let rows: Vec<ExportRow> = catalog
+        .entries
+        .iter()
+        .map(|(key, entry)| ExportRow {
+            path: key,
+            source: &entry.source,
+            date: entry.date.as_deref(),
+            date_source: entry.date_source,
+            size: entry.size,
+            sha256: entry.sha256.as_deref(),
+            owners: &entry.owners,
+        })
+        .collect();
+
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &rows)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "path,source,date,date_source,size,sha256,owners")?;
+            for row in &rows {
+                let date_source = row
+                    .date_source
+                    .and_then(|s| serde_json::to_value(s).ok())
+                    .and_then(|v| v.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(row.path),
+                    csv_field(row.source),
+                    row.date.unwrap_or_default(),
+                    date_source,
+                    row.size,
+                    row.sha256.unwrap_or_default(),
+                    csv_field(&row.owners.join(";"))
+                )?;
+            }
+        }
+    }
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CatalogEntry;
+
+    #[test]
+    fn test_export_catalog_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2019/July/04/a, b.jpg".to_string(),
+            CatalogEntry {
+                size: 10,
+                source: "Takeout/a, b.jpg".to_string(),
+                date: Some("2019-07-04T12:00:00+00:00".to_string()),
+                date_source: Some(DateSource::FolderHint),
+                owners: vec!["alice".to_string(), "bob".to_string()],
+                ..Default::default()
+            },
+        );
+        catalog.save(dir.path()).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(
+            export_catalog(dir.path(), ExportFormat::Csv, &mut out).unwrap(),
+            1
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "path,source,date,date_source,size,sha256,owners\n\
+             \"2019/July/04/a, b.jpg\",\"Takeout/a, b.jpg\",2019-07-04T12:00:00+00:00,folder-hint,10,,alice;bob\n"
+        );
+
+        let mut out = Vec::new();
+        export_catalog(dir.path(), ExportFormat::Json, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["date_source"], "folder-hint");
+    }
+}
@@ -0,0 +1,292 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::sniff::{self, ContentType};
+
+/// Which kind of media to organize.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MediaFilter {
+    #[default]
+    All,
+    Images,
+    Videos,
+}
+
+impl FromStr for MediaFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(MediaFilter::All),
+            "images" | "photos" => Ok(MediaFilter::Images),
+            "videos" => Ok(MediaFilter::Videos),
+            _ => Err(format!(
+                "invalid media type '{}' (expected all, images or videos)",
+                s
+            )),
+        }
+    }
+}
+
+/// Why a file was left out of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exclusion {
+    Path,
+    MediaType,
+    DateRange,
+}
+
+/// Per-reason counters of excluded files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct ExclusionCounts {
+    pub path: usize,
+    pub media_type: usize,
+    pub date_range: usize,
+}
+
+impl ExclusionCounts {
+    pub fn add(&mut self, reason: Exclusion) {
+        match reason {
+            Exclusion::Path => self.path += 1,
+            Exclusion::MediaType => self.media_type += 1,
+            Exclusion::DateRange => self.date_range += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.path + self.media_type + self.date_range
+    }
+}
+
+/// Include/exclude rules applied during the organize walk, before anything is copied.
+#[derive(Debug, Default)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    media: MediaFilter,
+    date_from: Option<NaiveDate>,
+    date_to: Option<NaiveDate>,
+}
+
+impl FileFilter {
+    /// Globs match the path relative to the Takeout root, case-insensitively
+    /// (e.g. `*.gif` or `**/Hangout:*/**`).
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        media: MediaFilter,
+        date_from: Option<NaiveDate>,
+        date_to: Option<NaiveDate>,
+    ) -> Result<Self> {
+        if let (Some(from), Some(to)) = (date_from, date_to)
+            && from > to
+        {
+            bail!("Invalid date range: {} is after {}", from, to);
+        }
+
+        Ok(Self {
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
+            media,
+            date_from,
+            date_to,
+        })
+    }
+
+    pub fn has_date_range(&self) -> bool {
+        self.date_from.is_some() || self.date_to.is_some()
+    }
+
+    /// Checks the rules that do not need a resolved date, given the file's sniffed `content`.
+    pub fn check_file(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        content: Option<ContentType>,
+    ) -> Option<Exclusion> {
+        if let Some(include) = &self.include
+            && !include.is_match(relative_path)
+        {
+            return Some(Exclusion::Path);
+        }
+        if let Some(exclude) = &self.exclude
+            && exclude.is_match(relative_path)
+        {
+            return Some(Exclusion::Path);
+        }
+
+        match self.media {
+            MediaFilter::All => None,
+            MediaFilter::Images if sniff::is_video(path, content) => Some(Exclusion::MediaType),
+            MediaFilter::Videos if !sniff::is_video(path, content) => Some(Exclusion::MediaType),
+            _ => None,
+        }
+    }
+
+    /// Checks the resolved date against the date range. Undated files fall outside any range.
+    pub fn check_date(&self, date: Option<DateTime<Utc>>) -> Option<Exclusion> {
+        if !self.has_date_range() {
+            return None;
+        }
+
+        let Some(day) = date.map(|d| d.date_naive()) else {
+            return Some(Exclusion::DateRange);
+        };
+        let after_start = self.date_from.is_none_or(|from| day >= from);
+        let before_end = self.date_to.is_none_or(|to| day <= to);
+
+        if after_start && before_end {
+            None
+        } else {
+            Some(Exclusion::DateRange)
+        }
+    }
+}
+
+/// Parses `YYYY`, `YYYY-MM` or `YYYY-MM-DD`. A partial date is widened to the start
+/// (or, with `end_of_period`, the end) of that year or month.
+pub fn parse_filter_date(value: &str, end_of_period: bool) -> Result<NaiveDate> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let parse = |s: &str| {
+        s.parse::<u32>()
+            .with_context(|| format!("Invalid date '{}'", value))
+    };
+
+    let year = parse(parts[0])? as i32;
+    let (month, day) = match parts.len() {
+        1 => (if end_of_period { 12 } else { 1 }, None),
+        2 => (parse(parts[1])?, None),
+        3 => (parse(parts[1])?, Some(parse(parts[2])?)),
+        _ => bail!(
+            "Invalid date '{}' (expected YYYY, YYYY-MM or YYYY-MM-DD)",
+            value
+        ),
+    };
+
+    let day = match day {
+        Some(day) => day,
+        None if end_of_period => {
+            last_day_of_month(year, month).with_context(|| format!("Invalid date '{}'", value))?
+        }
+        None => 1,
+    };
+
+    NaiveDate::from_ymd_opt(year, month, day).with_context(|| format!("Invalid date '{}'", value))
+}
+
+fn last_day_of_month(year: i32, month: u32) -> Option<u32> {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)?
+        .pred_opt()
+        .map(|d| d.day())
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("Invalid glob pattern '{}'", pattern))?;
+        builder.add(glob);
+    }
+    Ok(Some(builder.build()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::date_utils::naive_to_utc;
+
+    #[test]
+    fn test_path_globs() {
+        let filter = FileFilter::new(
+            &[],
+            &["*.gif".to_string(), "**/Hangout:*/**".to_string()],
+            MediaFilter::All,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let check = |p: &str| filter.check_file(Path::new(p), Path::new(p), None);
+        assert_eq!(
+            check("Takeout/Google Photos/anim.GIF"),
+            Some(Exclusion::Path)
+        );
+        assert_eq!(
+            check("Takeout/Google Photos/Hangout: Bob/img.jpg"),
+            Some(Exclusion::Path)
+        );
+        assert_eq!(
+            check("Takeout/Google Photos/Photos from 2015/img.jpg"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_include_and_media_type() {
+        let filter = FileFilter::new(
+            &["**/Photos from */**".to_string()],
+            &[],
+            MediaFilter::Videos,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let check = |p: &str| filter.check_file(Path::new(p), Path::new(p), None);
+        assert_eq!(check("Takeout/Album/clip.mp4"), Some(Exclusion::Path));
+        assert_eq!(
+            check("Takeout/Photos from 2015/img.jpg"),
+            Some(Exclusion::MediaType)
+        );
+        assert_eq!(check("Takeout/Photos from 2015/clip.mp4"), None);
+    }
+
+    #[test]
+    fn test_date_range() {
+        let filter = FileFilter::new(
+            &[],
+            &[],
+            MediaFilter::All,
+            Some(parse_filter_date("2015", false).unwrap()),
+            Some(parse_filter_date("2018", true).unwrap()),
+        )
+        .unwrap();
+
+        assert_eq!(filter.check_date(naive_to_utc(2015, 1, 1)), None);
+        assert_eq!(filter.check_date(naive_to_utc(2018, 12, 31)), None);
+        assert_eq!(
+            filter.check_date(naive_to_utc(2019, 1, 1)),
+            Some(Exclusion::DateRange)
+        );
+        assert_eq!(filter.check_date(None), Some(Exclusion::DateRange));
+    }
+
+    #[test]
+    fn test_parse_filter_date() {
+        assert_eq!(
+            parse_filter_date("2016-02", true).unwrap(),
+            NaiveDate::from_ymd_opt(2016, 2, 29).unwrap()
+        );
+        assert_eq!(
+            parse_filter_date("2016-02-10", true).unwrap(),
+            NaiveDate::from_ymd_opt(2016, 2, 10).unwrap()
+        );
+        assert!(parse_filter_date("2016-13", false).is_err());
+        assert!(parse_filter_date("soon", false).is_err());
+    }
+}
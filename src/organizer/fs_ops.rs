@@ -1,20 +1,51 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use chrono::{DateTime, Datelike, Utc};
 use log::{debug, info, warn};
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, PartialEq)]
+use super::OrganizeOptions;
+use super::cancel::PartialFile;
+use super::catalog::catalog_key;
+use super::metadata;
+use super::model::ResolvedDate;
+use super::name_template::NameContext;
+use super::sniff::{self, ContentType};
+use super::target_fs::TargetFs;
+use super::titles::{self, NameRegistry};
+use super::verify::hash_file;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FileAction {
     New,
     Updated,
     Skipped,
 }
 
-pub fn should_process_file(path: &Path) -> bool {
+#[derive(Debug)]
+pub struct ProcessedFile {
+    pub action: FileAction,
+    pub dest_path: PathBuf,
+    /// SHA-256 of the copied file, only computed when copies are verified.
+    pub checksum: Option<String>,
+}
+
+/// What an input file is, from a single look at its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// A photo or video, with its sniffed content when the format was recognised
+    Media(Option<ContentType>),
+    /// Takeout JSON metadata
+    Sidecar,
+    /// Neither media nor metadata (HTML indexes, notes, OS junk)
+    NonMedia,
+}
+
+/// Classifies a file, sniffing its content once. `None` for directories.
+pub fn file_kind(path: &Path) -> Option<FileKind> {
     if path.is_dir() {
-        return false;
+        return None;
     }
 
     let ext = path
@@ -23,140 +54,358 @@ pub fn should_process_file(path: &Path) -> bool {
         .map(|s| s.to_lowercase())
         .unwrap_or_default();
 
-    if ext == "json" || ext.is_empty() {
-        return false;
+    if ext == "json" {
+        return Some(FileKind::Sidecar);
+    }
+    if ext.is_empty() {
+        return Some(FileKind::NonMedia);
     }
 
-    true
+    // Trust the content over the extension; only formats the sniffer has no signature for
+    // (camera RAW, MPEG streams) are taken by extension
+    let content = sniff::sniff(path);
+    let is_media = match content {
+        Some(content) => content.is_media(),
+        None => sniff::is_unsniffed_media(path),
+    };
+    Some(if is_media {
+        FileKind::Media(content)
+    } else {
+        FileKind::NonMedia
+    })
 }
 
-pub fn is_archive(path: &Path) -> bool {
-    let filename = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
-    filename.ends_with(".zip") || filename.ends_with(".tar.gz") || filename.ends_with(".tgz")
+pub fn should_process_file(path: &Path) -> bool {
+    matches!(file_kind(path), Some(FileKind::Media(_)))
 }
 
-pub fn extract_archive(archive_path: &Path, extract_to: &Path) -> Result<()> {
-    let filename = archive_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("");
-
-    if filename.ends_with(".zip") {
-        info!("Extracting ZIP archive: {:?}", archive_path);
-        let file = fs::File::open(archive_path)?;
-        let mut archive = zip::ZipArchive::new(file)?;
-
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-            let outpath = match file.enclosed_name() {
-                Some(path) => extract_to.join(path),
-                None => continue,
-            };
-
-            if (*file.name()).ends_with('/') {
-                fs::create_dir_all(&outpath)?;
-            } else {
-                if let Some(p) = outpath.parent()
-                    && !p.exists()
-                {
-                    fs::create_dir_all(p)?;
-                }
-                let mut outfile = fs::File::create(&outpath)?;
-                io::copy(&mut file, &mut outfile)?;
-            }
-        }
-    } else if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
-        info!("Extracting TAR.GZ archive: {:?}", archive_path);
-        let tar_gz = fs::File::open(archive_path)?;
-        let tar = flate2::read::GzDecoder::new(tar_gz);
-        let mut archive = tar::Archive::new(tar);
-        archive.unpack(extract_to)?;
-    }
+/// Copies a media file into `YYYY/Month/DD`. Year-only dates go to `YYYY/<unknown_dir>`;
+/// undated files go to `<unknown_dir>`, optionally below their path relative to the Takeout root.
+/// With `names`, the file is named after the rename template or its sidecar title, made valid
+/// for the target filesystem and unique in its folder.
+pub fn process_file(
+    input_path: &Path,
+    relative_path: &Path,
+    content: Option<ContentType>,
+    output_path: &Path,
+    date: Option<ResolvedDate>,
+    options: &OrganizeOptions,
+    names: Option<&NameRegistry>,
+) -> Result<ProcessedFile> {
+    let (dest_folder, filename) = destination(
+        input_path,
+        relative_path,
+        content,
+        output_path,
+        date,
+        options,
+        names,
+    )?;
+    place_file(input_path, &dest_folder, &filename, options.verify_copies)
+}
 
-    Ok(())
+/// Folder and name [`process_file`] places a file under, claiming the name in `names`.
+pub fn destination(
+    input_path: &Path,
+    relative_path: &Path,
+    content: Option<ContentType>,
+    output_path: &Path,
+    date: Option<ResolvedDate>,
+    options: &OrganizeOptions,
+    names: Option<&NameRegistry>,
+) -> Result<(PathBuf, OsString)> {
+    let dest_folder = destination_folder(input_path, relative_path, output_path, date, options);
+    let filename = input_path
+        .file_name()
+        .with_context(|| format!("Input path has no file name: {:?}", input_path))?;
+    let filename = repaired_file_name(input_path, filename, content, options);
+    let filename = output_file_name(
+        input_path,
+        &filename,
+        relative_path,
+        &dest_folder,
+        date,
+        options,
+        names,
+    );
+    Ok((dest_folder, filename))
 }
 
-pub fn process_file(
+/// Folder a file with `date` belongs in under the current layout settings.
+pub fn destination_folder(
     input_path: &Path,
+    relative_path: &Path,
     output_path: &Path,
-    date: Option<DateTime<Utc>>,
-    unknown_dir: &str,
-) -> Result<FileAction> {
-    let dest_folder = match date {
-        Some(date) => {
-            let month_name = match date.month() {
-                1 => "January",
-                2 => "February",
-                3 => "March",
-                4 => "April",
-                5 => "May",
-                6 => "June",
-                7 => "July",
-                8 => "August",
-                9 => "September",
-                10 => "October",
-                11 => "November",
-                12 => "December",
-                _ => "Unknown",
-            };
-            output_path.join(format!("{}/{}/{:02}", date.year(), month_name, date.day()))
+    date: Option<ResolvedDate>,
+    options: &OrganizeOptions,
+) -> PathBuf {
+    let unknown_dir = options.unknown_dir.as_str();
+    match date {
+        Some(ResolvedDate { date, source }) if source.is_year_only() => {
+            output_path.join(date.year().to_string()).join(unknown_dir)
         }
+        Some(ResolvedDate { date, .. }) => dated_folder(output_path, date),
         None => {
             warn!(
                 "Date unknown for file: {:?}",
                 input_path.file_name().unwrap_or_default()
             );
-            output_path.join(unknown_dir)
+            let dest = output_path.join(unknown_dir);
+            if options.preserve_unknown_paths {
+                let parent = relative_path.parent().unwrap_or(Path::new(""));
+                dest.join(options.target_fs.sanitize_relative(parent))
+            } else {
+                dest
+            }
         }
+    }
+}
+
+/// `filename` with its extension repaired to match the sniffed `content`, when enabled.
+pub fn repaired_file_name(
+    input_path: &Path,
+    filename: &OsStr,
+    content: Option<ContentType>,
+    options: &OrganizeOptions,
+) -> OsString {
+    if options.fix_extensions
+        && let Some(corrected) = sniff::corrected_file_name(input_path, content)
+    {
+        debug!("Repairing extension: {:?} -> {}", filename, corrected);
+        let stem = Path::new(filename).file_stem().unwrap_or_default();
+        let ext = Path::new(&corrected).extension().unwrap_or_default();
+        return Path::new(stem).with_extension(ext).into_os_string();
+    }
+    filename.to_os_string()
+}
+
+/// Name a file gets in `dest_folder` under the current naming settings, starting from
+/// `filename` (its Takeout name, extension already repaired).
+pub fn output_file_name(
+    input_path: &Path,
+    filename: &OsStr,
+    relative_path: &Path,
+    dest_folder: &Path,
+    date: Option<ResolvedDate>,
+    options: &OrganizeOptions,
+    names: Option<&NameRegistry>,
+) -> OsString {
+    let filename = filename.to_os_string();
+
+    // Renamed files claim their name per source, so re-runs find the same name and the
+    // skip/update logic keeps working
+    let Some(names) = names else {
+        return filename;
     };
+    let target = options.target_fs;
+    let folder = names.library_folder(dest_folder);
+    let source = catalog_key(Path::new(""), relative_path).unwrap_or_default();
+    let current = filename.to_string_lossy().to_string();
+    match &options.rename_template {
+        Some(template) => {
+            let context = NameContext::read(input_path, &current, date);
+            names.claim_with(dest_folder, &source, input_path, |n| {
+                target.sanitize_file_name(&template.candidate(&context, n), folder, 0)
+            })
+        }
+        None => {
+            let extension = Path::new(&current)
+                .extension()
+                .map(|e| e.to_string_lossy().to_string());
+            // Room for a " (n)" suffix on clashes
+            let wanted = options
+                .restore_titles
+                .then(|| metadata::sidecar_title(input_path))
+                .flatten()
+                .and_then(|title| {
+                    titles::sanitize_title(&title, extension.as_deref(), target, folder)
+                })
+                .unwrap_or_else(|| target.sanitize_file_name(&current, folder, 8));
+            names.claim(dest_folder, &wanted, &source, input_path)
+        }
+    }
+}
 
-    fs::create_dir_all(&dest_folder).context("Failed to create destination folder")?;
+/// Returns `<output>/YYYY/MonthName/DD` for `date`.
+pub fn dated_folder(output_path: &Path, date: DateTime<Utc>) -> PathBuf {
+    let month_name = match date.month() {
+        1 => "January",
+        2 => "February",
+        3 => "March",
+        4 => "April",
+        5 => "May",
+        6 => "June",
+        7 => "July",
+        8 => "August",
+        9 => "September",
+        10 => "October",
+        11 => "November",
+        12 => "December",
+        _ => "Unknown",
+    };
+    output_path.join(format!("{}/{}/{:02}", date.year(), month_name, date.day()))
+}
 
-    if let Some(filename) = input_path.file_name() {
-        let dest_path = dest_folder.join(filename);
+/// Moves an organized file to `dest_path`, taking its gallery thumbnail and proxy along.
+pub fn relocate_file(src_path: &Path, dest_path: &Path) -> Result<()> {
+    if dest_path.exists() {
+        bail!("Cannot move {:?}: {:?} already exists", src_path, dest_path);
+    }
+    let (Some(src_dir), Some(dest_dir), Some(filename)) =
+        (src_path.parent(), dest_path.parent(), src_path.file_name())
+    else {
+        bail!("Invalid paths for move: {:?} -> {:?}", src_path, dest_path);
+    };
 
-        return if dest_path.exists() {
-            let input_metadata = fs::metadata(input_path)?;
-            let dest_metadata = fs::metadata(&dest_path)?;
+    fs::create_dir_all(dest_dir).context("Failed to create destination folder")?;
+    fs::rename(src_path, dest_path)
+        .with_context(|| format!("Failed to move {:?} to {:?}", src_path, dest_path))?;
 
-            if input_metadata.len() != dest_metadata.len() {
-                info!("Updating file (size changed): {:?}", filename);
-                fs::copy(input_path, &dest_path).with_context(|| {
-                    format!("Failed to copy file {:?} to {:?}", input_path, dest_path)
-                })?;
-                Ok(FileAction::Updated)
-            } else {
-                debug!(
-                    "Skipping file (already exists and same size): {:?}",
-                    filename
-                );
-                Ok(FileAction::Skipped)
+    let dest_name = dest_path.file_name().unwrap_or(filename);
+    for suffix in ["jpg", "mp4"] {
+        let thumb =
+            src_dir
+                .join(".thumbnails")
+                .join(format!("{}.{}", filename.to_string_lossy(), suffix));
+        if thumb.exists() {
+            let thumb_dir = dest_dir.join(".thumbnails");
+            let moved = fs::create_dir_all(&thumb_dir).and_then(|_| {
+                fs::rename(
+                    &thumb,
+                    thumb_dir.join(format!("{}.{}", dest_name.to_string_lossy(), suffix)),
+                )
+            });
+            if let Err(e) = moved {
+                warn!("Failed to move thumbnail {:?}: {}", thumb, e);
             }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies a non-media file to `<output>/<non_media_dir>/<path relative to the Takeout root>`.
+/// With `names`, names that sanitising makes clash get a ` (n)` suffix instead of overwriting
+/// each other.
+pub fn process_non_media_file(
+    input_path: &Path,
+    relative_path: &Path,
+    output_path: &Path,
+    non_media_dir: &str,
+    verify_copies: bool,
+    target_fs: TargetFs,
+    names: Option<&NameRegistry>,
+) -> Result<ProcessedFile> {
+    let dest_folder = output_path
+        .join(non_media_dir)
+        .join(target_fs.sanitize_relative(relative_path.parent().unwrap_or(Path::new(""))));
+    let filename = input_path
+        .file_name()
+        .with_context(|| format!("Input path has no file name: {:?}", input_path))?
+        .to_string_lossy();
+    let folder = dest_folder
+        .strip_prefix(output_path)
+        .unwrap_or(&dest_folder);
+    let filename: OsString = match names {
+        Some(names) => {
+            let source = catalog_key(Path::new(""), relative_path).unwrap_or_default();
+            // Room for a " (n)" suffix on clashes
+            let wanted = target_fs.sanitize_file_name(&filename, folder, 8);
+            names.claim(&dest_folder, &wanted, &source, input_path)
+        }
+        None => target_fs.sanitize_file_name(&filename, folder, 0).into(),
+    };
+
+    place_file(input_path, &dest_folder, &filename, verify_copies)
+}
+
+/// Copies `input_path` into `dest_folder` as `filename`, skipping it when an identical-size
+/// copy is already there.
+fn place_file(
+    input_path: &Path,
+    dest_folder: &Path,
+    filename: &OsStr,
+    verify_copies: bool,
+) -> Result<ProcessedFile> {
+    fs::create_dir_all(dest_folder).context("Failed to create destination folder")?;
+
+    let dest_path = dest_folder.join(filename);
+
+    if dest_path.exists() {
+        let input_metadata = fs::metadata(input_path)?;
+        let dest_metadata = fs::metadata(&dest_path)?;
+
+        if input_metadata.len() != dest_metadata.len() {
+            info!("Updating file (size changed): {:?}", filename);
+            let checksum = copy_file(input_path, &dest_path, verify_copies)?;
+            Ok(ProcessedFile {
+                action: FileAction::Updated,
+                dest_path,
+                checksum,
+            })
         } else {
-            fs::copy(input_path, &dest_path).with_context(|| {
-                format!("Failed to copy file {:?} to {:?}", input_path, dest_path)
-            })?;
             debug!(
-                "Copied {:?} -> {:?}",
-                input_path.file_name().unwrap(),
-                dest_folder
+                "Skipping file (already exists and same size): {:?}",
+                filename
             );
-            Ok(FileAction::New)
-        };
+            Ok(ProcessedFile {
+                action: FileAction::Skipped,
+                dest_path,
+                checksum: None,
+            })
+        }
+    } else {
+        let checksum = copy_file(input_path, &dest_path, verify_copies)?;
+        debug!("Copied {:?} -> {:?}", filename, dest_folder);
+        Ok(ProcessedFile {
+            action: FileAction::New,
+            dest_path,
+            checksum,
+        })
+    }
+}
+
+/// Copies `input_path` to `dest_path`. When `verify` is set, both files are re-read and
+/// hashed afterwards; a mismatch removes the bad copy and returns an error.
+fn copy_file(input_path: &Path, dest_path: &Path, verify: bool) -> Result<Option<String>> {
+    // Written aside and renamed into place, so an interrupted copy never looks complete
+    let partial = PartialFile::new(dest_path);
+    fs::copy(input_path, partial.path())
+        .with_context(|| format!("Failed to copy file {:?} to {:?}", input_path, dest_path))?;
+
+    if !verify {
+        partial.commit()?;
+        return Ok(None);
+    }
+
+    let source_hash = hash_file(input_path)?;
+    let dest_hash = hash_file(partial.path())?;
+    if source_hash != dest_hash {
+        bail!(
+            "Checksum mismatch after copying {:?} to {:?}",
+            input_path,
+            dest_path
+        );
     }
 
-    Ok(FileAction::Skipped)
+    partial.commit()?;
+    Ok(Some(dest_hash))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::organizer::date_utils::naive_to_utc;
+    use crate::organizer::model::DateSource;
 
     #[test]
     fn test_should_process_file() {
-        assert!(should_process_file(Path::new("photo.jpg")));
-        assert!(should_process_file(Path::new("video.mp4")));
-        assert!(should_process_file(Path::new("IMAGE.PNG")));
+        // Formats without a content signature are taken by extension
+        assert!(should_process_file(Path::new("IMG_1.NEF")));
+        assert!(should_process_file(Path::new("clip.MTS")));
+        // Others need their content to match
+        assert!(!should_process_file(Path::new("photo.jpg")));
+        assert!(!should_process_file(Path::new("video.mp4")));
 
         // Should reject
         assert!(!should_process_file(Path::new("metadata.json")));
@@ -165,63 +414,207 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_archive_zip() {
-        use std::io::Write;
+    fn test_should_process_file_sniffs_content() {
         let temp_dir = tempfile::tempdir().unwrap();
-        let archive_path = temp_dir.path().join("test.zip");
-        let extract_to = temp_dir.path().join("extracted_zip");
-
-        // Create a dummy zip file
-        let file = std::fs::File::create(&archive_path).unwrap();
-        let mut zip = zip::ZipWriter::new(file);
-        let options = zip::write::SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Stored);
-        zip.start_file("test.txt", options).unwrap();
-        zip.write_all(b"hello zip world").unwrap();
-        zip.finish().unwrap();
-
-        // Extract it
-        extract_archive(&archive_path, &extract_to).unwrap();
-
-        // Verify
-        let extracted_file = extract_to.join("test.txt");
-        assert!(extracted_file.exists());
+
+        let browser = temp_dir.path().join("archive_browser.html");
+        std::fs::write(&browser, "<!DOCTYPE html><html></html>").unwrap();
+        assert!(!should_process_file(&browser));
+        assert_eq!(file_kind(&browser), Some(FileKind::NonMedia));
+
+        let disguised = temp_dir.path().join("page.jpg");
+        std::fs::write(&disguised, "<html><body>not a photo</body></html>").unwrap();
+        assert!(!should_process_file(&disguised));
+
+        let text_as_jpg = temp_dir.path().join("scan.jpg");
+        std::fs::write(&text_as_jpg, "just some text").unwrap();
+        assert_eq!(file_kind(&text_as_jpg), Some(FileKind::NonMedia));
+
+        let notes = temp_dir.path().join("notes.txt");
+        std::fs::write(&notes, "hello").unwrap();
+        assert!(!should_process_file(&notes));
+        assert_eq!(file_kind(&notes), Some(FileKind::NonMedia));
+
+        let sidecar = temp_dir.path().join("photo.jpg.json");
+        std::fs::write(&sidecar, "{}").unwrap();
+        assert_eq!(file_kind(&sidecar), Some(FileKind::Sidecar));
+
+        // The sniffed content travels with the classification
+        let png_as_jpg = temp_dir.path().join("IMG_0001.JPG");
+        std::fs::write(&png_as_jpg, b"\x89PNG\r\n\x1a\n\x00\x00").unwrap();
         assert_eq!(
-            std::fs::read_to_string(extracted_file).unwrap(),
-            "hello zip world"
+            file_kind(&png_as_jpg),
+            Some(FileKind::Media(Some(ContentType::Image("png"))))
         );
     }
 
     #[test]
-    fn test_extract_archive_tar_gz() {
+    fn test_process_file_fixes_extension() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("IMG_0001.JPG");
+        let output = temp_dir.path().join("out");
+        std::fs::write(&input, b"\x89PNG\r\n\x1a\n\x00\x00").unwrap();
+
+        let options = OrganizeOptions {
+            fix_extensions: true,
+            ..Default::default()
+        };
+        let content = sniff::sniff(&input);
+        let processed = process_file(
+            &input,
+            Path::new(""),
+            content,
+            &output,
+            None,
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(processed.dest_path, output.join("unknown/IMG_0001.png"));
+    }
+
+    #[test]
+    fn test_process_file_verified_copy() {
         let temp_dir = tempfile::tempdir().unwrap();
-        let archive_path = temp_dir.path().join("test.tar.gz");
-        let extract_to = temp_dir.path().join("extracted_tar_gz");
-
-        // Create a dummy tar.gz file
-        let file = std::fs::File::create(&archive_path).unwrap();
-        let enc = flate2::write::GzEncoder::new(file, flate2::Compression::default());
-        let mut tar_builder = tar::Builder::new(enc);
-
-        let mut header = tar::Header::new_gnu();
-        header.set_path("test_tar.txt").unwrap();
-        header.set_size(14);
-        header.set_cksum();
-        tar_builder
-            .append(&header, "hello targz ok".as_bytes())
-            .unwrap();
-        // Fully flush the encoder to disk
-        tar_builder.into_inner().unwrap().finish().unwrap();
-
-        // Extract it
-        extract_archive(&archive_path, &extract_to).unwrap();
-
-        // Verify
-        let extracted_file = extract_to.join("test_tar.txt");
-        assert!(extracted_file.exists());
+        let input = temp_dir.path().join("photo.jpg");
+        let output = temp_dir.path().join("out");
+        std::fs::write(&input, "pixels").unwrap();
+
+        let options = OrganizeOptions {
+            verify_copies: true,
+            ..Default::default()
+        };
+
+        let processed =
+            process_file(&input, Path::new(""), None, &output, None, &options, None).unwrap();
+        assert_eq!(processed.action, FileAction::New);
+        assert_eq!(processed.dest_path, output.join("unknown/photo.jpg"));
+        assert_eq!(processed.checksum, Some(hash_file(&input).unwrap()));
+
+        let processed =
+            process_file(&input, Path::new(""), None, &output, None, &options, None).unwrap();
+        assert_eq!(processed.action, FileAction::Skipped);
+        assert_eq!(processed.checksum, None);
+    }
+
+    #[test]
+    fn test_process_file_low_confidence_placement() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("scan.jpg");
+        let output = temp_dir.path().join("out");
+        std::fs::write(&input, "pixels").unwrap();
+        let relative = Path::new("Takeout/Google Photos/Old Album/scan.jpg");
+
+        let year_only = ResolvedDate {
+            date: naive_to_utc(2014, 1, 1).unwrap(),
+            source: DateSource::FolderHint,
+        };
+        let options = OrganizeOptions::default();
+        let processed = process_file(
+            &input,
+            relative,
+            None,
+            &output,
+            Some(year_only),
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(processed.dest_path, output.join("2014/unknown/scan.jpg"));
+
+        let options = OrganizeOptions {
+            preserve_unknown_paths: true,
+            ..Default::default()
+        };
+        let processed =
+            process_file(&input, relative, None, &output, None, &options, None).unwrap();
+        assert_eq!(
+            processed.dest_path,
+            output.join("unknown/Takeout/Google Photos/Old Album/scan.jpg")
+        );
+    }
+
+    #[test]
+    fn test_process_file_rename_template() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = temp_dir.path().join("out");
+        let burst: Vec<_> = ["IMG_0001.JPG", "IMG_0002.JPG"]
+            .iter()
+            .map(|name| {
+                let input = temp_dir.path().join(name);
+                std::fs::write(&input, name.as_bytes()).unwrap();
+                input
+            })
+            .collect();
+        let date = ResolvedDate {
+            date: naive_to_utc(2019, 7, 4).unwrap(),
+            source: DateSource::Exif,
+        };
+        let options = OrganizeOptions {
+            rename_template: Some("{date}_{counter:2}.{ext_lower}".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let names = NameRegistry::default();
+        let place = |input: &Path| {
+            let relative = Path::new(input.file_name().unwrap());
+            process_file(
+                input,
+                relative,
+                None,
+                &output,
+                Some(date),
+                &options,
+                Some(&names),
+            )
+            .unwrap()
+        };
+        let first = place(&burst[0]);
+        let second = place(&burst[1]);
+        assert_eq!(
+            first.dest_path.file_name().unwrap(),
+            "20190704_120000_01.jpg"
+        );
         assert_eq!(
-            std::fs::read_to_string(extracted_file).unwrap(),
-            "hello targz ok"
+            second.dest_path.file_name().unwrap(),
+            "20190704_120000_02.jpg"
         );
+
+        // A re-run with a fresh registry still recognises the copies already in place
+        let names = NameRegistry::default();
+        let again = process_file(
+            &burst[1],
+            Path::new("IMG_0002.JPG"),
+            None,
+            &output,
+            Some(date),
+            &options,
+            Some(&names),
+        )
+        .unwrap();
+        assert_eq!(again.dest_path, second.dest_path);
+        assert_eq!(again.action, FileAction::Skipped);
+    }
+
+    #[test]
+    fn test_windows_path_limit_starts_at_library_root() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        // However deep the share is mounted locally, names are limited below its root
+        let output = temp_dir.path().join("mnt").join("n".repeat(200));
+        let name = format!("{}.txt", "x".repeat(100));
+        let input = temp_dir.path().join(&name);
+        std::fs::write(&input, "notes").unwrap();
+
+        let processed = process_non_media_file(
+            &input,
+            Path::new(&name),
+            &output,
+            "other",
+            false,
+            TargetFs::Smb,
+            None,
+        )
+        .unwrap();
+        assert_eq!(processed.dest_path, output.join("other").join(&name));
     }
 }
@@ -1,17 +1,76 @@
 use anyhow::Result;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
 use log::debug;
 use regex::Regex;
+use std::collections::HashMap;
 use std::fs;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use crate::organizer::clock_skew::{ClockSkewRules, SkewAdjustment};
 use crate::organizer::date_utils::{naive_to_utc, timestamp_string_to_date};
-use crate::organizer::model::PhotoMetadata;
+use crate::organizer::edits;
+use crate::organizer::model::{DateSource, PhotoMetadata, ResolvedDate};
+
+/// Localized names of the Takeout year folders, `{}` standing for the year.
+const YEAR_FOLDER_NAMES: [&str; 16] = [
+    "Photos from {}",
+    "Fotos de {}",
+    "Fotos von {}",
+    "Photos de {}",
+    "Foto del {}",
+    "Foto's uit {}",
+    "Fotos från {}",
+    "Billeder fra {}",
+    "Bilder fra {}",
+    "Zdjęcia z {}",
+    "Fotky z roku {}",
+    "Fotoğraflar ({})",
+    "Фото за {} г.",
+    "{}年の写真",
+    "{} 年的相片",
+    "{}년 사진",
+];
+
+/// Low-confidence strategies tried, in order, when JSON, EXIF and the filename give no date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateFallback {
+    /// Year of the Takeout `Photos from YYYY` folder
+    FolderHint,
+    /// Modification time stored in the header of the archive entry
+    ArchiveMtime,
+    /// Modification time of a file from a plain input directory
+    FileMtime,
+    /// No fallback; undated files stay in the unknown folder
+    None,
+}
+
+impl FromStr for DateFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "folder" | "folder-hint" => Ok(DateFallback::FolderHint),
+            "archive-mtime" => Ok(DateFallback::ArchiveMtime),
+            "file-mtime" => Ok(DateFallback::FileMtime),
+            "none" => Ok(DateFallback::None),
+            _ => Err(format!(
+                "invalid date fallback '{}' (expected folder, archive-mtime, file-mtime or none)",
+                s
+            )),
+        }
+    }
+}
 
 pub struct DateExtractor {
     regex_std: Regex,
     regex_dmy: Regex,
+    regex_folder_hint: Regex,
+    fallbacks: Vec<DateFallback>,
+    /// Header timestamps of the files extracted from archives, by extracted path
+    archive_dates: HashMap<PathBuf, DateTime<Utc>>,
+    clock_skew: ClockSkewRules,
 }
 
 impl DateExtractor {
@@ -19,40 +78,160 @@ impl DateExtractor {
         Ok(Self {
             regex_std: Regex::new(r"(\d{4})[-_]?(\d{2})[-_]?(\d{2})")?,
             regex_dmy: Regex::new(r"(\d{2})(\d{2})(\d{4})")?,
+            regex_folder_hint: year_folder_regex()?,
+            fallbacks: Vec::new(),
+            archive_dates: HashMap::new(),
+            clock_skew: ClockSkewRules::default(),
         })
     }
 
-    pub fn determine_date(&self, input_path: &Path) -> Option<DateTime<Utc>> {
-        let mut path_with_extra_json_ext = PathBuf::from(input_path);
-        if let Some(filename) = input_path.file_name() {
-            let mut new_name = filename.to_os_string();
-            new_name.push(".json");
-            path_with_extra_json_ext.set_file_name(new_name);
+    pub fn with_fallbacks(mut self, fallbacks: Vec<DateFallback>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    pub fn with_archive_dates(mut self, archive_dates: HashMap<PathBuf, DateTime<Utc>>) -> Self {
+        self.archive_dates = archive_dates;
+        self
+    }
+
+    /// Corrects the dates of cameras with a wrong clock.
+    pub fn with_clock_skew(mut self, clock_skew: ClockSkewRules) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// Resolves the date of `input_path`. `from_archive` tells whether the file was extracted
+    /// from an archive, which rules out the file mtime fallback: extraction sets it.
+    pub fn determine_date(&self, input_path: &Path, from_archive: bool) -> Option<ResolvedDate> {
+        self.resolve(input_path, from_archive).0
+    }
+
+    /// Resolves the date of `input_path` like [`Self::determine_date`], also returning the
+    /// clock skew correction it received, if any.
+    pub fn resolve(
+        &self,
+        input_path: &Path,
+        from_archive: bool,
+    ) -> (Option<ResolvedDate>, Option<SkewAdjustment>) {
+        let Some(date) = self.uncorrected_date(input_path, from_archive) else {
+            return (None, None);
+        };
+        match self.clock_skew.adjust(input_path, &date) {
+            Some(adjustment) => (Some(adjustment.corrected), Some(adjustment)),
+            None => (Some(date), None),
         }
-        let path_with_json_ext = input_path.with_extension("json");
+    }
 
+    fn uncorrected_date(&self, input_path: &Path, from_archive: bool) -> Option<ResolvedDate> {
         // Extract Date from metadata json
-        if let Some(date) = self
-            .parse_json_date(&path_with_extra_json_ext)
-            .or_else(|| self.parse_json_date(&path_with_json_ext))
-        {
-            return Some(date);
+        if let Some(date) = self.json_date(input_path) {
+            return Some(ResolvedDate {
+                date,
+                source: DateSource::Json,
+            });
         }
 
         // Extract Date from EXIF
         if let Some(date) = self.get_exif_date(input_path) {
             debug!("Date found in EXIF for: {:?}", input_path.file_name());
-            return Some(date);
+            return Some(ResolvedDate {
+                date,
+                source: DateSource::Exif,
+            });
         }
 
         // Extract Date from filename
         if let Some(date) = self.parse_filename_date(input_path) {
-            return Some(date);
+            return Some(ResolvedDate {
+                date,
+                source: DateSource::Filename,
+            });
+        }
+
+        self.fallback_date(input_path, from_archive)
+    }
+
+    /// Every date an organized file itself suggests (EXIF, filename), for manual review. Its
+    /// JSON sidecar stays in the Takeout; the catalog keeps that date instead.
+    pub fn candidates(&self, input_path: &Path) -> Vec<ResolvedDate> {
+        [
+            (self.get_exif_date(input_path), DateSource::Exif),
+            (self.parse_filename_date(input_path), DateSource::Filename),
+        ]
+        .into_iter()
+        .filter_map(|(date, source)| date.map(|date| ResolvedDate { date, source }))
+        .collect()
+    }
+
+    fn fallback_date(&self, input_path: &Path, from_archive: bool) -> Option<ResolvedDate> {
+        for fallback in &self.fallbacks {
+            let resolved = match fallback {
+                DateFallback::FolderHint => self
+                    .folder_hint_year(input_path)
+                    .and_then(|year| naive_to_utc(year, 1, 1))
+                    .map(|date| (date, DateSource::FolderHint)),
+                DateFallback::ArchiveMtime => self
+                    .archive_dates
+                    .get(input_path)
+                    .map(|date| (*date, DateSource::ArchiveMtime)),
+                DateFallback::FileMtime if !from_archive => {
+                    file_mtime(input_path).map(|date| (date, DateSource::FileMtime))
+                }
+                _ => None,
+            };
+
+            if let Some((date, source)) = resolved {
+                debug!(
+                    "Low-confidence date ({:?}) for: {:?}",
+                    source,
+                    input_path.file_name()
+                );
+                return Some(ResolvedDate { date, source });
+            }
         }
 
         None
     }
 
+    /// Finds the year of an enclosing Takeout `Photos from YYYY` folder (in any known language).
+    pub fn folder_hint_year(&self, input_path: &Path) -> Option<i32> {
+        input_path.ancestors().skip(1).find_map(|dir| {
+            let name = dir.file_name()?.to_str()?;
+            let caps = self.regex_folder_hint.captures(name)?;
+            caps.iter().skip(1).flatten().next()?.as_str().parse().ok()
+        })
+    }
+
+    /// Returns the year folder's year when an EXIF or filename date falls outside it.
+    /// A day of slack on either side absorbs time zone differences around New Year.
+    pub fn folder_year_mismatch(&self, input_path: &Path, resolved: &ResolvedDate) -> Option<i32> {
+        if !matches!(resolved.source, DateSource::Exif | DateSource::Filename) {
+            return None;
+        }
+
+        let folder_year = self.folder_hint_year(input_path)?;
+        let slack = chrono::Duration::days(1);
+        let within = [resolved.date - slack, resolved.date, resolved.date + slack]
+            .iter()
+            .any(|d| d.year() == folder_year);
+
+        if within { None } else { Some(folder_year) }
+    }
+
+    /// Reads the JSON sidecar of `input_path`. Edited copies have no sidecar of their own and
+    /// share the one of their original.
+    fn json_date(&self, input_path: &Path) -> Option<DateTime<Utc>> {
+        sidecar_paths(input_path)
+            .into_iter()
+            .chain(
+                edits::unedited_name(input_path)
+                    .into_iter()
+                    .flat_map(|original| sidecar_paths(&original)),
+            )
+            .find_map(|json_path| self.parse_json_date(&json_path))
+    }
+
     fn parse_json_date(&self, json_path: &Path) -> Option<DateTime<Utc>> {
         if !json_path.exists() {
             return None;
@@ -136,6 +315,58 @@ impl DateExtractor {
     }
 }
 
+/// Possible JSON sidecars of a media file: `IMG_1.jpg.json`, `IMG_1.json`, and for names
+/// Takeout disambiguated, `IMG_1.jpg(1).json` next to `IMG_1(1).jpg`.
+pub fn sidecar_paths(path: &Path) -> Vec<PathBuf> {
+    let Some(filename) = path.file_name() else {
+        return Vec::new();
+    };
+    let mut new_name = filename.to_os_string();
+    new_name.push(".json");
+    let mut sidecars = vec![path.with_file_name(new_name), path.with_extension("json")];
+
+    if let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+        && let Some((base, counter)) = stem.strip_suffix(')').and_then(|s| s.rsplit_once('('))
+        && !counter.is_empty()
+        && counter.chars().all(|c| c.is_ascii_digit())
+    {
+        let ext = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        sidecars.push(path.with_file_name(format!("{}{}({}).json", base, ext, counter)));
+    }
+    sidecars
+}
+
+/// Original file name recorded in the JSON sidecar, before Takeout truncated or numbered it.
+pub fn sidecar_title(input_path: &Path) -> Option<String> {
+    sidecar_paths(input_path).into_iter().find_map(|json_path| {
+        let file = fs::File::open(json_path).ok()?;
+        let metadata: PhotoMetadata = serde_json::from_reader(BufReader::new(file)).ok()?;
+        metadata.title.filter(|t| !t.trim().is_empty())
+    })
+}
+
+fn year_folder_regex() -> Result<Regex, regex::Error> {
+    let alternatives: Vec<String> = YEAR_FOLDER_NAMES
+        .iter()
+        .map(|name| regex::escape(name).replace(r"\{\}", r"(\d{4})"))
+        .collect();
+    Regex::new(&format!("^(?:{})$", alternatives.join("|")))
+}
+
+/// Modification time of a file, ignoring the zero dates (1980) of archives without timestamps
+/// and times in the future.
+fn file_mtime(path: &Path) -> Option<DateTime<Utc>> {
+    let modified: DateTime<Utc> = fs::metadata(path).ok()?.modified().ok()?.into();
+    if modified.year() > 1990 && modified <= Utc::now() {
+        Some(modified)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,10 +401,116 @@ mod tests {
         assert_eq!(date.day(), 25);
     }
 
+    #[test]
+    fn test_folder_hint_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("Takeout/Google Photos/Photos from 2014");
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("scan.jpg");
+        fs::write(&path, "no metadata").unwrap();
+
+        let extractor = DateExtractor::new().unwrap();
+        assert!(extractor.determine_date(&path, true).is_none());
+
+        let extractor = extractor.with_fallbacks(vec![DateFallback::FolderHint]);
+        let resolved = extractor.determine_date(&path, true).unwrap();
+        assert_eq!(resolved.source, DateSource::FolderHint);
+        assert_eq!(resolved.date.year(), 2014);
+        assert!(resolved.source.is_low_confidence());
+    }
+
+    #[test]
+    fn test_localized_year_folders() {
+        let extractor = DateExtractor::new().unwrap();
+        let year = |p: &str| extractor.folder_hint_year(Path::new(p));
+
+        assert_eq!(year("Takeout/Google Fotos/Fotos de 2019/a.jpg"), Some(2019));
+        assert_eq!(
+            year("Takeout/Google Fotos/Fotos von 2008/a.jpg"),
+            Some(2008)
+        );
+        assert_eq!(year("Takeout/Google フォト/2015年の写真/a.jpg"), Some(2015));
+        assert_eq!(year("Takeout/Google Photos/Trip 2019/a.jpg"), None);
+    }
+
+    #[test]
+    fn test_folder_year_mismatch() {
+        let extractor = DateExtractor::new().unwrap();
+        let path = Path::new("Takeout/Google Photos/Photos from 2019/IMG_0001.jpg");
+        let resolved = |y, m, d, source| ResolvedDate {
+            date: naive_to_utc(y, m, d).unwrap(),
+            source,
+        };
+
+        assert_eq!(
+            extractor.folder_year_mismatch(path, &resolved(2003, 1, 1, DateSource::Exif)),
+            Some(2019)
+        );
+        assert_eq!(
+            extractor.folder_year_mismatch(path, &resolved(2019, 7, 1, DateSource::Exif)),
+            None
+        );
+        // New Year's Eve in another time zone is still fine
+        assert_eq!(
+            extractor.folder_year_mismatch(path, &resolved(2020, 1, 1, DateSource::Filename)),
+            None
+        );
+        // Google's own metadata is not second-guessed
+        assert_eq!(
+            extractor.folder_year_mismatch(path, &resolved(2003, 1, 1, DateSource::Json)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_mtime_fallbacks_depend_on_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.jpg");
+        fs::write(&path, "no metadata").unwrap();
+
+        // Only the archive header counts, never the extracted file's mtime
+        let extractor = DateExtractor::new()
+            .unwrap()
+            .with_fallbacks(vec![DateFallback::ArchiveMtime]);
+        assert!(extractor.determine_date(&path, true).is_none());
+        let header_date = naive_to_utc(2012, 6, 3).unwrap();
+        let extractor = extractor.with_archive_dates(HashMap::from([(path.clone(), header_date)]));
+        assert_eq!(
+            extractor.determine_date(&path, true),
+            Some(ResolvedDate {
+                date: header_date,
+                source: DateSource::ArchiveMtime
+            })
+        );
+
+        let extractor = DateExtractor::new()
+            .unwrap()
+            .with_fallbacks(vec![DateFallback::FileMtime]);
+        assert!(extractor.determine_date(&path, true).is_none());
+        assert_eq!(
+            extractor.determine_date(&path, false).unwrap().source,
+            DateSource::FileMtime
+        );
+    }
+
     #[test]
     fn test_filename_no_date() {
         let extractor = DateExtractor::new().unwrap();
         let path = Path::new("random_image.jpg");
         assert!(extractor.parse_filename_date(path).is_none());
     }
+
+    #[test]
+    fn test_sidecar_paths_of_numbered_names() {
+        let sidecars = sidecar_paths(Path::new("Trip/IMG_0001(1).jpg"));
+        assert_eq!(
+            sidecars,
+            vec![
+                PathBuf::from("Trip/IMG_0001(1).jpg.json"),
+                PathBuf::from("Trip/IMG_0001(1).json"),
+                PathBuf::from("Trip/IMG_0001.jpg(1).json"),
+            ]
+        );
+        assert_eq!(sidecar_paths(Path::new("IMG_0001.jpg")).len(), 2);
+    }
 }
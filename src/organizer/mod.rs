@@ -1,56 +1,243 @@
+pub mod archive;
+pub mod cancel;
+pub mod catalog;
+pub mod clock_skew;
 pub mod date_utils;
+pub mod dedupe;
+pub mod disk_space;
+pub mod edits;
+pub mod export;
+pub mod filters;
 pub mod fs_ops;
 pub mod metadata;
 pub mod model;
+pub mod name_template;
+pub mod overrides;
+pub mod owners;
+pub mod progress;
+pub mod reorganize;
+pub mod report;
+pub mod sequence;
+pub mod sniff;
+pub mod stats;
+pub mod takeout_parts;
+pub mod target_fs;
+pub mod titles;
+pub mod triage;
 pub mod ui;
+pub mod verify;
+pub mod watch;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use chrono::Duration;
+use indicatif::ProgressBar;
 use log::{error, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use tempfile::TempDir;
+use std::sync::Mutex;
+use std::time::{Duration as StdDuration, Instant};
 use walkdir::WalkDir;
 
-use self::fs_ops::FileAction;
-use self::metadata::DateExtractor;
+use self::cancel::PartialDir;
+use self::catalog::{Catalog, CatalogEntry, catalog_key};
+use self::clock_skew::ClockSkewRules;
+use self::disk_space::SpaceRequirement;
+use self::edits::EditPolicy;
+use self::filters::FileFilter;
+use self::fs_ops::{FileAction, FileKind, ProcessedFile};
+use self::metadata::{DateExtractor, DateFallback};
+use self::model::{DateSource, ResolvedDate};
+use self::name_template::NameTemplate;
+use self::overrides::Overrides;
+use self::owners::DuplicateIndex;
+use self::progress::{FileResult, Phase};
+use self::report::RunReport;
+use self::sequence::SequenceIndex;
+use self::sniff::ContentType;
+use self::target_fs::TargetFs;
+use self::titles::NameRegistry;
 
-pub fn organize_files(input_paths: &[&Path], output_path: &Path, unknown_dir: &str) -> Result<()> {
+pub struct OrganizeOptions {
+    /// Name of the folder for files with no date
+    pub unknown_dir: String,
+    /// Re-read and hash source and destination after every copy
+    pub verify_copies: bool,
+    /// Test every archive entry before extracting anything
+    pub check_archives: bool,
+    /// Refuse to organize when Takeout parts are missing or come from different exports
+    pub require_complete_takeout: bool,
+    /// Folder to extract archives into instead of the system temp directory
+    pub staging_dir: Option<PathBuf>,
+    /// Compare the expected extraction and output sizes with the free disk space first
+    pub check_disk_space: bool,
+    /// Rename files whose extension does not match their content
+    pub fix_extensions: bool,
+    /// Copy non-media files here (keeping their Takeout path) instead of only reporting them
+    pub non_media_dir: Option<String>,
+    /// Include/exclude rules applied before copying
+    pub filter: FileFilter,
+    /// Low-confidence date sources tried, in order, when a file has no better date
+    pub date_fallbacks: Vec<DateFallback>,
+    /// Keep the Takeout folder structure below the unknown folder for undated files
+    pub preserve_unknown_paths: bool,
+    /// Hand-fixed dates that win over every other date source
+    pub overrides: Overrides,
+    /// Per-camera date corrections for cameras with wrong clocks
+    pub clock_skew: ClockSkewRules,
+    /// Write clock skew corrections into the EXIF dates of the organized copies
+    pub write_corrected_exif: bool,
+    /// Date undated frames of a numbered sequence from their dated neighbours
+    pub infer_sequence_dates: bool,
+    /// Largest gap between the two neighbours for an inferred date to be used
+    pub sequence_window: Duration,
+    /// Account owner of each labelled input path
+    pub owners: BTreeMap<PathBuf, String>,
+    /// Organize each owner's files below a folder named after the owner
+    pub owner_layout: bool,
+    /// Which versions of photos exported with an edited copy to keep
+    pub edit_policy: EditPolicy,
+    /// Name output files after the original name in their JSON sidecar
+    pub restore_titles: bool,
+    /// Pattern for output file names, e.g. `{date:%Y%m%d_%H%M%S}_{camera}.{ext_lower}`
+    pub rename_template: Option<NameTemplate>,
+    /// Filesystem of the output, whose naming rules file names are adapted to
+    pub target_fs: TargetFs,
+}
+
+impl Default for OrganizeOptions {
+    fn default() -> Self {
+        Self {
+            unknown_dir: "unknown".to_string(),
+            verify_copies: false,
+            check_archives: false,
+            require_complete_takeout: false,
+            staging_dir: None,
+            check_disk_space: true,
+            fix_extensions: false,
+            non_media_dir: None,
+            filter: FileFilter::default(),
+            date_fallbacks: vec![DateFallback::FolderHint],
+            preserve_unknown_paths: false,
+            overrides: Overrides::default(),
+            clock_skew: ClockSkewRules::default(),
+            write_corrected_exif: false,
+            infer_sequence_dates: false,
+            sequence_window: Duration::hours(24),
+            owners: BTreeMap::new(),
+            owner_layout: false,
+            edit_policy: EditPolicy::default(),
+            restore_titles: false,
+            rename_template: None,
+            target_fs: TargetFs::default(),
+        }
+    }
+}
+
+/// Organizes the files of `input_paths` into `output_path`. Files that fail are reported in
+/// the returned [`RunReport`]; errors are only returned when the run cannot go on.
+pub fn organize_files(
+    input_paths: &[&Path],
+    output_path: &Path,
+    options: &OrganizeOptions,
+) -> Result<RunReport> {
     info!("Starting organization...");
     info!("Sources: {:?}", input_paths);
     info!("Dest:   {:?}", output_path);
-    info!("Dir for unknown files: {:?}", unknown_dir);
+    info!("Dir for unknown files: {:?}", options.unknown_dir);
+
+    let mut report = RunReport::default();
+    let phase = Phase::start("preflight", None);
 
-    let mut processed_input_paths: Vec<PathBuf> = Vec::new();
+    // Every source carries the account it belongs to, when inputs are labelled
+    let mut processed_input_paths: Vec<(PathBuf, Option<String>)> = Vec::new();
 
-    let mut archives = Vec::new();
+    let mut archives: Vec<(PathBuf, Option<String>)> = Vec::new();
     for input_path in input_paths {
-        if fs_ops::is_archive(input_path) {
-            archives.push(input_path.to_path_buf());
+        let owner = options.owners.get(*input_path).cloned();
+        if archive::is_archive(input_path) {
+            archives.push((input_path.to_path_buf(), owner));
         } else if input_path.is_dir() {
-            processed_input_paths.push(input_path.to_path_buf());
+            processed_input_paths.push((input_path.to_path_buf(), owner.clone()));
             // Scan directory for internal archives too
             for entry in fs::read_dir(input_path)? {
                 let entry = entry?;
                 let path = entry.path();
-                if fs_ops::is_archive(&path) {
-                    archives.push(path);
+                if archive::is_archive(&path) {
+                    archives.push((path, owner.clone()));
                 }
             }
         }
     }
 
-    let temp_dir: Option<TempDir> = if !archives.is_empty() {
-        let temp = TempDir::new()?;
-        for archive in archives {
-            if let Err(e) = fs_ops::extract_archive(&archive, temp.path()) {
-                warn!("Failed to extract archive {:?}: {}", archive, e);
+    // Archives of one account form one Takeout; each account is checked on its own
+    let mut archive_groups: BTreeMap<Option<String>, Vec<PathBuf>> = BTreeMap::new();
+    for (path, owner) in &archives {
+        archive_groups
+            .entry(owner.clone())
+            .or_default()
+            .push(path.clone());
+    }
+    for (owner, group) in &archive_groups {
+        if let Some(owner) = owner {
+            info!("Takeout of {}:", owner);
+        }
+        let parts = takeout_parts::check_parts(group);
+        takeout_parts::log_parts_report(&parts);
+        if options.require_complete_takeout && !parts.is_complete() {
+            bail!(
+                "Takeout is incomplete; add the missing parts or drop --require-complete-takeout"
+            );
+        }
+    }
+
+    if options.check_archives && !archives.is_empty() {
+        info!(
+            "Running pre-flight integrity check on {} archives...",
+            archives.len()
+        );
+        let mut reports = Vec::new();
+        for (archive_path, _) in &archives {
+            match archive::check_archive(archive_path) {
+                Ok(report) => reports.push(report),
+                Err(e) => warn!("Failed to check archive {:?}: {}", archive_path, e),
             }
         }
-        processed_input_paths.push(temp.path().to_path_buf());
-        Some(temp)
-    } else {
-        None
-    };
+        if reports.iter().all(|r| r.is_healthy()) {
+            info!("All archives passed the integrity check");
+        }
+        archive::log_damaged_archives(&reports);
+    }
+
+    let catalog = Catalog::load(output_path)?;
+    if options.check_disk_space {
+        let archive_paths: Vec<PathBuf> = archives.iter().map(|(p, _)| p.clone()).collect();
+        let directories: Vec<PathBuf> = processed_input_paths
+            .iter()
+            .map(|(p, _)| p.clone())
+            .collect();
+        check_disk_space(
+            &archive_paths,
+            &directories,
+            output_path,
+            options.staging_dir.as_deref(),
+            &catalog,
+        )?;
+    }
+
+    report.add_phase("preflight", phase.finish());
+    let extract_phase =
+        (!archives.is_empty()).then(|| Phase::start("extract", Some(archives.len() as u64)));
+
+    let (temp_dir, mut archive_reports) = extract_archives(
+        archive_groups,
+        options.staging_dir.as_deref(),
+        &mut report,
+        &mut processed_input_paths,
+    )?;
+    if let Some(phase) = extract_phase {
+        report.add_phase("extract", phase.finish());
+    }
 
     // Check if output directory is already populated (incremental run)
     let is_incremental_run = output_path.exists()
@@ -58,77 +245,207 @@ pub fn organize_files(input_paths: &[&Path], output_path: &Path, unknown_dir: &s
             .map(|mut i| i.next().is_some())
             .unwrap_or(false);
 
-    // First pass: Count files to initialize progress bar
-    let mut total_files = 0;
-    for path in &processed_input_paths {
-        total_files += get_total_files(path);
-    }
+    // First pass: classify every file once, counting media to initialize the progress bar
+    let input_files: Vec<Vec<(PathBuf, FileKind)>> = processed_input_paths
+        .iter()
+        .map(|(path, _)| classify_files(path))
+        .collect();
+    let total_files = input_files
+        .iter()
+        .flatten()
+        .filter(|(_, kind)| matches!(kind, FileKind::Media(_)))
+        .count() as u64;
     info!("Found {} files to process", total_files);
+    let phase = Phase::start("organize", Some(total_files));
 
     let progress_bar = ui::create_progress_bar(total_files);
     progress_bar.set_message("Organizing Photos:");
 
     use rayon::prelude::*;
-    use std::sync::Mutex;
 
-    let date_extractor = DateExtractor::new()?;
-    let success_count = Mutex::new(0);
-    let error_count = Mutex::new(0);
-    let skipped_count = Mutex::new(0);
-    let new_files = Mutex::new(Vec::new());
+    let archive_dates = archive_reports
+        .iter_mut()
+        .flat_map(|r| std::mem::take(&mut r.entry_dates))
+        .collect();
+    let date_extractor = DateExtractor::new()?
+        .with_fallbacks(options.date_fallbacks.clone())
+        .with_archive_dates(archive_dates)
+        .with_clock_skew(options.clock_skew.clone());
+    let distinct_owners: BTreeSet<_> = processed_input_paths
+        .iter()
+        .filter_map(|(_, owner)| owner.as_deref())
+        .collect();
+    // Labelled runs hash every file, so shared content is collapsed against accounts organized
+    // in this run as well as in earlier ones
+    let duplicates = (!distinct_owners.is_empty()).then(|| DuplicateIndex::from_catalog(&catalog));
+    let names = (options.restore_titles
+        || options.rename_template.is_some()
+        || options.target_fs != TargetFs::Native)
+        .then(|| {
+            NameRegistry::from_catalog(output_path, &catalog)
+                .case_insensitive(options.target_fs.is_case_insensitive())
+        });
+    // `{counter}` numbers files in source order, so they are named only once all are dated
+    let held = options
+        .rename_template
+        .as_ref()
+        .is_some_and(NameTemplate::has_counter)
+        .then(Mutex::default);
 
-    processed_input_paths.par_iter().for_each(|source_path| {
-        WalkDir::new(source_path)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .par_bridge() // Bridge WalkDir (sequential) to parallel iterator
-            .for_each(|entry| {
-                let path = entry.path();
+    let run = Run {
+        options,
+        output_path,
+        date_extractor,
+        extracted_root: temp_dir.as_ref().map(|t| t.path().to_path_buf()),
+        duplicates,
+        names,
+        progress_bar,
+        catalog: Mutex::new(catalog),
+        report: Mutex::new(report),
+        collected: Mutex::default(),
+        sequence_index: Mutex::default(),
+        deferred: Mutex::default(),
+        held,
+        catalog_saved: Mutex::new(Instant::now()),
+    };
 
-                if !fs_ops::should_process_file(path) {
-                    return;
+    processed_input_paths
+        .par_iter()
+        .zip(input_files.par_iter())
+        .for_each(|((source_path, owner), files)| {
+            files.par_iter().for_each(|(path, kind)| {
+                // Files already being copied finish, nothing new starts
+                if !cancel::is_cancelled() {
+                    run.handle_file(source_path, owner.as_deref(), path, *kind);
                 }
-
-                let date = date_extractor.determine_date(path);
-
-                match fs_ops::process_file(path, output_path, date, unknown_dir) {
-                    Ok(action) => match action {
-                        FileAction::New => {
-                            if let Ok(mut count) = success_count.lock() {
-                                *count += 1;
-                            }
-                            if let Some(name) = path.file_name()
-                                && let Ok(mut files) = new_files.lock()
-                            {
-                                files.push(name.to_string_lossy().to_string());
-                            }
-                        }
-                        FileAction::Updated => {
-                            if let Ok(mut count) = success_count.lock() {
-                                *count += 1;
-                            }
-                        }
-                        FileAction::Skipped => {
-                            if let Ok(mut count) = skipped_count.lock() {
-                                *count += 1;
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        error!("Failed to process {:?}: {}", path, e);
-                        if let Ok(mut count) = error_count.lock() {
-                            *count += 1;
-                        }
-                    }
-                }
-                progress_bar.inc(1);
             });
-    });
+        });
+    run.place_deferred();
+    run.place_held();
 
-    let new_files = new_files.into_inner().unwrap_or_default();
+    let Run {
+        duplicates,
+        progress_bar,
+        catalog,
+        report,
+        collected,
+        ..
+    } = run;
+    let Collected {
+        new_files,
+        mut non_media_files,
+        mut skew_report,
+        placed_keys,
+        edit_links,
+    } = collected.into_inner().unwrap_or_default();
 
-    progress_bar.finish_with_message("Done");
+    let mut report = report.into_inner().unwrap_or_default();
+    if cancel::is_cancelled() {
+        progress_bar.abandon_with_message("Cancelled");
+        warn!(
+            "Cancelled after {} of {} files; they are kept and recorded in the catalog",
+            progress_bar.position(),
+            total_files
+        );
+        report.cancelled = true;
+    } else {
+        progress_bar.finish_with_message("Done");
+    }
 
+    if output_path.exists() {
+        let mut catalog = catalog.into_inner().unwrap_or_default();
+        if let Some(duplicates) = duplicates {
+            duplicates.merge_into(&mut catalog);
+        }
+        for (edited, original) in edit_links {
+            if let Some(original_key) = placed_keys.get(&original)
+                && let Some(entry) = catalog.entries.get_mut(&edited)
+            {
+                entry.edit_of = Some(original_key.clone());
+            }
+        }
+        catalog.save(output_path)?;
+    }
+    report.add_phase("organize", phase.finish());
+
+    non_media_files.sort();
+    skew_report.sort_by(|a, b| a.source.cmp(&b.source));
+    report.counts.non_media = non_media_files.len();
+    report.counts.excluded = report.exclusions.total();
+    if !skew_report.is_empty() && output_path.exists() {
+        clock_skew::write_report(output_path, &skew_report)?;
+    }
+    log_run_summary(
+        &report,
+        &new_files,
+        &non_media_files,
+        skew_report.len(),
+        options,
+        output_path,
+        is_incremental_run,
+    );
+
+    archive::log_damaged_archives(&archive_reports);
+
+    // If we used a temporary directory, it will be deleted when temp_dir is dropped
+    if temp_dir.is_some() {
+        info!("Cleaning up temporary directory...");
+    }
+
+    Ok(report)
+}
+
+/// Extracts the archives of every account into a staging folder, adding the extracted
+/// folders to `inputs`. Archives that fail are recorded in `report`.
+fn extract_archives(
+    archive_groups: BTreeMap<Option<String>, Vec<PathBuf>>,
+    staging_dir: Option<&Path>,
+    report: &mut RunReport,
+    inputs: &mut Vec<(PathBuf, Option<String>)>,
+) -> Result<(Option<PartialDir>, Vec<archive::ArchiveHealth>)> {
+    let mut archive_reports = Vec::new();
+    if archive_groups.is_empty() {
+        return Ok((None, archive_reports));
+    }
+
+    let temp = PartialDir::new(staging_dir)?;
+    let single_account = archive_groups.len() == 1;
+    for (i, (owner, group)) in archive_groups.into_iter().enumerate() {
+        // Separate folders keep the accounts apart after extraction
+        let extract_to = if single_account {
+            temp.path().to_path_buf()
+        } else {
+            temp.path().join(i.to_string())
+        };
+        fs::create_dir_all(&extract_to)?;
+        for archive_path in group {
+            if cancel::is_cancelled() {
+                break;
+            }
+            match archive::extract_archive(&archive_path, &extract_to, &cancel::is_cancelled) {
+                Ok(health) => archive_reports.push(health),
+                Err(e) => {
+                    warn!("Failed to extract archive {:?}: {}", archive_path, e);
+                    progress::failed(&archive_path.display().to_string(), &e.to_string());
+                    report.fail(&archive_path, e);
+                }
+            }
+        }
+        inputs.push((extract_to, owner));
+    }
+    Ok((Some(temp), archive_reports))
+}
+
+/// Logs the new and non-media files and the counts of an organizing run.
+fn log_run_summary(
+    report: &RunReport,
+    new_files: &[String],
+    non_media_files: &[String],
+    skew_corrections: usize,
+    options: &OrganizeOptions,
+    output_path: &Path,
+    is_incremental_run: bool,
+) {
     if is_incremental_run {
         if !new_files.is_empty() {
             info!("--- New Files Detected ---");
@@ -141,20 +458,686 @@ pub fn organize_files(input_paths: &[&Path], output_path: &Path, unknown_dir: &s
         }
     }
 
-    // If we used a temporary directory, it will be deleted when temp_dir is dropped
-    if temp_dir.is_some() {
-        info!("Cleaning up temporary directory...");
+    if !non_media_files.is_empty() {
+        info!("--- Non-Media Files ({}) ---", non_media_files.len());
+        for file in non_media_files {
+            info!("  - {}", file);
+        }
+        match &options.non_media_dir {
+            Some(dir) => info!("Copied to {:?}", output_path.join(dir)),
+            None => info!("Not organized (use --non-media-dir to keep them)"),
+        }
+        info!("--------------------------");
+    }
+
+    let excluded = report.exclusions;
+    info!("--- Summary ---");
+    info!(
+        "Organized: {}, Already present: {}, Errors: {}",
+        report.counts.new + report.counts.updated,
+        report.counts.already_present,
+        report.counts.failed
+    );
+    if excluded.total() > 0 {
+        info!(
+            "Excluded by filters: {} (path: {}, media type: {}, date range: {})",
+            excluded.total(),
+            excluded.path,
+            excluded.media_type,
+            excluded.date_range
+        );
+    }
+    let low_confidence_count: usize = report
+        .date_sources
+        .iter()
+        .filter(|(source, _)| source.is_low_confidence())
+        .map(|(_, count)| count)
+        .sum();
+    if low_confidence_count > 0 {
+        info!(
+            "Dated by low-confidence fallbacks: {} (source recorded in the catalog)",
+            low_confidence_count
+        );
+    }
+    if report.folder_year_mismatches > 0 {
+        warn!(
+            "Dates outside their Takeout year folder: {} (flagged in the catalog)",
+            report.folder_year_mismatches
+        );
+    }
+    if report.counts.dropped_by_edit_policy > 0 {
+        info!(
+            "Skipped by the edit policy ({:?}): {}",
+            options.edit_policy, report.counts.dropped_by_edit_policy
+        );
     }
+    if report.counts.cross_account_duplicates > 0 {
+        info!(
+            "Cross-account duplicates collapsed: {} (owners merged in the catalog)",
+            report.counts.cross_account_duplicates
+        );
+    }
+    if let Some(inferred_count) = report.date_sources.get(&DateSource::Inferred) {
+        info!("Dated from neighbouring files: {}", inferred_count);
+    }
+    if skew_corrections > 0 {
+        info!(
+            "Clock skew corrected: {} (see {})",
+            skew_corrections,
+            clock_skew::REPORT_FILE
+        );
+    }
+    info!("---------------");
+}
+
+/// A media file on its way into the library, once classified and dated.
+struct MediaFile {
+    /// Takeout root the file was found in
+    source_path: PathBuf,
+    path: PathBuf,
+    content: Option<ContentType>,
+    date: Option<ResolvedDate>,
+    adjustment: Option<clock_skew::SkewAdjustment>,
+    owner: Option<String>,
+}
+
+impl MediaFile {
+    /// Path relative to the Takeout root.
+    fn relative(&self) -> &Path {
+        self.path
+            .strip_prefix(&self.source_path)
+            .unwrap_or(&self.path)
+    }
+}
 
-    Ok(())
+/// What the workers of a run gather besides the counts of the [`RunReport`].
+#[derive(Default)]
+struct Collected {
+    /// Names of the files new to the library
+    new_files: Vec<String>,
+    /// Takeout paths of the files that are not media
+    non_media_files: Vec<String>,
+    skew_report: Vec<clock_skew::ReportRow>,
+    /// Library keys of the files placed, to link edited copies once their original is placed
+    placed_keys: HashMap<PathBuf, String>,
+    /// Library keys of edited copies, with the path of their original
+    edit_links: Vec<(String, PathBuf)>,
 }
 
-fn get_total_files(input_path: &Path) -> u64 {
+/// How often the catalog is written while files are being placed.
+const CATALOG_SAVE_INTERVAL: StdDuration = StdDuration::from_secs(30);
+
+/// State shared by the workers placing the files of one run.
+struct Run<'a> {
+    options: &'a OrganizeOptions,
+    output_path: &'a Path,
+    date_extractor: DateExtractor,
+    /// Folder the archives were extracted into
+    extracted_root: Option<PathBuf>,
+    /// Content already organized for an account, when several accounts can share files
+    duplicates: Option<DuplicateIndex>,
+    /// Names taken in the library, when files are renamed
+    names: Option<NameRegistry>,
+    progress_bar: ProgressBar,
+    catalog: Mutex<Catalog>,
+    report: Mutex<RunReport>,
+    collected: Mutex<Collected>,
+    sequence_index: Mutex<SequenceIndex>,
+    /// Undated frames of numbered sequences, waiting for the dates of their neighbours
+    deferred: Mutex<Vec<MediaFile>>,
+    /// Files waiting to be named in source order, when names carry a `{counter}`
+    held: Option<Mutex<Vec<MediaFile>>>,
+    /// When the catalog was last written during the run
+    catalog_saved: Mutex<Instant>,
+}
+
+impl Run<'_> {
+    /// Writes the catalog every [`CATALOG_SAVE_INTERVAL`], so the files copied so far stay
+    /// recorded if the run is killed or quit with a second Ctrl-C.
+    fn checkpoint_catalog(&self) {
+        let Ok(mut saved) = self.catalog_saved.try_lock() else {
+            // Another file is writing it right now
+            return;
+        };
+        if saved.elapsed() < CATALOG_SAVE_INTERVAL {
+            return;
+        }
+        *saved = Instant::now();
+        let Ok(snapshot) = self.catalog.lock().map(|catalog| catalog.clone()) else {
+            return;
+        };
+        if let Err(e) = snapshot.save(self.output_path) {
+            warn!("Failed to save the catalog: {:#}", e);
+        }
+    }
+
+    fn library_base(&self, owner: Option<&str>) -> PathBuf {
+        match owner {
+            Some(owner) if self.options.owner_layout => self.output_path.join(owner),
+            _ => self.output_path.to_path_buf(),
+        }
+    }
+
+    /// Counts a media file left out of the library with `count`.
+    fn skip(&self, relative: &Path, result: FileResult, count: impl FnOnce(&mut RunReport)) {
+        if let Ok(mut report) = self.report.lock() {
+            count(&mut report);
+        }
+        progress::file(&relative.to_string_lossy(), result, None);
+        self.progress_bar.inc(1);
+    }
+
+    /// Filters and dates one input file, then places it or queues it for later.
+    fn handle_file(&self, source_path: &Path, owner: Option<&str>, path: &Path, kind: FileKind) {
+        let options = self.options;
+        let relative = path.strip_prefix(source_path).unwrap_or(path);
+        let content = match kind {
+            FileKind::Media(content) => content,
+            FileKind::NonMedia => {
+                if let Ok(mut collected) = self.collected.lock() {
+                    collected
+                        .non_media_files
+                        .push(relative.to_string_lossy().to_string());
+                }
+                self.copy_non_media_file(path, source_path);
+                return;
+            }
+            FileKind::Sidecar => return,
+        };
+
+        if let Some(reason) = options.filter.check_file(path, relative, content) {
+            self.skip(relative, FileResult::Excluded, |report| {
+                report.exclusions.add(reason)
+            });
+            return;
+        }
+        if !options.edit_policy.keeps(path) {
+            self.skip(relative, FileResult::Excluded, |report| {
+                report.counts.dropped_by_edit_policy += 1
+            });
+            return;
+        }
+
+        let from_archive = self
+            .extracted_root
+            .as_deref()
+            .is_some_and(|root| source_path.starts_with(root));
+        let (date, adjustment) = match options
+            .overrides
+            .lookup(relative, || verify::hash_file(path).ok())
+        {
+            Some(date) => (Some(date), None),
+            None => self.date_extractor.resolve(path, from_archive),
+        };
+        let file = MediaFile {
+            source_path: source_path.to_path_buf(),
+            path: path.to_path_buf(),
+            content,
+            date,
+            adjustment,
+            owner: owner.map(str::to_string),
+        };
+
+        if options.infer_sequence_dates {
+            match date {
+                Some(d) if !d.source.is_low_confidence() => {
+                    if let Ok(mut index) = self.sequence_index.lock() {
+                        index.insert(relative, d.date);
+                    }
+                }
+                _ => {
+                    // Wait until every dated neighbour is known
+                    if let Ok(mut files) = self.deferred.lock() {
+                        files.push(file);
+                    }
+                    return;
+                }
+            }
+        }
+        self.place_or_hold(file);
+    }
+
+    fn place_or_hold(&self, file: MediaFile) {
+        match &self.held {
+            Some(held) => {
+                if let Ok(mut files) = held.lock() {
+                    files.push(file);
+                }
+            }
+            None => self.place_media(&file),
+        }
+    }
+
+    /// Inference pass: undated frames of a numbered sequence take their neighbours' dates.
+    fn place_deferred(&self) {
+        use rayon::prelude::*;
+
+        let index = self
+            .sequence_index
+            .lock()
+            .map(|mut index| std::mem::take(&mut *index))
+            .unwrap_or_default();
+        let deferred = self
+            .deferred
+            .lock()
+            .map(|mut files| std::mem::take(&mut *files))
+            .unwrap_or_default();
+        deferred.into_par_iter().for_each(|mut file| {
+            if cancel::is_cancelled() {
+                return;
+            }
+            if let Some(date) = index.interpolate(file.relative(), self.options.sequence_window) {
+                file.date = Some(ResolvedDate {
+                    date,
+                    source: DateSource::Inferred,
+                });
+            }
+            file.adjustment = None;
+            self.place_or_hold(file);
+        });
+    }
+
+    /// Claims the names of the held files one by one in source order, then copies them in
+    /// parallel under those names.
+    fn place_held(&self) {
+        use rayon::prelude::*;
+
+        let Some(held) = &self.held else {
+            return;
+        };
+        let mut files = held
+            .lock()
+            .map(|mut files| std::mem::take(&mut *files))
+            .unwrap_or_default();
+        if let Some(names) = &self.names {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            for file in &files {
+                if self
+                    .options
+                    .filter
+                    .check_date(file.date.map(|d| d.date))
+                    .is_none()
+                {
+                    let _ = fs_ops::destination(
+                        &file.path,
+                        file.relative(),
+                        file.content,
+                        &self.library_base(file.owner.as_deref()),
+                        file.date,
+                        self.options,
+                        Some(names),
+                    );
+                }
+            }
+        }
+        files.into_par_iter().for_each(|file| {
+            if !cancel::is_cancelled() {
+                self.place_media(&file);
+            }
+        });
+    }
+
+    /// Places one media file once its date is settled.
+    fn place_media(&self, file: &MediaFile) {
+        let options = self.options;
+        let output_path = self.output_path;
+        let MediaFile {
+            source_path,
+            path,
+            content,
+            date,
+            adjustment,
+            owner,
+        } = file;
+        let (path, date, owner) = (path.as_path(), *date, owner.as_deref());
+        let relative = file.relative();
+        let relative_name = relative.to_string_lossy();
+
+        if let Some(reason) = options.filter.check_date(date.map(|d| d.date)) {
+            self.skip(relative, FileResult::Excluded, |report| {
+                report.exclusions.add(reason)
+            });
+            return;
+        }
+
+        let folder_year = date
+            .as_ref()
+            .and_then(|d| self.date_extractor.folder_year_mismatch(path, d));
+        if folder_year.is_some()
+            && let Ok(mut report) = self.report.lock()
+        {
+            report.folder_year_mismatches += 1;
+        }
+
+        let content_hash = self
+            .duplicates
+            .as_ref()
+            .and_then(|_| verify::hash_file(path).ok());
+        if let (Some(duplicates), Some(hash), Some(owner)) =
+            (&self.duplicates, &content_hash, owner)
+            && !duplicates.claim(hash, owner)
+        {
+            self.skip(relative, FileResult::Duplicate, |report| {
+                report.counts.cross_account_duplicates += 1
+            });
+            return;
+        }
+
+        let mut processed = match fs_ops::process_file(
+            path,
+            relative,
+            *content,
+            &self.library_base(owner),
+            date,
+            options,
+            self.names.as_ref(),
+        ) {
+            Ok(processed) => processed,
+            Err(e) => {
+                error!("Failed to process {:?}: {}", path, e);
+                progress::failed(&relative_name, &e.to_string());
+                if let Ok(mut report) = self.report.lock() {
+                    report.fail(path, e);
+                }
+                self.progress_bar.inc(1);
+                return;
+            }
+        };
+        let key = catalog_key(output_path, &processed.dest_path);
+
+        if let Some(adjustment) = adjustment
+            && options.write_corrected_exif
+            && processed.action != FileAction::Skipped
+        {
+            write_corrected_exif(&mut processed, adjustment);
+        }
+
+        let dated = CatalogEntry {
+            date: date.map(|d| d.date.to_rfc3339()),
+            date_source: date.map(|d| d.source),
+            folder_year,
+            original_date: adjustment.as_ref().map(|a| a.original.to_rfc3339()),
+            sidecar_date: date.filter(|d| d.source == DateSource::Json).map(|d| {
+                adjustment
+                    .as_ref()
+                    .map_or(d.date, |a| a.original)
+                    .to_rfc3339()
+            }),
+            // A rewritten copy no longer matches the source hash
+            sha256: content_hash
+                .clone()
+                .filter(|_| !(options.write_corrected_exif && adjustment.is_some())),
+            owners: owner.map(str::to_string).into_iter().collect(),
+            archive_name: self
+                .names
+                .is_some()
+                .then(|| path.file_name())
+                .flatten()
+                .filter(|name| Some(*name) != processed.dest_path.file_name())
+                .map(|name| name.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        if let (Some(duplicates), Some(hash), Some(key)) = (&self.duplicates, &content_hash, &key) {
+            duplicates.set_key(hash, key);
+        }
+        record_in_catalog(
+            &self.catalog,
+            output_path,
+            source_path,
+            path,
+            &processed,
+            dated,
+        );
+        self.checkpoint_catalog();
+
+        if let Ok(mut collected) = self.collected.lock() {
+            if let Some(adjustment) = adjustment {
+                collected.skew_report.push(clock_skew::ReportRow {
+                    source: relative_name.to_string(),
+                    dest: key.clone().unwrap_or_default(),
+                    adjustment: adjustment.clone(),
+                });
+            }
+            if let Some(key) = &key {
+                if options.edit_policy == EditPolicy::Both
+                    && let Some(original) = edits::original_of(path)
+                {
+                    collected.edit_links.push((key.clone(), original));
+                }
+                collected
+                    .placed_keys
+                    .insert(path.to_path_buf(), key.clone());
+            }
+            if processed.action == FileAction::New
+                && let Some(name) = path.file_name()
+            {
+                collected.new_files.push(name.to_string_lossy().to_string());
+            }
+        }
+        if let Ok(mut report) = self.report.lock() {
+            report.record(processed.action, date);
+        }
+        progress::file(&relative_name, processed.action.into(), key.as_deref());
+        self.progress_bar.inc(1);
+    }
+
+    /// Copies a file that is not media under the non-media folder, when one is requested.
+    fn copy_non_media_file(&self, path: &Path, source_root: &Path) {
+        let options = self.options;
+        let relative = path.strip_prefix(source_root).unwrap_or(path);
+        let Some(dir) = &options.non_media_dir else {
+            return;
+        };
+
+        match fs_ops::process_non_media_file(
+            path,
+            relative,
+            self.output_path,
+            dir,
+            options.verify_copies,
+            options.target_fs,
+            self.names.as_ref(),
+        ) {
+            Ok(processed) => {
+                let renamed = CatalogEntry {
+                    archive_name: path
+                        .file_name()
+                        .filter(|name| Some(*name) != processed.dest_path.file_name())
+                        .map(|name| name.to_string_lossy().to_string()),
+                    ..Default::default()
+                };
+                record_in_catalog(
+                    &self.catalog,
+                    self.output_path,
+                    source_root,
+                    path,
+                    &processed,
+                    renamed,
+                );
+                self.checkpoint_catalog();
+            }
+            Err(e) => {
+                error!("Failed to copy non-media file {:?}: {}", path, e);
+                if let Ok(mut report) = self.report.lock() {
+                    report.fail(path, e);
+                }
+            }
+        }
+    }
+}
+
+/// Aborts before extraction when the staging or output filesystem is too small for this run.
+fn check_disk_space(
+    archives: &[PathBuf],
+    directories: &[PathBuf],
+    output_path: &Path,
+    staging_dir: Option<&Path>,
+    catalog: &Catalog,
+) -> Result<()> {
+    let (staging_bytes, output_bytes) = space_needed(archives, directories, catalog);
+
+    let mut requirements = vec![SpaceRequirement {
+        label: "output",
+        path: output_path.to_path_buf(),
+        bytes: output_bytes,
+    }];
+    if staging_bytes > 0 {
+        requirements.push(SpaceRequirement {
+            label: "staging",
+            path: staging_dir
+                .map(Path::to_path_buf)
+                .unwrap_or_else(std::env::temp_dir),
+            bytes: staging_bytes,
+        });
+    }
+
+    disk_space::ensure_space(&requirements)
+}
+
+/// Bytes this run extracts to staging and adds to the output. Files the catalog already
+/// holds, by source path and size, take no new room in the output.
+fn space_needed(archives: &[PathBuf], directories: &[PathBuf], catalog: &Catalog) -> (u64, u64) {
+    let present: HashSet<(&str, u64)> = catalog
+        .entries
+        .values()
+        .map(|e| (e.source.as_str(), e.size))
+        .collect();
+    let new_bytes = |entries: &[(String, u64)]| -> u64 {
+        entries
+            .iter()
+            .filter(|(name, size)| !present.contains(&(name.as_str(), *size)))
+            .map(|(_, size)| size)
+            .sum()
+    };
+
+    let mut staging_bytes = 0;
+    let mut output_bytes = 0;
+    for archive_path in archives {
+        match archive::indexed_entries(archive_path) {
+            Ok(Some(entries)) => {
+                staging_bytes += entries.iter().map(|(_, size)| size).sum::<u64>();
+                output_bytes += new_bytes(&entries);
+            }
+            Ok(None) => {
+                let size = archive::uncompressed_size(archive_path).unwrap_or_default();
+                staging_bytes += size;
+                // Which files are new is unknown without decompressing
+                if present.is_empty() {
+                    output_bytes += size;
+                } else {
+                    info!(
+                        "Not counting {:?} towards the output space: the library may already hold its files",
+                        archive_path
+                    );
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Cannot read the size of {:?} from its headers: {}",
+                    archive_path, e
+                );
+                let size = fs::metadata(archive_path)
+                    .map(|m| m.len())
+                    .unwrap_or_default();
+                staging_bytes += size;
+                output_bytes += size;
+            }
+        }
+    }
+    for directory in directories {
+        output_bytes += new_bytes(&directory_files(directory));
+    }
+    (staging_bytes, output_bytes)
+}
+
+/// Media files under `input_path` with their catalog source path and size. Told apart by
+/// extension alone: the estimate does not need their content sniffed ahead of the run.
+fn directory_files(input_path: &Path) -> Vec<(String, u64)> {
     WalkDir::new(input_path)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| fs_ops::should_process_file(e.path()))
-        .count() as u64
+        .filter(|e| e.file_type().is_file() && sniff::has_media_extension(e.path()))
+        .filter_map(|e| {
+            let size = e.metadata().ok()?.len();
+            Some((catalog_key(input_path, e.path())?, size))
+        })
+        .collect()
+}
+
+/// Records a processed file in the library catalog. Skipped files keep their existing entry,
+/// but are added when missing so libraries organized before the catalog existed catch up.
+fn record_in_catalog(
+    catalog: &Mutex<Catalog>,
+    output_path: &Path,
+    source_root: &Path,
+    source_path: &Path,
+    processed: &ProcessedFile,
+    dated: CatalogEntry,
+) {
+    let Some(key) = catalog_key(output_path, &processed.dest_path) else {
+        return;
+    };
+    let Ok(mut catalog) = catalog.lock() else {
+        return;
+    };
+
+    if processed.action == FileAction::Skipped
+        && let Some(existing) = catalog.entries.get_mut(&key)
+    {
+        for owner in dated.owners {
+            if !existing.owners.contains(&owner) {
+                existing.owners.push(owner);
+            }
+        }
+        return;
+    }
+
+    let size = fs::metadata(&processed.dest_path)
+        .map(|m| m.len())
+        .unwrap_or_default();
+    let source = catalog_key(source_root, source_path)
+        .unwrap_or_else(|| source_path.to_string_lossy().to_string());
+
+    catalog.insert(
+        key,
+        CatalogEntry {
+            size,
+            sha256: processed.checksum.clone().or(dated.sha256.clone()),
+            source,
+            ..dated
+        },
+    );
+}
+
+/// Rewrites the EXIF dates of a fresh copy, keeping its recorded checksum current.
+fn write_corrected_exif(processed: &mut ProcessedFile, adjustment: &clock_skew::SkewAdjustment) {
+    match clock_skew::write_shifted_exif_dates(&processed.dest_path, &adjustment.shift) {
+        Ok(true) => {
+            if processed.checksum.is_some() {
+                processed.checksum = verify::hash_file(&processed.dest_path).ok();
+            }
+        }
+        Ok(false) => {}
+        Err(e) => warn!(
+            "Failed to write corrected EXIF dates to {:?}: {}",
+            processed.dest_path, e
+        ),
+    }
+}
+
+/// Every file under `input_path` with its kind, sniffed in parallel.
+fn classify_files(input_path: &Path) -> Vec<(PathBuf, FileKind)> {
+    use rayon::prelude::*;
+
+    WalkDir::new(input_path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .par_bridge() // Bridge WalkDir (sequential) to parallel iterator
+        .filter_map(|e| {
+            let kind = fs_ops::file_kind(e.path())?;
+            Some((e.into_path(), kind))
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -171,12 +1154,14 @@ mod tests {
 
         // Create a dummy image file with an explicit modified time to force standard date extraction
         let dummy_file = input_dir.join("image.jpg");
-        fs::write(&dummy_file, b"dummy data")?;
+        fs::write(&dummy_file, sniff::fake_jpeg(b"dummy data"))?;
 
         let input_paths = vec![input_dir.as_path()];
 
         // Execute organization
-        organize_files(&input_paths, &output_dir, "unknown")?;
+        let report = organize_files(&input_paths, &output_dir, &OrganizeOptions::default())?;
+        assert_eq!(report.counts.new, 1);
+        assert_eq!(report.outcome(), report::RunOutcome::Success);
 
         // Verify output directory was created and contains the copied file.
         // It might be organized by year/month/day or fall back to "unknown".
@@ -184,7 +1169,7 @@ mod tests {
         let output_files: Vec<_> = WalkDir::new(&output_dir)
             .into_iter()
             .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.file_type().is_file() && e.file_name() != catalog::CATALOG_FILE)
             .map(|e| e.path().file_name().unwrap_or_default().to_os_string())
             .collect();
 
@@ -202,23 +1187,23 @@ mod tests {
         fs::create_dir_all(&input_dir)?;
 
         let dummy_file_1 = input_dir.join("image1.jpg");
-        fs::write(&dummy_file_1, b"dummy data 1")?;
+        fs::write(&dummy_file_1, sniff::fake_jpeg(b"dummy data 1"))?;
 
         // First run
         let input_paths = vec![input_dir.as_path()];
-        organize_files(&input_paths, &output_dir, "unknown")?;
+        organize_files(&input_paths, &output_dir, &OrganizeOptions::default())?;
 
         // Add a second file
         let dummy_file_2 = input_dir.join("image2.jpg");
-        fs::write(&dummy_file_2, b"dummy data 2")?;
+        fs::write(&dummy_file_2, sniff::fake_jpeg(b"dummy data 2"))?;
 
         // Second (incremental) run
-        organize_files(&input_paths, &output_dir, "unknown")?;
+        organize_files(&input_paths, &output_dir, &OrganizeOptions::default())?;
 
         let output_files: Vec<_> = WalkDir::new(&output_dir)
             .into_iter()
             .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.file_type().is_file() && e.file_name() != catalog::CATALOG_FILE)
             .map(|e| e.path().file_name().unwrap_or_default().to_os_string())
             .collect();
 
@@ -228,4 +1213,390 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_space_needed_skips_files_already_in_library() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&input_dir)?;
+        fs::write(
+            input_dir.join("image1.jpg"),
+            sniff::fake_jpeg(b"dummy data 1"),
+        )?;
+        organize_files(
+            &[input_dir.as_path()],
+            &output_dir,
+            &OrganizeOptions::default(),
+        )?;
+
+        let archive_path = temp_dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive_path)?);
+        for (name, content) in [
+            ("image1.jpg", b"dummy data 1".as_slice()),
+            ("image3.jpg", b"new"),
+        ] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())?;
+            std::io::Write::write_all(&mut zip, &sniff::fake_jpeg(content))?;
+        }
+        zip.finish()?;
+        fs::write(
+            input_dir.join("image2.jpg"),
+            sniff::fake_jpeg(b"dummy data 22"),
+        )?;
+
+        let catalog = Catalog::load(&output_dir)?;
+        let (staging, output) = space_needed(&[archive_path], &[input_dir], &catalog);
+        // Everything is extracted, but only image2 and image3 are new to the library
+        assert_eq!(staging, 21);
+        assert_eq!(output, 22);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_with_filters() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(input_dir.join("Hangout: Bob"))?;
+
+        fs::write(
+            input_dir.join("IMG_20160101_120000.jpg"),
+            sniff::fake_jpeg(b"in range"),
+        )?;
+        fs::write(
+            input_dir.join("IMG_20190101_120000.jpg"),
+            sniff::fake_jpeg(b"too late"),
+        )?;
+        fs::write(input_dir.join("anim_20160101.gif"), b"GIF89a")?;
+        fs::write(
+            input_dir.join("Hangout: Bob/IMG_20160101.jpg"),
+            sniff::fake_jpeg(b"hangout"),
+        )?;
+
+        let options = OrganizeOptions {
+            filter: FileFilter::new(
+                &[],
+                &["*.gif".to_string(), "Hangout:*/**".to_string()],
+                filters::MediaFilter::All,
+                Some(filters::parse_filter_date("2015", false)?),
+                Some(filters::parse_filter_date("2018", true)?),
+            )?,
+            ..Default::default()
+        };
+        organize_files(&[input_dir.as_path()], &output_dir, &options)?;
+
+        let output_files: Vec<_> = WalkDir::new(&output_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.file_name() != catalog::CATALOG_FILE)
+            .map(|e| e.path().file_name().unwrap_or_default().to_os_string())
+            .collect();
+
+        assert_eq!(output_files, vec!["IMG_20160101_120000.jpg"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_infers_sequence_dates() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&input_dir)?;
+
+        // 2019-07-04 10:00 and 12:00 UTC
+        for (name, timestamp) in [("IMG_4511.jpg", 1562234400), ("IMG_4513.jpg", 1562241600)] {
+            fs::write(input_dir.join(name), sniff::fake_jpeg(b"dated"))?;
+            fs::write(
+                input_dir.join(format!("{}.json", name)),
+                format!(r#"{{"photoTakenTime": {{"timestamp": "{}"}}}}"#, timestamp),
+            )?;
+        }
+        fs::write(input_dir.join("IMG_4512.jpg"), sniff::fake_jpeg(b"scanned"))?;
+
+        let options = OrganizeOptions {
+            infer_sequence_dates: true,
+            ..Default::default()
+        };
+        organize_files(&[input_dir.as_path()], &output_dir, &options)?;
+
+        assert!(output_dir.join("2019/July/04/IMG_4512.jpg").exists());
+        let catalog = Catalog::load(&output_dir)?;
+        let entry = catalog.get("2019/July/04/IMG_4512.jpg").unwrap();
+        assert_eq!(entry.date_source, Some(DateSource::Inferred));
+        assert_eq!(entry.date.as_deref(), Some("2019-07-04T11:00:00+00:00"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_merges_owner_takeouts() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let alice_dir = temp_dir.path().join("alice");
+        let bob_dir = temp_dir.path().join("bob");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&alice_dir)?;
+        fs::create_dir_all(&bob_dir)?;
+
+        let metadata = r#"{"photoTakenTime": {"timestamp": "1562234400"}}"#;
+        for dir in [&alice_dir, &bob_dir] {
+            fs::write(dir.join("shared.jpg"), sniff::fake_jpeg(b"same pixels"))?;
+            fs::write(dir.join("shared.jpg.json"), metadata)?;
+        }
+        fs::write(bob_dir.join("own.jpg"), sniff::fake_jpeg(b"bob only"))?;
+        fs::write(bob_dir.join("own.jpg.json"), metadata)?;
+
+        let options = OrganizeOptions {
+            owners: BTreeMap::from([
+                (alice_dir.clone(), "alice".to_string()),
+                (bob_dir.clone(), "bob".to_string()),
+            ]),
+            owner_layout: true,
+            ..Default::default()
+        };
+        organize_files(
+            &[alice_dir.as_path(), bob_dir.as_path()],
+            &output_dir,
+            &options,
+        )?;
+
+        assert!(output_dir.join("bob/2019/July/04/own.jpg").exists());
+        // The shared photo is copied once, below whichever account claimed it first
+        let catalog = Catalog::load(&output_dir)?;
+        let shared: Vec<_> = catalog
+            .entries
+            .iter()
+            .filter(|(key, _)| key.ends_with("shared.jpg"))
+            .collect();
+        assert_eq!(shared.len(), 1);
+        let mut owners = shared[0].1.owners.clone();
+        owners.sort();
+        assert_eq!(owners, vec!["alice", "bob"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_merges_owner_takeouts_across_runs() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let alice_dir = temp_dir.path().join("alice");
+        let bob_dir = temp_dir.path().join("bob");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&alice_dir)?;
+        fs::create_dir_all(&bob_dir)?;
+
+        let metadata = r#"{"photoTakenTime": {"timestamp": "1562234400"}}"#;
+        fs::write(
+            alice_dir.join("shared.jpg"),
+            sniff::fake_jpeg(b"same pixels"),
+        )?;
+        fs::write(alice_dir.join("shared.jpg.json"), metadata)?;
+        fs::write(bob_dir.join("IMG_9.jpg"), sniff::fake_jpeg(b"same pixels"))?;
+        fs::write(bob_dir.join("IMG_9.jpg.json"), metadata)?;
+
+        // Each account is organized on its own day
+        for (dir, owner) in [(&alice_dir, "alice"), (&bob_dir, "bob")] {
+            let options = OrganizeOptions {
+                owners: BTreeMap::from([(dir.clone(), owner.to_string())]),
+                ..Default::default()
+            };
+            organize_files(&[dir.as_path()], &output_dir, &options)?;
+        }
+
+        assert!(!output_dir.join("2019/July/04/IMG_9.jpg").exists());
+        let catalog = Catalog::load(&output_dir)?;
+        assert_eq!(catalog.entries.len(), 1);
+        let entry = catalog.get("2019/July/04/shared.jpg").unwrap();
+        assert_eq!(entry.owners, vec!["alice", "bob"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_edit_policy() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir)?;
+        fs::write(input_dir.join("IMG_1.jpg"), sniff::fake_jpeg(b"original"))?;
+        fs::write(
+            input_dir.join("IMG_1-edited.jpg"),
+            sniff::fake_jpeg(b"edited pixels"),
+        )?;
+        // Google only exports a sidecar for the original
+        fs::write(
+            input_dir.join("IMG_1.jpg.json"),
+            r#"{"photoTakenTime": {"timestamp": "1562234400"}}"#,
+        )?;
+
+        let both_dir = temp_dir.path().join("both");
+        organize_files(
+            &[input_dir.as_path()],
+            &both_dir,
+            &OrganizeOptions::default(),
+        )?;
+        assert!(both_dir.join("2019/July/04/IMG_1.jpg").exists());
+        let catalog = Catalog::load(&both_dir)?;
+        let edited = catalog.get("2019/July/04/IMG_1-edited.jpg").unwrap();
+        assert_eq!(edited.edit_of.as_deref(), Some("2019/July/04/IMG_1.jpg"));
+
+        // Another Takeout with the same names links to its own original
+        let other_dir = temp_dir.path().join("other");
+        fs::create_dir_all(&other_dir)?;
+        fs::write(
+            other_dir.join("IMG_1.jpg"),
+            sniff::fake_jpeg(b"other original"),
+        )?;
+        fs::write(
+            other_dir.join("IMG_1-edited.jpg"),
+            sniff::fake_jpeg(b"other edited"),
+        )?;
+        fs::write(
+            other_dir.join("IMG_1.jpg.json"),
+            r#"{"photoTakenTime": {"timestamp": "1262304000"}}"#,
+        )?;
+        organize_files(
+            &[input_dir.as_path(), other_dir.as_path()],
+            &both_dir,
+            &OrganizeOptions::default(),
+        )?;
+        let catalog = Catalog::load(&both_dir)?;
+        let edited = catalog.get("2010/January/01/IMG_1-edited.jpg").unwrap();
+        assert_eq!(edited.edit_of.as_deref(), Some("2010/January/01/IMG_1.jpg"));
+        let edited = catalog.get("2019/July/04/IMG_1-edited.jpg").unwrap();
+        assert_eq!(edited.edit_of.as_deref(), Some("2019/July/04/IMG_1.jpg"));
+
+        let edited_dir = temp_dir.path().join("edited");
+        let options = OrganizeOptions {
+            edit_policy: EditPolicy::Edited,
+            ..Default::default()
+        };
+        organize_files(&[input_dir.as_path()], &edited_dir, &options)?;
+        assert!(edited_dir.join("2019/July/04/IMG_1-edited.jpg").exists());
+        assert!(!edited_dir.join("2019/July/04/IMG_1.jpg").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_restores_titles() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&input_dir)?;
+
+        let sidecar = |title: &str| {
+            format!(
+                r#"{{"title": "{}", "photoTakenTime": {{"timestamp": "1562234400"}}}}"#,
+                title
+            )
+        };
+        fs::write(
+            input_dir.join("Summer holiday at the la.jpg"),
+            sniff::fake_jpeg(b"lake"),
+        )?;
+        fs::write(
+            input_dir.join("Summer holiday at the la.json"),
+            sidecar("Summer holiday at the lake house.jpg"),
+        )?;
+        fs::write(
+            input_dir.join("IMG_0001.jpg"),
+            sniff::fake_jpeg(b"first camera"),
+        )?;
+        fs::write(input_dir.join("IMG_0001.jpg.json"), sidecar("IMG_0001.jpg"))?;
+        fs::write(
+            input_dir.join("IMG_0001(1).jpg"),
+            sniff::fake_jpeg(b"second camera"),
+        )?;
+        fs::write(
+            input_dir.join("IMG_0001.jpg(1).json"),
+            sidecar("IMG_0001.jpg"),
+        )?;
+
+        let options = OrganizeOptions {
+            restore_titles: true,
+            ..Default::default()
+        };
+        organize_files(&[input_dir.as_path()], &output_dir, &options)?;
+
+        let day = output_dir.join("2019/July/04");
+        assert!(day.join("Summer holiday at the lake house.jpg").exists());
+        assert!(day.join("IMG_0001.jpg").exists());
+        assert!(day.join("IMG_0001 (1).jpg").exists());
+        let catalog = Catalog::load(&output_dir)?;
+        let entry = catalog
+            .get("2019/July/04/Summer holiday at the lake house.jpg")
+            .unwrap();
+        assert_eq!(
+            entry.archive_name.as_deref(),
+            Some("Summer holiday at the la.jpg")
+        );
+
+        // A second run keeps every name instead of numbering them again
+        organize_files(&[input_dir.as_path()], &output_dir, &options)?;
+        assert_eq!(fs::read_dir(&day)?.count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_sanitised_non_media_names_do_not_clash() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(input_dir.join("Trip: Paris"))?;
+        fs::create_dir_all(input_dir.join("Trip_ Paris"))?;
+        fs::write(input_dir.join("Trip: Paris/notes?.txt"), "first")?;
+        fs::write(input_dir.join("Trip_ Paris/notes_.txt"), "second")?;
+
+        let options = OrganizeOptions {
+            non_media_dir: Some("other".to_string()),
+            target_fs: TargetFs::Exfat,
+            ..Default::default()
+        };
+        organize_files(&[input_dir.as_path()], &output_dir, &options)?;
+
+        let folder = output_dir.join("other/Trip_ Paris");
+        assert_eq!(fs::read_dir(&folder)?.count(), 2);
+        let catalog = Catalog::load(&output_dir)?;
+        let renamed = catalog
+            .entries
+            .values()
+            .find(|e| e.source == "Trip: Paris/notes?.txt")
+            .unwrap();
+        assert_eq!(renamed.archive_name.as_deref(), Some("notes?.txt"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_organize_files_counter_follows_source_order() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&input_dir)?;
+        // A burst within one second
+        for name in ["IMG_3.jpg", "IMG_1.jpg", "IMG_2.jpg"] {
+            fs::write(input_dir.join(name), sniff::fake_jpeg(name.as_bytes()))?;
+            fs::write(
+                input_dir.join(format!("{}.json", name)),
+                r#"{"photoTakenTime": {"timestamp": "1562234400"}}"#,
+            )?;
+        }
+
+        let options = OrganizeOptions {
+            rename_template: Some("{date}_{counter:2}.{ext_lower}".parse().unwrap()),
+            ..Default::default()
+        };
+        organize_files(&[input_dir.as_path()], &output_dir, &options)?;
+
+        let catalog = Catalog::load(&output_dir)?;
+        for (n, source) in ["IMG_1.jpg", "IMG_2.jpg", "IMG_3.jpg"].iter().enumerate() {
+            let key = format!("2019/July/04/20190704_100000_{:02}.jpg", n + 1);
+            assert_eq!(catalog.get(&key).unwrap().source, *source);
+        }
+
+        Ok(())
+    }
 }
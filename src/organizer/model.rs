@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct GoogleTimestamp {
@@ -8,6 +9,49 @@ pub struct GoogleTimestamp {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PhotoMetadata {
+    pub title: Option<String>,
     pub photo_taken_time: Option<GoogleTimestamp>,
     pub creation_time: Option<GoogleTimestamp>,
 }
+
+/// Where a resolved date came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateSource {
+    /// Set by hand in the overrides file
+    Override,
+    Json,
+    Exif,
+    Filename,
+    FolderHint,
+    ArchiveMtime,
+    FileMtime,
+    /// Interpolated from the dated neighbours of a numbered sequence
+    Inferred,
+    /// Day folder the file sat in within a library organized earlier
+    Library,
+}
+
+impl DateSource {
+    /// Fallback sources only loosely bound the real capture date.
+    pub fn is_low_confidence(self) -> bool {
+        matches!(
+            self,
+            DateSource::FolderHint
+                | DateSource::ArchiveMtime
+                | DateSource::FileMtime
+                | DateSource::Inferred
+        )
+    }
+
+    /// Takeout folder hints only tell the year.
+    pub fn is_year_only(self) -> bool {
+        self == DateSource::FolderHint
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedDate {
+    pub date: DateTime<Utc>,
+    pub source: DateSource,
+}
@@ -0,0 +1,246 @@
+use chrono::format::{Item, StrftimeItems};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::clock_skew;
+use super::metadata;
+use super::model::ResolvedDate;
+use super::titles;
+
+/// Date format of a bare `{date}`.
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Date(String),
+    Camera,
+    Make,
+    Model,
+    Title,
+    OrigStem,
+    Ext,
+    ExtLower,
+    Counter(usize),
+}
+
+/// Output file name pattern such as `{date:%Y%m%d_%H%M%S}_{camera}_{orig_stem}.{ext_lower}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    tokens: Vec<Token>,
+}
+
+/// Everything a template can refer to, read once per file.
+#[derive(Debug, Default)]
+pub struct NameContext {
+    date: Option<ResolvedDate>,
+    make: Option<String>,
+    model: Option<String>,
+    title: Option<String>,
+    stem: String,
+    ext: String,
+}
+
+impl NameContext {
+    /// `file_name` is the name the file would otherwise get (after any extension repair).
+    pub fn read(input_path: &Path, file_name: &str, date: Option<ResolvedDate>) -> Self {
+        let name = Path::new(file_name);
+        let (make, model) = match clock_skew::read_camera(input_path) {
+            Some((make, model)) => (Some(make).filter(|m| !m.is_empty()), Some(model)),
+            None => (None, None),
+        };
+        Self {
+            // Year-only fallbacks say nothing about the day or time
+            date: date.filter(|d| !d.source.is_year_only()),
+            make,
+            model,
+            title: metadata::sidecar_title(input_path).map(|title| {
+                Path::new(&title)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or(title)
+            }),
+            stem: name
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            ext: name
+                .extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                tokens.push(Token::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed '{{' in name template '{}'", s))?;
+            tokens.push(parse_token(&rest[start + 1..start + end])?);
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            tokens.push(Token::Literal(rest.to_string()));
+        }
+
+        if !tokens.iter().any(|t| !matches!(t, Token::Literal(_))) {
+            return Err(format!("name template '{}' has no placeholder", s));
+        }
+        Ok(Self { tokens })
+    }
+}
+
+fn parse_token(token: &str) -> Result<Token, String> {
+    let (name, arg) = match token.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (token, None),
+    };
+    let parsed = match (name, arg) {
+        ("date", format) => {
+            let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("invalid date format '{}'", format));
+            }
+            Token::Date(format.to_string())
+        }
+        ("camera", None) => Token::Camera,
+        ("make", None) => Token::Make,
+        ("model", None) => Token::Model,
+        ("title", None) => Token::Title,
+        ("orig_stem", None) => Token::OrigStem,
+        ("ext", None) => Token::Ext,
+        ("ext_lower", None) => Token::ExtLower,
+        ("counter", width) => Token::Counter(match width {
+            Some(width) => width
+                .parse()
+                .map_err(|_| format!("invalid counter width '{}'", width))?,
+            None => 1,
+        }),
+        _ => {
+            return Err(format!(
+                "unknown placeholder '{{{}}}' (expected date, camera, make, model, title, \
+                 orig_stem, ext, ext_lower or counter)",
+                token
+            ));
+        }
+    };
+    Ok(parsed)
+}
+
+impl NameTemplate {
+    /// Whether names are numbered with `{counter}`, which then depends on the order files
+    /// are named in.
+    pub fn has_counter(&self) -> bool {
+        self.tokens.iter().any(|t| matches!(t, Token::Counter(_)))
+    }
+
+    /// The `n`-th name to try for a file (0 first). Templates with `{counter}` count bursts
+    /// from 1; others add ` (n)` like any other name clash.
+    pub fn candidate(&self, context: &NameContext, n: usize) -> String {
+        let name = self.render(context, n + 1);
+        if self.has_counter() {
+            name
+        } else {
+            titles::numbered(&name, n)
+        }
+    }
+
+    fn render(&self, context: &NameContext, counter: usize) -> String {
+        let unknown = || "unknown".to_string();
+        let mut name = String::new();
+        for token in &self.tokens {
+            let part = match token {
+                Token::Literal(text) => text.clone(),
+                // UTC, like the date folders
+                Token::Date(format) => match &context.date {
+                    Some(date) => date.date.format(format).to_string(),
+                    None => "undated".to_string(),
+                },
+                Token::Camera => context
+                    .model
+                    .as_deref()
+                    .map(|model| model.replace(' ', "-"))
+                    .unwrap_or_else(unknown),
+                Token::Make => context.make.clone().unwrap_or_else(unknown),
+                Token::Model => context.model.clone().unwrap_or_else(unknown),
+                Token::Title => context.title.clone().unwrap_or(context.stem.clone()),
+                Token::OrigStem => context.stem.clone(),
+                Token::Ext => context.ext.clone(),
+                Token::ExtLower => context.ext.to_lowercase(),
+                Token::Counter(width) => format!("{:0width$}", counter, width = *width),
+            };
+            name.push_str(&part);
+        }
+        // Made valid for the target filesystem where the name is claimed
+        name.trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::model::DateSource;
+    use chrono::{TimeZone, Utc};
+
+    fn context() -> NameContext {
+        NameContext {
+            date: Some(ResolvedDate {
+                date: Utc.with_ymd_and_hms(2019, 7, 4, 10, 30, 5).unwrap(),
+                source: DateSource::Exif,
+            }),
+            make: Some("Canon".to_string()),
+            model: Some("Canon EOS 80D".to_string()),
+            stem: "IMG_0001".to_string(),
+            ext: "JPG".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template: NameTemplate = "{date:%Y%m%d_%H%M%S}_{camera}_{orig_stem}.{ext_lower}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.candidate(&context(), 0),
+            "20190704_103005_Canon-EOS-80D_IMG_0001.jpg"
+        );
+        assert_eq!(
+            template.candidate(&context(), 1),
+            "20190704_103005_Canon-EOS-80D_IMG_0001 (1).jpg"
+        );
+
+        let undated = NameContext {
+            date: None,
+            ..context()
+        };
+        assert_eq!(
+            template.candidate(&undated, 0),
+            "undated_Canon-EOS-80D_IMG_0001.jpg"
+        );
+    }
+
+    #[test]
+    fn test_counter_numbers_bursts() {
+        let template: NameTemplate = "{date}_{counter:2}.{ext}".parse().unwrap();
+        assert_eq!(template.candidate(&context(), 0), "20190704_103005_01.JPG");
+        assert_eq!(template.candidate(&context(), 1), "20190704_103005_02.JPG");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!("{date:%Q}.jpg".parse::<NameTemplate>().is_err());
+        assert!("{lens}.jpg".parse::<NameTemplate>().is_err());
+        assert!("{date.jpg".parse::<NameTemplate>().is_err());
+        assert!("photo.jpg".parse::<NameTemplate>().is_err());
+    }
+}
@@ -0,0 +1,446 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use globset::{GlobBuilder, GlobMatcher};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use super::cancel;
+use super::catalog::{Catalog, CatalogEntry, catalog_key};
+use super::fs_ops;
+use super::model::{DateSource, ResolvedDate};
+use super::verify::hash_file;
+
+/// Dates fixed by hand, which take precedence over every other date source.
+///
+/// Files are matched by content hash first, then by their path relative to the Takeout
+/// root, then by the first matching glob.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    by_hash: HashMap<String, DateTime<Utc>>,
+    by_path: HashMap<String, DateTime<Utc>>,
+    globs: Vec<(GlobMatcher, DateTime<Utc>)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OverridesFile {
+    #[serde(default, rename = "override")]
+    overrides: Vec<OverrideRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideRule {
+    hash: Option<String>,
+    path: Option<String>,
+    glob: Option<String>,
+    date: String,
+}
+
+impl Overrides {
+    /// Loads a `.toml` file of `[[override]]` tables, or a CSV file of `target,date` lines
+    /// where the target is a path, `sha256:<hex>` or `glob:<pattern>`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read overrides file {:?}", path))?;
+
+        let is_toml = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        let overrides = if is_toml {
+            Self::parse_toml(&content)
+        } else {
+            Self::parse_csv(&content)
+        }
+        .with_context(|| format!("Invalid overrides file {:?}", path))?;
+
+        info!(
+            "Loaded {} date override(s) from {:?}",
+            overrides.len(),
+            path
+        );
+        Ok(overrides)
+    }
+
+    pub fn parse_toml(content: &str) -> Result<Self> {
+        let file: OverridesFile = toml::from_str(content)?;
+        let mut overrides = Self::default();
+        for rule in file.overrides {
+            let date = parse_override_date(&rule.date)?;
+            match (rule.hash, rule.path, rule.glob) {
+                (Some(hash), None, None) => overrides.add_hash(&hash, date),
+                (None, Some(path), None) => overrides.add_path(&path, date),
+                (None, None, Some(glob)) => overrides.add_glob(&glob, date)?,
+                _ => bail!(
+                    "Override for {} needs exactly one of hash, path or glob",
+                    rule.date
+                ),
+            }
+        }
+        Ok(overrides)
+    }
+
+    pub fn parse_csv(content: &str) -> Result<Self> {
+        let mut overrides = Self::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // Paths may contain commas, dates never do
+            let Some((target, date)) = line.rsplit_once(',') else {
+                bail!("Line {}: expected `target,date`", number + 1);
+            };
+            let (target, date) = (target.trim().trim_matches('"'), date.trim());
+            if number == 0 && date.eq_ignore_ascii_case("date") {
+                continue;
+            }
+
+            let date = parse_override_date(date).with_context(|| format!("Line {}", number + 1))?;
+            if let Some(hash) = target.strip_prefix("sha256:") {
+                overrides.add_hash(hash, date);
+            } else if let Some(glob) = target.strip_prefix("glob:") {
+                overrides.add_glob(glob, date)?;
+            } else {
+                overrides.add_path(target, date);
+            }
+        }
+        Ok(overrides)
+    }
+
+    pub fn add_hash(&mut self, hash: &str, date: DateTime<Utc>) {
+        self.by_hash.insert(hash.trim().to_lowercase(), date);
+    }
+
+    pub fn add_path(&mut self, path: &str, date: DateTime<Utc>) {
+        self.by_path
+            .insert(path.trim_start_matches("./").replace('\\', "/"), date);
+    }
+
+    pub fn add_glob(&mut self, pattern: &str, date: DateTime<Utc>) -> Result<()> {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("Invalid glob pattern '{}'", pattern))?;
+        self.globs.push((glob.compile_matcher(), date));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_hash.len() + self.by_path.len() + self.globs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Looks up the override for a file by its Takeout-relative path. `hash` is only called
+    /// when hash rules exist, since hashing every file is expensive.
+    pub fn lookup(
+        &self,
+        relative_path: &Path,
+        hash: impl FnOnce() -> Option<String>,
+    ) -> Option<ResolvedDate> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let by_hash = || {
+            if self.by_hash.is_empty() {
+                return None;
+            }
+            hash().and_then(|h| self.by_hash.get(&h).copied())
+        };
+        let key = path_key(relative_path);
+
+        by_hash()
+            .or_else(|| self.by_path.get(&key).copied())
+            .or_else(|| {
+                self.globs
+                    .iter()
+                    .find(|(glob, _)| glob.is_match(&key))
+                    .map(|(_, date)| *date)
+            })
+            .map(|date| ResolvedDate {
+                date,
+                source: DateSource::Override,
+            })
+    }
+}
+
+/// Accepts `YYYY-MM-DD`, `YYYY-MM-DD HH:MM[:SS]` (UTC) or a full RFC 3339 timestamp.
+pub fn parse_override_date(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(date.and_utc());
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|d| d.and_utc())
+        .with_context(|| format!("Invalid override date '{}'", value))
+}
+
+fn path_key(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    /// Files moved to the folder of their new date.
+    pub moved: usize,
+    /// Files already in place whose catalog date was updated.
+    pub retagged: usize,
+    pub errors: usize,
+}
+
+/// Moves already-organized files whose override date differs from the date they were
+/// organized with, and records the new date in the catalog.
+pub fn apply_overrides(root: &Path, overrides: &Overrides) -> Result<ApplyReport> {
+    let mut catalog = Catalog::load(root)?;
+    let mut report = ApplyReport::default();
+
+    let keys: Vec<String> = catalog.entries.keys().cloned().collect();
+    for key in keys {
+        if cancel::is_cancelled() {
+            warn!("Cancelled; the files moved so far are recorded in the catalog");
+            break;
+        }
+        let Some(entry) = catalog.get(&key).cloned() else {
+            continue;
+        };
+        let current_path = root.join(&key);
+        if !fs_ops::should_process_file(&current_path) {
+            continue;
+        }
+
+        let Some(resolved) = overrides.lookup(Path::new(&entry.source), || {
+            entry
+                .sha256
+                .clone()
+                .or_else(|| hash_file(&current_path).ok())
+        }) else {
+            continue;
+        };
+
+        if entry.date.as_deref() == Some(resolved.date.to_rfc3339().as_str())
+            && entry.date_source == Some(DateSource::Override)
+        {
+            continue;
+        }
+
+        match move_to_date(root, &mut catalog, &key, resolved) {
+            Ok(true) => report.moved += 1,
+            Ok(false) => report.retagged += 1,
+            Err(e) => {
+                warn!("{}", e);
+                report.errors += 1;
+            }
+        }
+    }
+
+    catalog.save(root)?;
+    Ok(report)
+}
+
+/// Gives the catalog entry `key` a hand-set date, moving the file into that date's folder.
+/// Returns whether the file moved (it may already be in the right folder).
+pub fn move_to_date(
+    root: &Path,
+    catalog: &mut Catalog,
+    key: &str,
+    resolved: ResolvedDate,
+) -> Result<bool> {
+    let entry = catalog
+        .get(key)
+        .cloned()
+        .with_context(|| format!("{} is not in the catalog", key))?;
+    let current_path = root.join(key);
+    let filename = current_path
+        .file_name()
+        .with_context(|| format!("Invalid catalog key {}", key))?;
+    // Files organized with --owner-layout stay below their owner's folder
+    let base = match entry.owners.first() {
+        Some(owner) if key.starts_with(&format!("{}/", owner)) => root.join(owner),
+        _ => root.to_path_buf(),
+    };
+    let new_path = fs_ops::dated_folder(&base, resolved.date).join(filename);
+    let updated = CatalogEntry {
+        date: Some(resolved.date.to_rfc3339()),
+        date_source: Some(DateSource::Override),
+        folder_year: None,
+        ..entry
+    };
+
+    if new_path == current_path {
+        catalog.insert(key.to_string(), updated);
+        return Ok(false);
+    }
+
+    fs_ops::relocate_file(&current_path, &new_path)?;
+    info!("Moved {} -> {:?}", key, new_path);
+    catalog.entries.remove(key);
+    if let Some(new_key) = catalog_key(root, &new_path) {
+        catalog.relink_edits(&HashMap::from([(key.to_string(), new_key.clone())]));
+        catalog.insert(new_key, updated);
+    }
+    Ok(true)
+}
+
+/// Appends a path override to an overrides file, creating it if needed.
+pub fn append_override(file: &Path, source: &str, date: DateTime<Utc>) -> Result<()> {
+    let is_toml = file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+    let line = if is_toml {
+        format!(
+            "\n[[override]]\npath = {}\ndate = \"{}\"\n",
+            toml::Value::String(source.to_string()),
+            date.to_rfc3339()
+        )
+    } else if file.exists() {
+        format!("{},{}\n", source, date.to_rfc3339())
+    } else {
+        format!("target,date\n{},{}\n", source, date.to_rfc3339())
+    };
+
+    let mut out = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .with_context(|| format!("Failed to open overrides file {:?}", file))?;
+    out.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CATALOG_FILE;
+    use crate::organizer::sniff;
+
+    fn new_date() -> DateTime<Utc> {
+        parse_override_date("1999-12-24").unwrap()
+    }
+
+    #[test]
+    fn test_parse_csv_and_lookup() {
+        let overrides = Overrides::parse_csv(
+            "target,date\n\
+             # scanned prints\n\
+             Takeout/Google Photos/Scans, 1987/print.jpg,1987-06-01\n\
+             sha256:ABC123,2001-02-03 10:00\n\
+             glob:**/Old Camera/**,2004-01-01\n",
+        )
+        .unwrap();
+        assert_eq!(overrides.len(), 3);
+
+        let date = |p: &str, hash: Option<&str>| {
+            overrides
+                .lookup(Path::new(p), || hash.map(str::to_string))
+                .map(|r| r.date.to_rfc3339())
+        };
+        assert_eq!(
+            date("Takeout/Google Photos/Scans, 1987/print.jpg", None),
+            Some("1987-06-01T00:00:00+00:00".to_string())
+        );
+        assert_eq!(
+            date("Takeout/Google Photos/Old Camera/img.jpg", Some("abc123")),
+            Some("2001-02-03T10:00:00+00:00".to_string())
+        );
+        assert_eq!(
+            date("Takeout/Google Photos/old camera/img.jpg", None),
+            Some("2004-01-01T00:00:00+00:00".to_string())
+        );
+        assert_eq!(date("Takeout/Google Photos/img.jpg", None), None);
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let overrides = Overrides::parse_toml(
+            r#"
+            [[override]]
+            path = "Takeout/Google Photos/img.jpg"
+            date = "2010-05-06T07:08:09+02:00"
+
+            [[override]]
+            glob = "**/Scans/**"
+            date = "1995-01-01"
+            "#,
+        )
+        .unwrap();
+        let resolved = overrides
+            .lookup(Path::new("Takeout/Google Photos/img.jpg"), || None)
+            .unwrap();
+        assert_eq!(resolved.source, DateSource::Override);
+        assert_eq!(resolved.date.to_rfc3339(), "2010-05-06T05:08:09+00:00");
+
+        let ambiguous = "[[override]]\npath = \"a.jpg\"\nglob = \"*.jpg\"\ndate = \"2010-01-01\"";
+        assert!(Overrides::parse_toml(ambiguous).is_err());
+    }
+
+    #[test]
+    fn test_apply_overrides_moves_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let old_dir = root.join("unknown");
+        fs::create_dir_all(old_dir.join(".thumbnails")).unwrap();
+        fs::write(old_dir.join("scan.jpg"), sniff::fake_jpeg(b"pixels")).unwrap();
+        fs::write(old_dir.join(".thumbnails/scan.jpg.jpg"), "thumb").unwrap();
+
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "unknown/scan.jpg".to_string(),
+            CatalogEntry {
+                size: 6,
+                source: "Takeout/Google Photos/scan.jpg".to_string(),
+                ..Default::default()
+            },
+        );
+        catalog.save(root).unwrap();
+
+        let overrides =
+            Overrides::parse_csv("Takeout/Google Photos/scan.jpg,1999-12-24\n").unwrap();
+        let report = apply_overrides(root, &overrides).unwrap();
+        assert_eq!(report.moved, 1);
+
+        let new_path = root.join("1999/December/24/scan.jpg");
+        assert!(new_path.exists());
+        assert!(
+            root.join("1999/December/24/.thumbnails/scan.jpg.jpg")
+                .exists()
+        );
+        assert!(!old_dir.join("scan.jpg").exists());
+
+        let catalog = Catalog::load(root).unwrap();
+        let entry = catalog.get("1999/December/24/scan.jpg").unwrap();
+        assert_eq!(entry.date_source, Some(DateSource::Override));
+        assert!(root.join(CATALOG_FILE).exists());
+
+        // Appended overrides are read back
+        let file = dir.path().join("overrides.toml");
+        append_override(&file, "Takeout/a \"b\".jpg", new_date()).unwrap();
+        append_override(&file, "Takeout/c.jpg", new_date()).unwrap();
+        let appended = Overrides::load(&file).unwrap();
+        assert!(
+            appended
+                .lookup(Path::new("Takeout/a \"b\".jpg"), || None)
+                .is_some()
+        );
+        assert_eq!(appended.len(), 2);
+
+        // A second run finds nothing to do
+        let report = apply_overrides(root, &overrides).unwrap();
+        assert_eq!(report.moved + report.retagged, 0);
+    }
+}
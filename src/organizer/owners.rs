@@ -0,0 +1,172 @@
+use anyhow::{Result, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::catalog::Catalog;
+
+/// Splits an `owner=path` input. Inputs without an owner label (or whose full text is an
+/// existing path) are returned unlabelled.
+pub fn parse_owned_input(value: &str) -> Result<(Option<String>, PathBuf)> {
+    if Path::new(value).exists() {
+        return Ok((None, PathBuf::from(value)));
+    }
+
+    match value.split_once('=') {
+        Some((owner, path)) if !owner.contains(['/', '\\']) => {
+            if !is_valid_owner(owner) {
+                bail!(
+                    "Invalid owner '{}' (use letters, digits, '.', '-' or '_')",
+                    owner
+                );
+            }
+            Ok((Some(owner.to_string()), PathBuf::from(path)))
+        }
+        _ => Ok((None, PathBuf::from(value))),
+    }
+}
+
+/// Owners become folder names and HTML attributes, so they are kept to a safe alphabet.
+pub fn is_valid_owner(owner: &str) -> bool {
+    !owner.is_empty()
+        && !owner.starts_with('.')
+        && owner
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    owner: Option<String>,
+    /// Catalog key of the copy, once it is placed.
+    key: Option<String>,
+    /// Other accounts that had the same content.
+    extra_owners: BTreeSet<String>,
+}
+
+/// Content hashes seen across accounts, so an item shared between several Takeouts is
+/// copied once and tagged with every owner.
+#[derive(Debug, Default)]
+pub struct DuplicateIndex {
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+impl DuplicateIndex {
+    /// Seeds the index with the hashed files of an existing library.
+    pub fn from_catalog(catalog: &Catalog) -> Self {
+        let slots = catalog
+            .entries
+            .iter()
+            .filter_map(|(key, entry)| {
+                let hash = entry.sha256.clone()?;
+                Some((
+                    hash,
+                    Slot {
+                        owner: entry.owners.first().cloned(),
+                        key: Some(key.clone()),
+                        extra_owners: BTreeSet::new(),
+                    },
+                ))
+            })
+            .collect();
+        Self {
+            slots: Mutex::new(slots),
+        }
+    }
+
+    /// Claims `hash` for `owner`. Returns `false` when another account already provided the
+    /// same content, in which case the owner is recorded on that copy instead.
+    pub fn claim(&self, hash: &str, owner: &str) -> bool {
+        let Ok(mut slots) = self.slots.lock() else {
+            return true;
+        };
+        match slots.get_mut(hash) {
+            None => {
+                slots.insert(
+                    hash.to_string(),
+                    Slot {
+                        owner: Some(owner.to_string()),
+                        ..Default::default()
+                    },
+                );
+                true
+            }
+            Some(slot) if slot.owner.as_deref() == Some(owner) => true,
+            Some(slot) => {
+                slot.extra_owners.insert(owner.to_string());
+                false
+            }
+        }
+    }
+
+    pub fn set_key(&self, hash: &str, key: &str) {
+        if let Ok(mut slots) = self.slots.lock()
+            && let Some(slot) = slots.get_mut(hash)
+            && slot.key.is_none()
+        {
+            slot.key = Some(key.to_string());
+        }
+    }
+
+    /// Adds the collapsed owners to the catalog entries of the kept copies.
+    pub fn merge_into(self, catalog: &mut Catalog) {
+        let slots = self.slots.into_inner().unwrap_or_default();
+        for slot in slots.into_values() {
+            if let Some(key) = slot.key
+                && let Some(entry) = catalog.entries.get_mut(&key)
+            {
+                for owner in slot.extra_owners {
+                    if !entry.owners.contains(&owner) {
+                        entry.owners.push(owner);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CatalogEntry;
+
+    #[test]
+    fn test_parse_owned_input() {
+        assert_eq!(
+            parse_owned_input("alice=/downloads/takeout-alice.zip").unwrap(),
+            (
+                Some("alice".to_string()),
+                PathBuf::from("/downloads/takeout-alice.zip")
+            )
+        );
+        assert_eq!(
+            parse_owned_input("/downloads/a=b.zip").unwrap(),
+            (None, PathBuf::from("/downloads/a=b.zip"))
+        );
+        assert!(parse_owned_input("al ice=x.zip").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_index_collapses_across_owners() {
+        let index = DuplicateIndex::default();
+        assert!(index.claim("h1", "alice"));
+        // Same account twice (album and year folder) is not a cross-account duplicate
+        assert!(index.claim("h1", "alice"));
+        assert!(!index.claim("h1", "bob"));
+        index.set_key("h1", "2020/May/01/shared.jpg");
+
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2020/May/01/shared.jpg".to_string(),
+            CatalogEntry {
+                owners: vec!["alice".to_string()],
+                ..Default::default()
+            },
+        );
+        index.merge_into(&mut catalog);
+        assert_eq!(
+            catalog.get("2020/May/01/shared.jpg").unwrap().owners,
+            vec!["alice", "bob"]
+        );
+    }
+}
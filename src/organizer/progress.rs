@@ -0,0 +1,204 @@
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::fs_ops::FileAction;
+use super::report::{ActionCounts, RunOutcome};
+use super::ui;
+
+/// Where JSON progress events go; nothing is emitted while unset.
+static SINK: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Progress bars on a terminal
+    #[default]
+    Bar,
+    /// One JSON event per line, for front-ends
+    Json,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bar" => Ok(ProgressMode::Bar),
+            "json" => Ok(ProgressMode::Json),
+            _ => Err(format!(
+                "invalid progress mode '{}' (expected bar or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// What happened to one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileResult {
+    New,
+    Updated,
+    AlreadyPresent,
+    /// Left out by a filter or the edit policy
+    Excluded,
+    /// Same content as a file of another account
+    Duplicate,
+    Failed,
+    Thumbnail,
+    Transcoded,
+}
+
+impl From<FileAction> for FileResult {
+    fn from(action: FileAction) -> Self {
+        match action {
+            FileAction::New => FileResult::New,
+            FileAction::Updated => FileResult::Updated,
+            FileAction::Skipped => FileResult::AlreadyPresent,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    PhaseStart {
+        phase: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<u64>,
+    },
+    PhaseEnd {
+        phase: &'a str,
+        seconds: f64,
+    },
+    File {
+        path: &'a str,
+        result: FileResult,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dest: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    },
+    /// Parallel transcodes running and allowed, from [`crate::gallery`]'s memory throttle
+    Throttle {
+        active: usize,
+        limit: usize,
+        free_memory_percent: u64,
+    },
+    Totals {
+        outcome: RunOutcome,
+        counts: &'a ActionCounts,
+    },
+}
+
+/// Sets up progress reporting: in JSON mode bars are hidden and events are written to
+/// standard output, or to the TCP `socket` a front-end listens on.
+pub fn init(mode: ProgressMode, socket: Option<&str>) -> Result<()> {
+    let sink: Box<dyn Write + Send> = match (mode, socket) {
+        (ProgressMode::Bar, None) => return Ok(()),
+        (ProgressMode::Bar, Some(_)) => bail!("--progress-socket needs --progress json"),
+        (ProgressMode::Json, None) => Box::new(io::stdout()),
+        (ProgressMode::Json, Some(address)) => Box::new(
+            TcpStream::connect(address)
+                .with_context(|| format!("Failed to connect to progress socket {}", address))?,
+        ),
+    };
+    ui::disable_bars();
+    if let Ok(mut current) = SINK.lock() {
+        *current = Some(sink);
+    }
+    Ok(())
+}
+
+/// Writes `event` as one JSON line when JSON progress is on.
+pub fn emit(event: &Event) {
+    if let Ok(mut sink) = SINK.lock()
+        && let Some(out) = sink.as_mut()
+        && let Ok(line) = serde_json::to_string(event)
+    {
+        // A front-end that went away must not stop the run
+        let _ = writeln!(out, "{}", line).and_then(|()| out.flush());
+    }
+}
+
+pub fn file(path: &str, result: FileResult, dest: Option<&str>) {
+    emit(&Event::File {
+        path,
+        result,
+        dest,
+        error: None,
+    });
+}
+
+pub fn failed(path: &str, error: &str) {
+    emit(&Event::File {
+        path,
+        result: FileResult::Failed,
+        dest: None,
+        error: Some(error),
+    });
+}
+
+/// A running phase, reported when it starts and when [`Phase::finish`] is called.
+pub struct Phase {
+    name: &'static str,
+    started: Instant,
+}
+
+impl Phase {
+    pub fn start(name: &'static str, total: Option<u64>) -> Self {
+        emit(&Event::PhaseStart { phase: name, total });
+        Self {
+            name,
+            started: Instant::now(),
+        }
+    }
+
+    /// Reports the end of the phase and returns how long it took.
+    pub fn finish(self) -> Duration {
+        let elapsed = self.started.elapsed();
+        emit(&Event::PhaseEnd {
+            phase: self.name,
+            seconds: elapsed.as_secs_f64(),
+        });
+        elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_lines() {
+        let line = |event: &Event| serde_json::to_string(event).unwrap();
+        assert_eq!(
+            line(&Event::PhaseStart {
+                phase: "organize",
+                total: Some(3)
+            }),
+            r#"{"event":"phase-start","phase":"organize","total":3}"#
+        );
+        assert_eq!(
+            line(&Event::File {
+                path: "Photos from 2019/a.jpg",
+                result: FileResult::AlreadyPresent,
+                dest: Some("2019/July/04/a.jpg"),
+                error: None,
+            }),
+            r#"{"event":"file","path":"Photos from 2019/a.jpg","result":"already-present","dest":"2019/July/04/a.jpg"}"#
+        );
+        assert_eq!(
+            line(&Event::Throttle {
+                active: 1,
+                limit: 2,
+                free_memory_percent: 45
+            }),
+            r#"{"event":"throttle","active":1,"limit":2,"free_memory_percent":45}"#
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use log::{info, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::OrganizeOptions;
+use super::cancel;
+use super::catalog::{Catalog, CatalogEntry, catalog_key};
+use super::date_utils::naive_to_utc;
+use super::fs_ops::{self, FileKind};
+use super::metadata::DateExtractor;
+use super::model::{DateSource, ResolvedDate};
+use super::owners;
+use super::sniff::ContentType;
+use super::titles::NameRegistry;
+use super::verify::{hash_file, is_generated};
+
+#[derive(Debug, Default)]
+pub struct ReorganizeReport {
+    pub moved: usize,
+    pub unchanged: usize,
+    pub errors: usize,
+    /// Folders that lost or gained files, whose gallery pages are stale.
+    pub affected_dirs: BTreeSet<PathBuf>,
+}
+
+/// Moves every media file of the library at `root` to where the current layout and naming
+/// settings put it, re-resolving its date from the overrides, the catalog or the file itself.
+pub fn reorganize(root: &Path, options: &OrganizeOptions) -> Result<ReorganizeReport> {
+    let mut catalog = Catalog::load(root)?;
+    let extractor = DateExtractor::new()?.with_fallbacks(options.date_fallbacks.clone());
+    let names = NameRegistry::from_catalog(root, &catalog)
+        .case_insensitive(options.target_fs.is_case_insensitive());
+    let mut report = ReorganizeReport::default();
+
+    // Listed up front so files moved further down the tree are not visited twice
+    let non_media_dir = options.non_media_dir.as_ref().map(|dir| root.join(dir));
+    let mut files: Vec<(PathBuf, Option<ContentType>)> = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| {
+            let hidden = e.depth() > 0 && e.file_name().to_string_lossy().starts_with('.');
+            !hidden && non_media_dir.as_deref() != Some(e.path())
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| match fs_ops::file_kind(e.path()) {
+            Some(FileKind::Media(content)) => Some((e.into_path(), content)),
+            _ => None,
+        })
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // Old and new keys of the moved files, for the edited copies linked to them
+    let mut moved = HashMap::new();
+    for (path, content) in files {
+        if cancel::is_cancelled() {
+            warn!("Cancelled; the files moved so far are recorded in the catalog");
+            break;
+        }
+        let Some(key) = catalog_key(root, &path) else {
+            continue;
+        };
+        let entry = catalog.entries.remove(&key);
+        let tracked = entry.is_some();
+        let entry = entry.unwrap_or_else(|| CatalogEntry {
+            size: fs::metadata(&path).map(|m| m.len()).unwrap_or_default(),
+            source: untracked_source(&key, options),
+            ..Default::default()
+        });
+
+        match reorganize_file(root, &path, content, &entry, &extractor, &names, options) {
+            Ok((dest, date, name_changed)) => {
+                let updated = CatalogEntry {
+                    date: date.map(|d| d.date.to_rfc3339()),
+                    date_source: date.map(|d| d.source),
+                    archive_name: name_changed.or(entry.archive_name.clone()),
+                    ..entry
+                };
+                let updated_key = catalog_key(root, &dest).unwrap_or_else(|| key.clone());
+                if updated_key != key {
+                    moved.insert(key, updated_key.clone());
+                }
+                // Files missing from the catalog join it, so later runs know their date
+                catalog.insert(updated_key, updated);
+                if dest == path {
+                    report.unchanged += 1;
+                    continue;
+                }
+                info!("Moved {:?} -> {:?}", path, dest);
+                report.moved += 1;
+                report.affected_dirs.extend(
+                    path.parent()
+                        .into_iter()
+                        .chain(dest.parent())
+                        .map(Path::to_path_buf),
+                );
+            }
+            Err(e) => {
+                warn!("{}", e);
+                report.errors += 1;
+                if tracked {
+                    catalog.insert(key, entry);
+                }
+            }
+        }
+    }
+
+    catalog.relink_edits(&moved);
+    for dir in report.affected_dirs.clone() {
+        remove_emptied_dirs(root, &dir);
+    }
+    catalog.save(root)?;
+    Ok(report)
+}
+
+/// Moves one file to its place under the current settings. Returns the new path, the date it
+/// was placed by and, when the file got a new name, the name it had in the Takeout.
+fn reorganize_file(
+    root: &Path,
+    path: &Path,
+    content: Option<ContentType>,
+    entry: &CatalogEntry,
+    extractor: &DateExtractor,
+    names: &NameRegistry,
+    options: &OrganizeOptions,
+) -> Result<(PathBuf, Option<ResolvedDate>, Option<String>)> {
+    let relative = Path::new(&entry.source);
+    let date = resolve_date(root, path, entry, extractor, options);
+
+    let base = match entry.owners.first() {
+        Some(owner) if options.owner_layout && owners::is_valid_owner(owner) => root.join(owner),
+        _ => root.to_path_buf(),
+    };
+    let folder = fs_ops::destination_folder(path, relative, &base, date, options);
+    let archive_name = entry
+        .archive_name
+        .clone()
+        .map(OsString::from)
+        .or_else(|| path.file_name().map(|n| n.to_os_string()))
+        .unwrap_or_default();
+    let repaired = fs_ops::repaired_file_name(path, &archive_name, content, options);
+    let name = fs_ops::output_file_name(
+        path,
+        &repaired,
+        relative,
+        &folder,
+        date,
+        options,
+        Some(names),
+    );
+    let dest = folder.join(&name);
+
+    if dest != path {
+        fs_ops::relocate_file(path, &dest)?;
+    }
+    let renamed = (name != archive_name).then(|| archive_name.to_string_lossy().to_string());
+    Ok((dest, date, renamed))
+}
+
+/// Overrides win, then the file's own metadata (its sidecar date kept in the catalog, EXIF,
+/// filename), then the date it was organized with, then the day folder it already sits in,
+/// then the configured fallbacks. A date corrected for clock skew is kept as it is, since the
+/// file's EXIF may still hold the skewed one.
+fn resolve_date(
+    root: &Path,
+    path: &Path,
+    entry: &CatalogEntry,
+    extractor: &DateExtractor,
+    options: &OrganizeOptions,
+) -> Option<ResolvedDate> {
+    options
+        .overrides
+        .lookup(Path::new(&entry.source), || {
+            entry.sha256.clone().or_else(|| hash_file(path).ok())
+        })
+        .or_else(|| {
+            entry
+                .original_date
+                .as_ref()
+                .and_then(|_| catalog_date(entry))
+        })
+        .or_else(|| {
+            let date = DateTime::parse_from_rfc3339(entry.sidecar_date.as_deref()?).ok()?;
+            Some(ResolvedDate {
+                date: date.with_timezone(&Utc),
+                source: DateSource::Json,
+            })
+        })
+        .or_else(|| extractor.candidates(path).into_iter().next())
+        .or_else(|| catalog_date(entry))
+        .or_else(|| {
+            let relative = path.strip_prefix(root).ok()?.parent()?;
+            library_date(relative).map(|date| ResolvedDate {
+                date,
+                source: DateSource::Library,
+            })
+        })
+        .or_else(|| extractor.determine_date(path, false))
+}
+
+fn catalog_date(entry: &CatalogEntry) -> Option<ResolvedDate> {
+    let date = DateTime::parse_from_rfc3339(entry.date.as_deref()?).ok()?;
+    Some(ResolvedDate {
+        date: date.with_timezone(&Utc),
+        // Catalogs written before sources were recorded only held sidecar or EXIF dates
+        source: entry.date_source.unwrap_or(DateSource::Json),
+    })
+}
+
+/// Date of a `YYYY/MM/DD` or `YYYY/Month/DD` run of folders anywhere in `relative`.
+fn library_date(relative: &Path) -> Option<DateTime<Utc>> {
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    parts.windows(3).rev().find_map(|window| {
+        let [year, month, day] = window else {
+            return None;
+        };
+        if year.len() != 4 || day.len() > 2 {
+            return None;
+        }
+        let month = month.parse().ok().or_else(|| {
+            NaiveDate::parse_from_str(&format!("{} 1 2000", month), "%B %d %Y")
+                .ok()
+                .map(|d| d.month())
+        })?;
+        naive_to_utc(year.parse().ok()?, month, day.parse().ok()?)
+    })
+}
+
+/// Takeout path guessed for a file missing from the catalog: its library path without the
+/// unknown folder it was left in.
+fn untracked_source(key: &str, options: &OrganizeOptions) -> String {
+    key.strip_prefix(&format!("{}/", options.unknown_dir))
+        .unwrap_or(key)
+        .to_string()
+}
+
+/// Deletes `dir` and its parents below `root` while they hold nothing but gallery files.
+fn remove_emptied_dirs(root: &Path, dir: &Path) {
+    let mut current = Some(dir);
+    while let Some(dir) = current.filter(|d| *d != root && d.starts_with(root)) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let only_generated = entries.flatten().all(|e| {
+            let path = e.path();
+            is_generated(&path, root)
+                && (!path.is_dir() || fs::read_dir(&path).is_ok_and(|mut d| d.next().is_none()))
+        });
+        if !only_generated {
+            return;
+        }
+        if let Err(e) = fs::remove_dir_all(dir) {
+            warn!("Failed to remove emptied folder {:?}: {}", dir, e);
+            return;
+        }
+        current = dir.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::sniff;
+
+    #[test]
+    fn test_library_date() {
+        let expected = naive_to_utc(2019, 7, 4);
+        assert_eq!(library_date(Path::new("2019/07/04")), expected);
+        assert_eq!(library_date(Path::new("alice/2019/July/04")), expected);
+        assert_eq!(library_date(Path::new("Unknown/Trip")), None);
+        assert_eq!(library_date(Path::new("2019/Unknown")), None);
+    }
+
+    #[test]
+    fn test_reorganize_moves_to_current_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        // Old numeric layout, with a thumbnail and a catalog entry holding the real date
+        let old = root.join("2019/07/04");
+        fs::create_dir_all(old.join(".thumbnails")).unwrap();
+        fs::write(old.join("IMG_1.jpg"), sniff::fake_jpeg(b"pixels")).unwrap();
+        fs::write(old.join(".thumbnails/IMG_1.jpg.jpg"), "thumb").unwrap();
+        fs::write(old.join("index.html"), "").unwrap();
+        // Not in the catalog: dated by the folder it sits in
+        fs::write(old.join("IMG_2.jpg"), sniff::fake_jpeg(b"other")).unwrap();
+        // Its name holds a better date than the fallback it was organized with
+        fs::write(
+            old.join("IMG_20200102_101010.jpg"),
+            sniff::fake_jpeg(b"named"),
+        )
+        .unwrap();
+
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2019/07/04/IMG_1.jpg".to_string(),
+            CatalogEntry {
+                size: 9,
+                source: "Takeout/Google Photos/IMG_1.jpg".to_string(),
+                date: Some("2019-07-05T09:00:00+00:00".to_string()),
+                date_source: Some(DateSource::Exif),
+                ..Default::default()
+            },
+        );
+        catalog.insert(
+            "2019/07/04/IMG_20200102_101010.jpg".to_string(),
+            CatalogEntry {
+                size: 8,
+                source: "Takeout/Google Photos/IMG_20200102_101010.jpg".to_string(),
+                date: Some("2019-07-04T00:00:00+00:00".to_string()),
+                date_source: Some(DateSource::FileMtime),
+                ..Default::default()
+            },
+        );
+        catalog.save(root).unwrap();
+
+        let report = reorganize(root, &OrganizeOptions::default()).unwrap();
+        assert_eq!(report.moved, 3);
+        assert_eq!(report.errors, 0);
+
+        assert!(root.join("2019/July/05/IMG_1.jpg").exists());
+        assert!(root.join("2019/July/05/.thumbnails/IMG_1.jpg.jpg").exists());
+        assert!(root.join("2019/July/04/IMG_2.jpg").exists());
+        assert!(
+            root.join("2020/January/02/IMG_20200102_101010.jpg")
+                .exists()
+        );
+        // The old folder only held gallery files once emptied
+        assert!(!root.join("2019/07").exists());
+
+        let catalog = Catalog::load(root).unwrap();
+        let entry = catalog.get("2019/July/05/IMG_1.jpg").unwrap();
+        assert_eq!(entry.source, "Takeout/Google Photos/IMG_1.jpg");
+        let untracked = catalog.get("2019/July/04/IMG_2.jpg").unwrap();
+        assert_eq!(untracked.date_source, Some(DateSource::Library));
+        assert_eq!(untracked.size, 8);
+        assert_eq!(catalog.entries.len(), 3);
+
+        // Running again finds everything in place
+        let report = reorganize(root, &OrganizeOptions::default()).unwrap();
+        assert_eq!((report.moved, report.unchanged), (0, 3));
+    }
+
+    #[test]
+    fn test_reorganize_keeps_edited_copies_linked() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let old = root.join("2019/07/04");
+        fs::create_dir_all(&old).unwrap();
+        let mut catalog = Catalog::default();
+        for (name, content, edit_of) in [
+            ("IMG_1.jpg", b"original".as_slice(), None),
+            ("IMG_1-edited.jpg", b"edited", Some("2019/07/04/IMG_1.jpg")),
+        ] {
+            fs::write(old.join(name), sniff::fake_jpeg(content)).unwrap();
+            catalog.insert(
+                format!("2019/07/04/{}", name),
+                CatalogEntry {
+                    source: format!("Takeout/Google Photos/{}", name),
+                    date: Some("2019-07-04T09:00:00+00:00".to_string()),
+                    date_source: Some(DateSource::Exif),
+                    edit_of: edit_of.map(str::to_string),
+                    ..Default::default()
+                },
+            );
+        }
+        catalog.save(root).unwrap();
+
+        let report = reorganize(root, &OrganizeOptions::default()).unwrap();
+        assert_eq!(report.moved, 2);
+
+        let catalog = Catalog::load(root).unwrap();
+        let edited = catalog.get("2019/July/04/IMG_1-edited.jpg").unwrap();
+        assert_eq!(edited.edit_of.as_deref(), Some("2019/July/04/IMG_1.jpg"));
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use super::cancel::CANCELLED_EXIT_CODE;
+use super::filters::ExclusionCounts;
+use super::fs_ops::FileAction;
+use super::model::{DateSource, ResolvedDate};
+
+/// Failures listed in the summary at the end of a run; the JSON report keeps all of them.
+const SUMMARY_FAILURES: usize = 20;
+
+/// How a run went overall, which decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunOutcome {
+    Success,
+    /// Some files failed, the rest were organized, or the gallery could not be generated
+    Partial,
+    /// Files failed and none could be organized
+    Failed,
+    /// Stopped with Ctrl-C; what was done is kept
+    Cancelled,
+}
+
+impl RunOutcome {
+    /// `0` on success, `4` on partial and `3` on total failure, `130` when cancelled. Errors
+    /// that stop a run before it finishes exit with `1`, and `2` is left to command line errors.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            RunOutcome::Success => 0,
+            RunOutcome::Partial => 4,
+            RunOutcome::Failed => 3,
+            RunOutcome::Cancelled => CANCELLED_EXIT_CODE,
+        }
+    }
+}
+
+/// What happened to the files of a run.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ActionCounts {
+    pub new: usize,
+    pub updated: usize,
+    pub already_present: usize,
+    pub failed: usize,
+    pub excluded: usize,
+    pub dropped_by_edit_policy: usize,
+    pub cross_account_duplicates: usize,
+    pub non_media: usize,
+}
+
+impl ActionCounts {
+    /// Files the run came across, whatever happened to them.
+    pub fn total(&self) -> usize {
+        self.new
+            + self.updated
+            + self.already_present
+            + self.failed
+            + self.excluded
+            + self.dropped_by_edit_policy
+            + self.cross_account_duplicates
+            + self.non_media
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Failure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub seconds: f64,
+}
+
+/// Counts, failures, date sources and phase durations of one organizing run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    pub started_at: String,
+    pub counts: ActionCounts,
+    /// Files placed per source of their date
+    pub date_sources: BTreeMap<DateSource, usize>,
+    pub undated: usize,
+    /// Files left out by the filters, per reason
+    pub exclusions: ExclusionCounts,
+    /// Dates outside the Takeout year folder of their file
+    pub folder_year_mismatches: usize,
+    pub failures: Vec<Failure>,
+    pub phases: Vec<PhaseTiming>,
+    /// Stopped with Ctrl-C before every file was handled
+    pub cancelled: bool,
+    /// Why the gallery could not be generated after organizing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gallery_error: Option<String>,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        Self {
+            started_at: Utc::now().to_rfc3339(),
+            counts: ActionCounts::default(),
+            date_sources: BTreeMap::new(),
+            undated: 0,
+            exclusions: ExclusionCounts::default(),
+            folder_year_mismatches: 0,
+            failures: Vec::new(),
+            phases: Vec::new(),
+            cancelled: false,
+            gallery_error: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ReportFile<'a> {
+    outcome: RunOutcome,
+    #[serde(flatten)]
+    report: &'a RunReport,
+}
+
+impl RunReport {
+    /// Counts a media file placed in the library, with the date it was placed by.
+    pub fn record(&mut self, action: FileAction, date: Option<ResolvedDate>) {
+        match action {
+            FileAction::New => self.counts.new += 1,
+            FileAction::Updated => self.counts.updated += 1,
+            FileAction::Skipped => self.counts.already_present += 1,
+        }
+        match date {
+            Some(date) => *self.date_sources.entry(date.source).or_default() += 1,
+            None => self.undated += 1,
+        }
+    }
+
+    pub fn fail(&mut self, path: &Path, error: impl ToString) {
+        self.counts.failed += 1;
+        self.failures.push(Failure {
+            path: path.display().to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn add_phase(&mut self, phase: &str, duration: Duration) {
+        self.phases.push(PhaseTiming {
+            phase: phase.to_string(),
+            seconds: duration.as_secs_f64(),
+        });
+    }
+
+    pub fn outcome(&self) -> RunOutcome {
+        if self.cancelled {
+            return RunOutcome::Cancelled;
+        }
+        let placed = self.counts.new + self.counts.updated + self.counts.already_present;
+        match (self.counts.failed, placed) {
+            (0, _) if self.gallery_error.is_some() => RunOutcome::Partial,
+            (0, _) => RunOutcome::Success,
+            (_, 0) => RunOutcome::Failed,
+            _ => RunOutcome::Partial,
+        }
+    }
+
+    /// Writes the report as JSON, with its outcome, to `path`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = ReportFile {
+            outcome: self.outcome(),
+            report: self,
+        };
+        let json = serde_json::to_string_pretty(&file)?;
+        fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write run report {:?}", path))
+    }
+}
+
+/// Logs the part of the report not covered by the organizing summary: date sources, failures,
+/// phase durations and the outcome.
+pub fn log_summary(report: &RunReport) {
+    if !report.date_sources.is_empty() || report.undated > 0 {
+        let mut sources: Vec<String> = report
+            .date_sources
+            .iter()
+            .map(|(source, count)| format!("{:?}: {}", source, count))
+            .collect();
+        sources.push(format!("undated: {}", report.undated));
+        info!("Date sources: {}", sources.join(", "));
+    }
+
+    if !report.failures.is_empty() {
+        error!("--- Failed Files ({}) ---", report.failures.len());
+        for failure in report.failures.iter().take(SUMMARY_FAILURES) {
+            error!("  - {}: {}", failure.path, failure.error);
+        }
+        if report.failures.len() > SUMMARY_FAILURES {
+            error!(
+                "  ... and {} more (see the run report)",
+                report.failures.len() - SUMMARY_FAILURES
+            );
+        }
+    }
+
+    let phases: Vec<String> = report
+        .phases
+        .iter()
+        .map(|p| format!("{} {:.1}s", p.phase, p.seconds))
+        .collect();
+    if !phases.is_empty() {
+        info!("Durations: {}", phases.join(", "));
+    }
+
+    match report.outcome() {
+        RunOutcome::Success => info!("Run finished without errors"),
+        RunOutcome::Partial if report.counts.failed == 0 => warn!(
+            "Run finished without a gallery (exit code {})",
+            RunOutcome::Partial.exit_code()
+        ),
+        RunOutcome::Partial => warn!(
+            "Run finished with {} failed files (exit code {})",
+            report.counts.failed,
+            RunOutcome::Partial.exit_code()
+        ),
+        RunOutcome::Failed => error!(
+            "Run failed: no file could be organized (exit code {})",
+            RunOutcome::Failed.exit_code()
+        ),
+        RunOutcome::Cancelled => warn!(
+            "Run cancelled: {} files organized and {} already present are kept, run again to finish",
+            report.counts.new + report.counts.updated,
+            report.counts.already_present
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dated(source: DateSource) -> Option<ResolvedDate> {
+        Some(ResolvedDate {
+            date: Utc::now(),
+            source,
+        })
+    }
+
+    #[test]
+    fn test_outcome_distinguishes_partial_and_total_failure() {
+        let mut report = RunReport::default();
+        assert_eq!(report.outcome(), RunOutcome::Success);
+        report.gallery_error = Some("ffmpeg crashed".to_string());
+        assert_eq!(report.outcome(), RunOutcome::Partial);
+        report.gallery_error = None;
+
+        report.fail(Path::new("a.jpg"), "permission denied");
+        assert_eq!(report.outcome(), RunOutcome::Failed);
+        assert_eq!(report.outcome().exit_code(), 3);
+
+        report.record(FileAction::Skipped, None);
+        assert_eq!(report.outcome(), RunOutcome::Partial);
+        assert_eq!(report.outcome().exit_code(), 4);
+    }
+
+    #[test]
+    fn test_write_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut report = RunReport::default();
+        report.record(FileAction::New, dated(DateSource::Exif));
+        report.record(FileAction::New, dated(DateSource::FolderHint));
+        report.fail(Path::new("b.mp4"), "disk full");
+        report.add_phase("organize", Duration::from_millis(1500));
+
+        let path = dir.path().join("report.json");
+        report.write(&path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(json["outcome"], "partial");
+        assert_eq!(json["counts"]["new"], 2);
+        assert_eq!(json["date_sources"]["folder-hint"], 1);
+        assert_eq!(json["failures"][0]["error"], "disk full");
+        assert_eq!(json["phases"][0]["seconds"], 1.5);
+    }
+}
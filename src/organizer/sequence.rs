@@ -0,0 +1,159 @@
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+lazy_static::lazy_static! {
+    static ref SEQUENCE_NAME: Regex = Regex::new(r"^(.*?[^\d])?(\d{3,})(?: ?\(\d+\))?$").unwrap();
+}
+
+/// A camera file name like `IMG_4512.JPG` split into its prefix and frame number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SequenceName {
+    /// Folder (relative to the Takeout root) and lowercase prefix, e.g. `Takeout/Trip` + `img_`
+    pub group: (String, String),
+    pub number: u64,
+}
+
+/// Parses the numbered name of a file given by its path relative to the Takeout root.
+pub fn parse_sequence_name(relative_path: &Path) -> Option<SequenceName> {
+    let stem = relative_path.file_stem()?.to_str()?;
+    let caps = SEQUENCE_NAME.captures(stem)?;
+    let prefix = caps.get(1).map_or("", |m| m.as_str()).to_lowercase();
+    let number = caps.get(2)?.as_str().parse().ok()?;
+    let folder = relative_path
+        .parent()
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+    Some(SequenceName {
+        group: (folder, prefix),
+        number,
+    })
+}
+
+/// A frame number with its date.
+pub type Frame = (u64, DateTime<Utc>);
+
+/// Dated frames grouped by folder and file name prefix.
+#[derive(Debug, Default)]
+pub struct SequenceIndex {
+    groups: HashMap<(String, String), BTreeMap<u64, DateTime<Utc>>>,
+}
+
+impl SequenceIndex {
+    pub fn insert(&mut self, relative_path: &Path, date: DateTime<Utc>) {
+        if let Some(name) = parse_sequence_name(relative_path) {
+            self.groups
+                .entry(name.group)
+                .or_default()
+                .insert(name.number, date);
+        }
+    }
+
+    /// The closest dated frames before and after `relative_path` in its sequence.
+    pub fn neighbours(&self, relative_path: &Path) -> (Option<Frame>, Option<Frame>) {
+        let Some(name) = parse_sequence_name(relative_path) else {
+            return (None, None);
+        };
+        let Some(frames) = self.groups.get(&name.group) else {
+            return (None, None);
+        };
+
+        let before = frames
+            .range(..name.number)
+            .next_back()
+            .map(|(n, d)| (*n, *d));
+        let after = frames
+            .range(name.number + 1..)
+            .next()
+            .map(|(n, d)| (*n, *d));
+        (before, after)
+    }
+
+    /// Interpolates a date for `relative_path` from the frames on both sides, by frame
+    /// number. Gives up when a side is missing or the neighbours are further apart than
+    /// `window` (or out of order), since the sequence then likely spans separate events.
+    pub fn interpolate(&self, relative_path: &Path, window: Duration) -> Option<DateTime<Utc>> {
+        let name = parse_sequence_name(relative_path)?;
+        let (Some((first, start)), Some((last, end))) = self.neighbours(relative_path) else {
+            return None;
+        };
+
+        let span = end - start;
+        if span < Duration::zero() || span > window {
+            return None;
+        }
+
+        let fraction = (name.number - first) as f64 / (last - first) as f64;
+        let offset = Duration::milliseconds((span.num_milliseconds() as f64 * fraction) as i64);
+        Some(start + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::date_utils::naive_to_utc;
+
+    #[test]
+    fn test_parse_sequence_name() {
+        let name = parse_sequence_name(Path::new("Takeout/Trip/IMG_4512.JPG")).unwrap();
+        assert_eq!(name.group, ("Takeout/Trip".to_string(), "img_".to_string()));
+        assert_eq!(name.number, 4512);
+
+        let name = parse_sequence_name(Path::new("Takeout/Trip/DSCN0042(1).jpg")).unwrap();
+        assert_eq!(name.number, 42);
+        assert_eq!(name.group.1, "dscn");
+
+        assert!(parse_sequence_name(Path::new("Takeout/Trip/beach.jpg")).is_none());
+    }
+
+    #[test]
+    fn test_neighbours() {
+        let mut index = SequenceIndex::default();
+        index.insert(
+            Path::new("Trip/IMG_4511.JPG"),
+            naive_to_utc(2019, 7, 4).unwrap(),
+        );
+        index.insert(
+            Path::new("Trip/IMG_4515.JPG"),
+            naive_to_utc(2019, 7, 5).unwrap(),
+        );
+        index.insert(
+            Path::new("Other/IMG_4512.JPG"),
+            naive_to_utc(2001, 1, 1).unwrap(),
+        );
+
+        let (before, after) = index.neighbours(Path::new("Trip/IMG_4512.JPG"));
+        assert_eq!(before.map(|(n, _)| n), Some(4511));
+        assert_eq!(after.map(|(n, _)| n), Some(4515));
+        assert_eq!(
+            index.neighbours(Path::new("Trip/DSC_0001.JPG")),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn test_interpolate_within_window() {
+        let mut index = SequenceIndex::default();
+        let start = naive_to_utc(2019, 7, 4).unwrap();
+        index.insert(Path::new("Trip/IMG_4510.JPG"), start);
+        index.insert(Path::new("Trip/IMG_4514.JPG"), start + Duration::hours(4));
+        index.insert(Path::new("Trip/IMG_4600.JPG"), start + Duration::days(30));
+
+        assert_eq!(
+            index.interpolate(Path::new("Trip/IMG_4511.JPG"), Duration::hours(24)),
+            Some(start + Duration::hours(1))
+        );
+        // Neighbours a month apart are not trusted
+        assert_eq!(
+            index.interpolate(Path::new("Trip/IMG_4550.JPG"), Duration::hours(24)),
+            None
+        );
+        // The end of a sequence has no neighbour after it
+        assert_eq!(
+            index.interpolate(Path::new("Trip/IMG_4700.JPG"), Duration::days(365)),
+            None
+        );
+    }
+}
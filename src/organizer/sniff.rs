@@ -0,0 +1,272 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// What a file really contains according to its leading bytes, with the canonical extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Image(&'static str),
+    Video(&'static str),
+    NonMedia(&'static str),
+}
+
+impl ContentType {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ContentType::Image(ext) | ContentType::Video(ext) | ContentType::NonMedia(ext) => ext,
+        }
+    }
+
+    pub fn is_media(self) -> bool {
+        !matches!(self, ContentType::NonMedia(_))
+    }
+}
+
+/// Extensions accepted as media when the content itself is not recognised (e.g. camera RAW files).
+const MEDIA_EXTENSIONS: [&str; 33] = [
+    "jpg", "jpeg", "jpe", "jfif", "png", "gif", "bmp", "webp", "heic", "heif", "hif", "avif",
+    "tif", "tiff", "dng", "cr2", "cr3", "nef", "arw", "orf", "rw2", "raf", "mp4", "m4v", "mov",
+    "3gp", "3g2", "avi", "mkv", "webm", "mpg", "mts", "m2ts",
+];
+
+/// Media extensions of the formats [`sniff_bytes`] recognises: content that does not match
+/// them is not media, whatever the extension says.
+const SNIFFED_EXTENSIONS: [&str; 23] = [
+    "jpg", "jpeg", "jpe", "jfif", "png", "gif", "bmp", "webp", "heic", "heif", "hif", "avif",
+    "tif", "tiff", "cr3", "mp4", "m4v", "mov", "3gp", "3g2", "avi", "mkv", "webm",
+];
+
+const VIDEO_EXTENSIONS: [&str; 11] = [
+    "mp4", "m4v", "mov", "3gp", "3g2", "avi", "mkv", "webm", "mpg", "mts", "m2ts",
+];
+
+/// Groups of extensions that are interchangeable for the same content, so repairs only
+/// happen when the extension really lies (TIFF-based RAW formats stay untouched, for example).
+const COMPATIBLE_EXTENSIONS: [&[&str]; 5] = [
+    &["jpg", "jpeg", "jpe", "jfif"],
+    &["heic", "heif", "hif"],
+    &[
+        "tiff", "tif", "dng", "cr2", "nef", "arw", "orf", "rw2", "pef", "srw",
+    ],
+    &["mp4", "m4v", "mov", "qt", "3gp", "3g2"],
+    &["mkv", "webm"],
+];
+
+/// Reads the first bytes of `path` and identifies its format. `None` when unreadable or unknown.
+pub fn sniff(path: &Path) -> Option<ContentType> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut header = [0u8; 64];
+    let mut len = 0;
+    while len < header.len() {
+        match file.read(&mut header[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    sniff_bytes(&header[..len])
+}
+
+pub fn sniff_bytes(header: &[u8]) -> Option<ContentType> {
+    use ContentType::*;
+
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    if at(0, b"\xff\xd8\xff") {
+        return Some(Image("jpg"));
+    }
+    if at(0, b"\x89PNG\r\n\x1a\n") {
+        return Some(Image("png"));
+    }
+    if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        return Some(Image("gif"));
+    }
+    if at(0, b"RIFF") && at(8, b"WEBP") {
+        return Some(Image("webp"));
+    }
+    if at(0, b"RIFF") && at(8, b"AVI ") {
+        return Some(Video("avi"));
+    }
+    if at(0, b"II*\x00") || at(0, b"MM\x00*") {
+        return Some(Image("tiff"));
+    }
+    if at(0, b"BM") && header.len() >= 14 && at(6, b"\x00\x00\x00\x00") {
+        return Some(Image("bmp"));
+    }
+    if at(4, b"ftyp") {
+        let brand = header.get(8..12)?;
+        return Some(match brand {
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1" => {
+                Image("heic")
+            }
+            b"avif" | b"avis" => Image("avif"),
+            b"crx " => Image("cr3"),
+            b"qt  " => Video("mov"),
+            b"3gp4" | b"3gp5" | b"3gp6" | b"3g2a" => Video("3gp"),
+            b"M4A " | b"M4B " => NonMedia("m4a"),
+            _ => Video("mp4"),
+        });
+    }
+    if at(4, b"moov") || at(4, b"mdat") || at(4, b"wide") {
+        return Some(Video("mov"));
+    }
+    if at(0, b"\x1a\x45\xdf\xa3") {
+        let is_webm = header.windows(4).any(|w| w == b"webm");
+        return Some(Video(if is_webm { "webm" } else { "mkv" }));
+    }
+    if at(0, b"%PDF") {
+        return Some(NonMedia("pdf"));
+    }
+    if at(0, b"PK\x03\x04") {
+        return Some(NonMedia("zip"));
+    }
+    if at(4, b"Bud1") {
+        return Some(NonMedia("ds_store"));
+    }
+
+    let text = String::from_utf8_lossy(header);
+    let text = text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        return Some(NonMedia("html"));
+    }
+
+    None
+}
+
+pub fn has_media_extension(path: &Path) -> bool {
+    lowercase_extension(path).is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Whether a file whose content was not recognised is still taken as media: only for formats
+/// without a signature in [`sniff_bytes`], such as most camera RAW files.
+pub fn is_unsniffed_media(path: &Path) -> bool {
+    lowercase_extension(path).is_some_and(|ext| {
+        MEDIA_EXTENSIONS.contains(&ext.as_str()) && !SNIFFED_EXTENSIONS.contains(&ext.as_str())
+    })
+}
+
+/// Whether a media file is a video, by its sniffed `content` first and by extension otherwise.
+pub fn is_video(path: &Path, content: Option<ContentType>) -> bool {
+    match content {
+        Some(content) => matches!(content, ContentType::Video(_)),
+        None => {
+            lowercase_extension(path).is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()))
+        }
+    }
+}
+
+/// Returns the file name with its extension replaced when its sniffed `content` says it is
+/// something else.
+pub fn corrected_file_name(path: &Path, content: Option<ContentType>) -> Option<String> {
+    let content = content?;
+    if !content.is_media() {
+        return None;
+    }
+
+    let ext = lowercase_extension(path).unwrap_or_default();
+    if is_compatible_extension(&ext, content.extension()) {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_string_lossy();
+    Some(format!("{}.{}", stem, content.extension()))
+}
+
+/// JPEG magic bytes followed by `body`, for tests that need files sniffed as photos.
+#[cfg(test)]
+pub fn fake_jpeg(body: &[u8]) -> Vec<u8> {
+    [b"\xff\xd8\xff".as_slice(), body].concat()
+}
+
+fn is_compatible_extension(ext: &str, canonical: &str) -> bool {
+    ext == canonical
+        || COMPATIBLE_EXTENSIONS
+            .iter()
+            .any(|group| group.contains(&ext) && group.contains(&canonical))
+}
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_bytes() {
+        assert_eq!(
+            sniff_bytes(b"\xff\xd8\xff\xe0\x00\x10JFIF"),
+            Some(ContentType::Image("jpg"))
+        );
+        assert_eq!(
+            sniff_bytes(b"\x89PNG\r\n\x1a\n\x00\x00"),
+            Some(ContentType::Image("png"))
+        );
+        assert_eq!(
+            sniff_bytes(b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00"),
+            Some(ContentType::Image("heic"))
+        );
+        assert_eq!(
+            sniff_bytes(b"\x00\x00\x00\x14ftypqt  \x00\x00\x00\x00"),
+            Some(ContentType::Video("mov"))
+        );
+        assert_eq!(
+            sniff_bytes(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"),
+            Some(ContentType::Video("mp4"))
+        );
+        assert_eq!(
+            sniff_bytes(b"\n<!DOCTYPE html><html>"),
+            Some(ContentType::NonMedia("html"))
+        );
+        assert_eq!(
+            sniff_bytes(b"\x00\x00\x00\x01Bud1\x00\x00"),
+            Some(ContentType::NonMedia("ds_store"))
+        );
+        assert_eq!(sniff_bytes(b"just some text"), None);
+    }
+
+    #[test]
+    fn test_corrected_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let heic_as_jpg = dir.path().join("IMG_0001.jpg");
+        fs::write(&heic_as_jpg, b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00").unwrap();
+        assert_eq!(
+            corrected_file_name(&heic_as_jpg, sniff(&heic_as_jpg)),
+            Some("IMG_0001.heic".to_string())
+        );
+
+        let png_as_jpg = dir.path().join("Screenshot.JPG");
+        fs::write(&png_as_jpg, b"\x89PNG\r\n\x1a\n\x00\x00").unwrap();
+        assert_eq!(
+            corrected_file_name(&png_as_jpg, sniff(&png_as_jpg)),
+            Some("Screenshot.png".to_string())
+        );
+
+        let real_jpeg = dir.path().join("photo.JPEG");
+        fs::write(&real_jpeg, b"\xff\xd8\xff\xe1").unwrap();
+        assert_eq!(corrected_file_name(&real_jpeg, sniff(&real_jpeg)), None);
+
+        let raw = dir.path().join("DSC_0001.NEF");
+        fs::write(&raw, b"MM\x00*\x00\x00\x00\x08").unwrap();
+        assert_eq!(corrected_file_name(&raw, sniff(&raw)), None);
+    }
+
+    #[test]
+    fn test_has_media_extension() {
+        assert!(has_media_extension(Path::new("a.JPG")));
+        assert!(has_media_extension(Path::new("clip.mts")));
+        assert!(!has_media_extension(Path::new("notes.txt")));
+        assert!(!has_media_extension(Path::new(".DS_Store")));
+
+        assert!(is_unsniffed_media(Path::new("IMG_1.RAF")));
+        assert!(is_unsniffed_media(Path::new("clip.mts")));
+        assert!(!is_unsniffed_media(Path::new("a.jpg")));
+        assert!(!is_unsniffed_media(Path::new("notes.txt")));
+    }
+}
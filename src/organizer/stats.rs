@@ -0,0 +1,129 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike};
+use log::info;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::catalog::Catalog;
+use super::model::DateSource;
+
+/// Counts over the catalog of an organized library.
+#[derive(Debug, Default, PartialEq)]
+pub struct LibraryStats {
+    pub files: usize,
+    pub bytes: u64,
+    pub by_year: BTreeMap<i32, usize>,
+    pub undated: usize,
+    pub by_source: BTreeMap<DateSource, usize>,
+    /// Files dated by a fallback source, worth a review with --triage
+    pub low_confidence: usize,
+    pub by_owner: BTreeMap<String, usize>,
+    /// Edited copies linked to their original
+    pub edited: usize,
+}
+
+pub fn library_stats(root: &Path) -> Result<LibraryStats> {
+    let catalog = Catalog::load_existing(root)?;
+    let mut stats = LibraryStats::default();
+
+    for entry in catalog.entries.values() {
+        stats.files += 1;
+        stats.bytes += entry.size;
+
+        match entry
+            .date
+            .as_deref()
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+        {
+            Some(date) => *stats.by_year.entry(date.year()).or_default() += 1,
+            None => stats.undated += 1,
+        }
+        if let Some(source) = entry.date_source {
+            *stats.by_source.entry(source).or_default() += 1;
+            if source.is_low_confidence() {
+                stats.low_confidence += 1;
+            }
+        }
+        for owner in &entry.owners {
+            *stats.by_owner.entry(owner.clone()).or_default() += 1;
+        }
+        if entry.edit_of.is_some() {
+            stats.edited += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+pub fn log_stats(stats: &LibraryStats) {
+    info!(
+        "{} files, {:.1} GB",
+        stats.files,
+        stats.bytes as f64 / 1_000_000_000.0
+    );
+    for (year, count) in &stats.by_year {
+        info!("  {}: {}", year, count);
+    }
+    info!("  undated: {}", stats.undated);
+    info!("Date sources:");
+    for (source, count) in &stats.by_source {
+        info!("  {:?}: {}", source, count);
+    }
+    info!("Low-confidence dates: {}", stats.low_confidence);
+    if !stats.by_owner.is_empty() {
+        info!("Owners:");
+        for (owner, count) in &stats.by_owner {
+            info!("  {}: {}", owner, count);
+        }
+    }
+    info!("Linked edited copies: {}", stats.edited);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CatalogEntry;
+
+    #[test]
+    fn test_library_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "2019/July/04/a.jpg".to_string(),
+            CatalogEntry {
+                size: 10,
+                date: Some("2019-07-04T12:00:00+00:00".to_string()),
+                date_source: Some(DateSource::Exif),
+                owners: vec!["alice".to_string(), "bob".to_string()],
+                ..Default::default()
+            },
+        );
+        catalog.insert(
+            "2019/unknown/b.jpg".to_string(),
+            CatalogEntry {
+                size: 5,
+                date: Some("2019-01-01T00:00:00+00:00".to_string()),
+                date_source: Some(DateSource::FolderHint),
+                owners: vec!["alice".to_string()],
+                ..Default::default()
+            },
+        );
+        catalog.insert(
+            "unknown/c.jpg".to_string(),
+            CatalogEntry {
+                size: 1,
+                ..Default::default()
+            },
+        );
+        catalog.save(dir.path()).unwrap();
+
+        let stats = library_stats(dir.path()).unwrap();
+        assert_eq!(stats.files, 3);
+        assert_eq!(stats.bytes, 16);
+        assert_eq!(stats.by_year.get(&2019), Some(&2));
+        assert_eq!(stats.undated, 1);
+        assert_eq!(stats.low_confidence, 1);
+        assert_eq!(stats.by_owner.get("alice"), Some(&2));
+        assert_eq!(stats.by_owner.get("bob"), Some(&1));
+    }
+}
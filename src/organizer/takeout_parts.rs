@@ -0,0 +1,153 @@
+use log::{info, warn};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+lazy_static::lazy_static! {
+    static ref PART_NAME: Regex =
+        Regex::new(r"^takeout-(\d{8}T\d{6}Z(?:-\d+)*)-(\d{3,})\.(?:zip|tgz|7z|tar(?:\.\w+)?)$")
+            .unwrap();
+}
+
+/// Multi-part Takeout archives grouped by the export they belong to.
+#[derive(Debug, Default)]
+pub struct PartsReport {
+    /// Export timestamp -> part numbers present among the inputs.
+    pub exports: BTreeMap<String, Vec<u32>>,
+    /// Export timestamp -> part numbers absent below the highest part seen.
+    pub missing: BTreeMap<String, Vec<u32>>,
+    /// Archives whose name does not follow the Takeout naming scheme.
+    pub unrecognised: Vec<PathBuf>,
+}
+
+impl PartsReport {
+    pub fn has_gaps(&self) -> bool {
+        !self.missing.is_empty()
+    }
+
+    /// More than one export was given, which usually means parts of an old download slipped in.
+    pub fn has_mixed_exports(&self) -> bool {
+        self.exports.len() > 1
+    }
+
+    pub fn is_complete(&self) -> bool {
+        !self.has_gaps() && !self.has_mixed_exports()
+    }
+}
+
+/// Parses `takeout-YYYYMMDDTHHMMSSZ-NNN.zip` (or `.tgz`, `.tar.*`, `.7z`) into (export, part number).
+pub fn parse_part_name(path: &Path) -> Option<(String, u32)> {
+    let filename = path.file_name()?.to_str()?;
+    let caps = PART_NAME.captures(filename)?;
+    let export = caps.get(1)?.as_str().to_string();
+    let number = caps.get(2)?.as_str().parse().ok()?;
+    Some((export, number))
+}
+
+/// Groups archives by export and finds gaps in the part numbering. A missing *last* part
+/// cannot be detected from file names alone.
+pub fn check_parts(archives: &[PathBuf]) -> PartsReport {
+    let mut report = PartsReport::default();
+
+    for archive in archives {
+        match parse_part_name(archive) {
+            Some((export, number)) => report.exports.entry(export).or_default().push(number),
+            None => report.unrecognised.push(archive.clone()),
+        }
+    }
+
+    for (export, parts) in report.exports.iter_mut() {
+        parts.sort_unstable();
+        parts.dedup();
+        let max = parts.last().copied().unwrap_or(0);
+        let missing: Vec<u32> = (1..=max).filter(|n| !parts.contains(n)).collect();
+        if !missing.is_empty() {
+            report.missing.insert(export.clone(), missing);
+        }
+    }
+
+    report
+}
+
+pub fn log_parts_report(report: &PartsReport) {
+    for (export, parts) in &report.exports {
+        info!("Takeout export {}: {} part(s) found", export, parts.len());
+    }
+
+    if report.has_mixed_exports() {
+        warn!(
+            "Inputs mix {} different Takeout exports: {}",
+            report.exports.len(),
+            report
+                .exports
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    for (export, missing) in &report.missing {
+        warn!(
+            "Takeout export {} is missing part(s): {}",
+            export,
+            missing
+                .iter()
+                .map(|n| format!("{:03}", n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_part_name() {
+        assert_eq!(
+            parse_part_name(Path::new("/dl/takeout-20240105T101500Z-003.zip")),
+            Some(("20240105T101500Z".to_string(), 3))
+        );
+        assert_eq!(
+            parse_part_name(Path::new("takeout-20240105T101500Z-2-011.tgz")),
+            Some(("20240105T101500Z-2".to_string(), 11))
+        );
+        assert_eq!(parse_part_name(Path::new("photos.zip")), None);
+    }
+
+    #[test]
+    fn test_check_parts_detects_gaps() {
+        let archives: Vec<PathBuf> = [
+            "takeout-20240105T101500Z-001.zip",
+            "takeout-20240105T101500Z-002.zip",
+            "takeout-20240105T101500Z-005.zip",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let report = check_parts(&archives);
+        assert_eq!(report.missing["20240105T101500Z"], vec![3, 4]);
+        assert!(!report.has_mixed_exports());
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn test_check_parts_detects_mixed_exports() {
+        let archives: Vec<PathBuf> = [
+            "takeout-20240105T101500Z-001.zip",
+            "takeout-20231201T080000Z-001.zip",
+            "my-photos.zip",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let report = check_parts(&archives);
+        assert!(report.has_mixed_exports());
+        assert!(!report.has_gaps());
+        assert_eq!(report.unrecognised, vec![PathBuf::from("my-photos.zip")]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// Device names Windows (and so FAT/exFAT drives and SMB shares) refuses as file names,
+/// with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters FAT/exFAT and SMB shares do not accept in names.
+const WINDOWS_CHARS: [char; 9] = ['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Longest path Windows clients handle without long path support, in UTF-16 units. Only the
+/// part below the library root is known, so that is what is kept within it.
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Longest file name, in bytes (native) or UTF-16 units (everything else).
+const MAX_NAME: usize = 255;
+
+/// The filesystem the library is written to, which decides what names are valid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetFs {
+    /// Names are written as they come
+    #[default]
+    Native,
+    /// FAT32/exFAT drives: Windows character rules, case-insensitive, NFC
+    Exfat,
+    /// SMB/CIFS shares: the same rules as exFAT, served to Windows clients
+    Smb,
+    /// macOS HFS+/APFS: no ':', case-insensitive, NFD
+    Hfs,
+}
+
+impl FromStr for TargetFs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "native" => Ok(TargetFs::Native),
+            "exfat" | "fat" | "fat32" => Ok(TargetFs::Exfat),
+            "smb" | "cifs" => Ok(TargetFs::Smb),
+            "hfs" | "apfs" | "macos" => Ok(TargetFs::Hfs),
+            _ => Err(format!(
+                "invalid target filesystem '{}' (expected native, exfat, smb or hfs)",
+                s
+            )),
+        }
+    }
+}
+
+impl TargetFs {
+    pub fn is_case_insensitive(self) -> bool {
+        self != TargetFs::Native
+    }
+
+    fn is_windows(self) -> bool {
+        matches!(self, TargetFs::Exfat | TargetFs::Smb)
+    }
+
+    /// Length of `name` in the unit the filesystem limits.
+    fn name_len(self, name: &str) -> usize {
+        match self {
+            TargetFs::Native => name.len(),
+            _ => name.encode_utf16().count(),
+        }
+    }
+
+    /// Makes one path component valid on this filesystem: forbidden characters become `_`,
+    /// trailing dots and spaces are dropped, device names are prefixed and Unicode is brought
+    /// into the form the filesystem stores.
+    pub fn sanitize_component(self, name: &str) -> String {
+        let normalized: String = match self {
+            TargetFs::Native => return name.replace(['/', '\0'], "_"),
+            TargetFs::Exfat | TargetFs::Smb => name.nfc().collect(),
+            TargetFs::Hfs => name.nfd().collect(),
+        };
+
+        let mut cleaned: String = normalized
+            .chars()
+            .map(|c| match c {
+                '/' | ':' => '_',
+                c if self.is_windows() && (WINDOWS_CHARS.contains(&c) || c.is_control()) => '_',
+                c => c,
+            })
+            .collect();
+
+        if self.is_windows() {
+            cleaned = cleaned.trim_end_matches(['.', ' ']).to_string();
+            let stem = cleaned.split('.').next().unwrap_or_default();
+            if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+                cleaned.insert(0, '_');
+            }
+        }
+        if cleaned.is_empty() {
+            cleaned.push('_');
+        }
+        cleaned
+    }
+
+    /// Makes a file name valid and short enough to be written into `folder` (relative to the
+    /// library root), keeping its extension. `reserve` units are left free for a ` (n)` clash
+    /// suffix.
+    pub fn sanitize_file_name(self, name: &str, folder: &Path, reserve: usize) -> String {
+        self.fit_file_name(&self.sanitize_component(name), folder, reserve)
+    }
+
+    /// Shortens an already sanitised file name like [`TargetFs::sanitize_file_name`].
+    pub fn fit_file_name(self, name: &str, folder: &Path, reserve: usize) -> String {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+            _ => (name.to_string(), String::new()),
+        };
+
+        let mut limit = MAX_NAME;
+        if self.is_windows() {
+            // One more unit for the separator before the name
+            let folder_len = self.name_len(&folder.to_string_lossy()) + 1;
+            limit = limit.min(WINDOWS_MAX_PATH.saturating_sub(folder_len));
+        }
+        let limit = limit.saturating_sub(reserve);
+
+        let mut stem = stem;
+        while !stem.is_empty() && self.name_len(&stem) + self.name_len(&ext) > limit {
+            stem.pop();
+        }
+        let stem = match self {
+            TargetFs::Exfat | TargetFs::Smb => stem.trim_end_matches(['.', ' ']).to_string(),
+            _ => stem,
+        };
+        if stem.is_empty() {
+            return format!("_{}", ext);
+        }
+        format!("{}{}", stem, ext)
+    }
+
+    /// Sanitises every component of a relative folder path.
+    pub fn sanitize_relative(self, path: &Path) -> PathBuf {
+        path.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(OsString::from(
+                    self.sanitize_component(&name.to_string_lossy()),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_component_for_windows_targets() {
+        let fs = TargetFs::Exfat;
+        assert_eq!(fs.sanitize_component("Trip: Paris?"), "Trip_ Paris_");
+        assert_eq!(fs.sanitize_component("notes..."), "notes");
+        assert_eq!(fs.sanitize_component("con.jpg"), "_con.jpg");
+        assert_eq!(fs.sanitize_component("..."), "_");
+        // Decomposed "é" is stored composed
+        assert_eq!(fs.sanitize_component("Cafe\u{301}.jpg"), "Caf\u{e9}.jpg");
+        assert_eq!(TargetFs::Hfs.sanitize_component("Caf\u{e9}"), "Cafe\u{301}");
+        assert_eq!(
+            TargetFs::Native.sanitize_component("Trip: Paris?"),
+            "Trip: Paris?"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_file_name_limits_length() {
+        let folder = Path::new("2019/July/04");
+        let long = format!("{}.jpg", "é".repeat(300));
+
+        let name = TargetFs::Hfs.sanitize_file_name(&long, folder, 0);
+        assert_eq!(name.encode_utf16().count(), MAX_NAME);
+        assert!(name.ends_with(".jpg"));
+
+        // Windows targets also keep the full path within 260 units, minus the clash reserve
+        let name = TargetFs::Smb.sanitize_file_name(&long, folder, 8);
+        let folder_len = folder.to_string_lossy().len() + 1;
+        assert_eq!(
+            name.encode_utf16().count(),
+            WINDOWS_MAX_PATH - folder_len - 8
+        );
+    }
+
+    #[test]
+    fn test_sanitize_relative() {
+        assert_eq!(
+            TargetFs::Smb.sanitize_relative(Path::new("Takeout/Album: 2019 */sub.")),
+            PathBuf::from("Takeout/Album_ 2019 _/sub")
+        );
+    }
+}
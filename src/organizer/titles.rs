@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::catalog::Catalog;
+use super::target_fs::TargetFs;
+use super::verify::hash_file;
+
+/// `IMG_0001.jpg` numbered `n`: `IMG_0001 (n).jpg`, or the name itself for 0.
+pub fn numbered(name: &str, n: usize) -> String {
+    if n == 0 {
+        return name.to_string();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, ext),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+/// Turns a sidecar `title` into a file name valid on `target` in `folder` (relative to the
+/// library root) that keeps the extension of the file it names (`extension`), since titles
+/// are free text and may lack or misstate it. Room is left for a ` (n)` clash suffix.
+pub fn sanitize_title(
+    title: &str,
+    extension: Option<&str>,
+    target: TargetFs,
+    folder: &Path,
+) -> Option<String> {
+    let cleaned = target.sanitize_component(title);
+    let cleaned = cleaned.trim().trim_end_matches('.');
+
+    let stem = match (Path::new(cleaned).extension(), extension) {
+        (Some(ext), Some(wanted)) if ext.to_string_lossy().eq_ignore_ascii_case(wanted) => {
+            Path::new(cleaned)
+                .file_stem()?
+                .to_string_lossy()
+                .to_string()
+        }
+        _ => cleaned.to_string(),
+    };
+    let stem = stem.trim_start_matches('.').trim();
+    if stem.is_empty() {
+        return None;
+    }
+
+    let suffix = extension.map(|e| format!(".{}", e)).unwrap_or_default();
+    Some(target.fit_file_name(&format!("{}{}", stem, suffix), folder, 8))
+}
+
+/// Output names handed out during a run, so files restored to the same title get distinct
+/// names (`IMG_0001.jpg`, `IMG_0001 (1).jpg`) and keep them on later runs.
+#[derive(Debug, Default)]
+pub struct NameRegistry {
+    /// Library root the claimed paths lie in
+    root: PathBuf,
+    /// Destination path -> Takeout-relative source that owns it.
+    claimed: Mutex<HashMap<PathBuf, String>>,
+    /// Names differing only in case clash on the target filesystem
+    case_insensitive: bool,
+}
+
+impl NameRegistry {
+    pub fn from_catalog(root: &Path, catalog: &Catalog) -> Self {
+        let claimed = catalog
+            .entries
+            .iter()
+            .map(|(key, entry)| (root.join(key), entry.source.clone()))
+            .collect();
+        Self {
+            root: root.to_path_buf(),
+            claimed: Mutex::new(claimed),
+            case_insensitive: false,
+        }
+    }
+
+    /// `folder` relative to the library root, as name lengths are measured from there.
+    pub fn library_folder<'a>(&self, folder: &'a Path) -> &'a Path {
+        folder.strip_prefix(&self.root).unwrap_or(folder)
+    }
+
+    /// Treats `IMG_1.JPG` and `img_1.jpg` as the same name, as FAT, SMB and macOS volumes do.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        if case_insensitive && let Ok(claimed) = self.claimed.get_mut() {
+            *claimed = claimed
+                .drain()
+                .map(|(dest, source)| (fold_case(&dest), source))
+                .collect();
+        }
+        self
+    }
+
+    /// Picks the first free variant of `name` in `folder` for the file from `source`.
+    pub fn claim(&self, folder: &Path, name: &str, source: &str, input_path: &Path) -> OsString {
+        self.claim_with(folder, source, input_path, |n| numbered(name, n))
+    }
+
+    /// Picks the first free name among `candidate(0)`, `candidate(1)`, ... A name already owned
+    /// by the same source is reused; an unclaimed file on disk with the same content is taken to
+    /// be an earlier copy of it.
+    pub fn claim_with(
+        &self,
+        folder: &Path,
+        source: &str,
+        input_path: &Path,
+        candidate: impl Fn(usize) -> String,
+    ) -> OsString {
+        for n in 0.. {
+            let name = candidate(n);
+            let dest = folder.join(&name);
+            let key = if self.case_insensitive {
+                fold_case(&dest)
+            } else {
+                dest.clone()
+            };
+
+            {
+                let Ok(mut claimed) = self.claimed.lock() else {
+                    return candidate(0).into();
+                };
+                match claimed.get(&key) {
+                    Some(owner) if owner == source => return name.into(),
+                    Some(_) => continue,
+                    None if dest == input_path || !dest.exists() => {
+                        claimed.insert(key, source.to_string());
+                        return name.into();
+                    }
+                    None => {}
+                }
+            }
+
+            // Hashed without holding the lock, so other workers keep claiming names meanwhile;
+            // the name must still be unclaimed afterwards
+            if same_content(input_path, &dest)
+                && let Ok(mut claimed) = self.claimed.lock()
+                && !claimed.contains_key(&key)
+            {
+                claimed.insert(key, source.to_string());
+                return name.into();
+            }
+        }
+        unreachable!("ran out of name variants")
+    }
+}
+
+fn fold_case(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().to_lowercase())
+}
+
+fn same_content(a: &Path, b: &Path) -> bool {
+    let len = |p: &Path| fs::metadata(p).map(|m| m.len()).ok();
+    len(a) == len(b)
+        && hash_file(a)
+            .ok()
+            .is_some_and(|h| hash_file(b).ok() == Some(h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_title() {
+        let folder = Path::new("2019/July/04");
+        let title = |title, ext| sanitize_title(title, ext, TargetFs::Native, folder);
+        assert_eq!(
+            title("Family dinner: part 2.jpg", Some("jpg")),
+            Some("Family dinner: part 2.jpg".to_string())
+        );
+        assert_eq!(
+            sanitize_title(
+                "Family dinner: part 2.jpg",
+                Some("jpg"),
+                TargetFs::Exfat,
+                folder
+            ),
+            Some("Family dinner_ part 2.jpg".to_string())
+        );
+        // The real extension wins over a missing or different one
+        assert_eq!(
+            title("holiday", Some("HEIC")),
+            Some("holiday.HEIC".to_string())
+        );
+        assert_eq!(
+            title("a/b.png", Some("jpg")),
+            Some("a_b.png.jpg".to_string())
+        );
+        assert_eq!(title(" .. ", Some("jpg")), None);
+        // Shortened with room for a clash suffix
+        let long = title(&"x".repeat(400), Some("jpg")).unwrap();
+        assert_eq!(long.len(), 255 - 8);
+    }
+
+    #[test]
+    fn test_registry_resolves_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.jpg");
+        fs::write(&input, "pixels").unwrap();
+        let folder = dir.path().join("2020/May/01");
+
+        let registry = NameRegistry::default();
+        assert_eq!(
+            registry.claim(&folder, "IMG_0001.jpg", "a/IMG_0001.jpg", &input),
+            "IMG_0001.jpg"
+        );
+        assert_eq!(
+            registry.claim(&folder, "IMG_0001.jpg", "b/IMG_0001(1).jpg", &input),
+            "IMG_0001 (1).jpg"
+        );
+        // Asking again for the same source gives the same name
+        assert_eq!(
+            registry.claim(&folder, "IMG_0001.jpg", "a/IMG_0001.jpg", &input),
+            "IMG_0001.jpg"
+        );
+    }
+
+    #[test]
+    fn test_registry_case_insensitive_clash() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.jpg");
+        fs::write(&input, "pixels").unwrap();
+        let folder = dir.path().join("2020/May/01");
+
+        let registry = NameRegistry::default().case_insensitive(true);
+        assert_eq!(
+            registry.claim(&folder, "IMG_1.JPG", "a/IMG_1.JPG", &input),
+            "IMG_1.JPG"
+        );
+        assert_eq!(
+            registry.claim(&folder, "img_1.jpg", "b/img_1.jpg", &input),
+            "img_1 (1).jpg"
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use super::cancel;
+use super::catalog::{Catalog, CatalogEntry};
+use super::date_utils::naive_to_utc;
+use super::fs_ops;
+use super::metadata::DateExtractor;
+use super::model::{DateSource, ResolvedDate};
+use super::overrides::{self, parse_override_date};
+use super::sequence::SequenceIndex;
+
+#[derive(Debug, Default)]
+pub struct TriageReport {
+    pub resolved: usize,
+    pub skipped: usize,
+}
+
+/// A date offered to the user, with where it came from.
+#[derive(Debug)]
+struct Candidate {
+    date: DateTime<Utc>,
+    label: String,
+}
+
+/// Files worth a manual look: undated, dated by a weak fallback, or contradicting their
+/// Takeout year folder.
+fn needs_triage(entry: &CatalogEntry) -> bool {
+    match entry.date_source {
+        None => entry.date.is_none(),
+        Some(DateSource::Override) => false,
+        Some(source) => source.is_low_confidence() || entry.folder_year.is_some(),
+    }
+}
+
+fn entry_date(entry: &CatalogEntry) -> Option<DateTime<Utc>> {
+    let date = DateTime::parse_from_rfc3339(entry.date.as_deref()?).ok()?;
+    Some(date.with_timezone(&Utc))
+}
+
+/// Walks through the files of the library at `root` that need a date decision, one at a
+/// time. Each answer is appended to `overrides_file` so later runs keep it, and the file is
+/// moved into its date folder right away.
+pub fn run_triage<R: BufRead, W: Write>(
+    root: &Path,
+    overrides_file: &Path,
+    input: &mut R,
+    out: &mut W,
+) -> Result<TriageReport> {
+    let mut catalog = Catalog::load(root)?;
+    let extractor = DateExtractor::new()?;
+    let mut report = TriageReport::default();
+
+    let mut index = SequenceIndex::default();
+    for entry in catalog.entries.values() {
+        if let (Some(date), Some(source)) = (entry_date(entry), entry.date_source)
+            && !source.is_low_confidence()
+            && entry.folder_year.is_none()
+        {
+            index.insert(Path::new(&entry.source), date);
+        }
+    }
+
+    let pending: Vec<String> = catalog
+        .entries
+        .iter()
+        .filter(|(key, entry)| needs_triage(entry) && fs_ops::should_process_file(&root.join(key)))
+        .map(|(key, _)| key.clone())
+        .collect();
+    if pending.is_empty() {
+        writeln!(out, "Nothing to triage.")?;
+        return Ok(report);
+    }
+
+    'files: for (position, key) in pending.iter().enumerate() {
+        if cancel::is_cancelled() {
+            break;
+        }
+        let Some(entry) = catalog.get(key).cloned() else {
+            continue;
+        };
+        let candidates = collect_candidates(root, key, &entry, &extractor, &index);
+
+        writeln!(out)?;
+        writeln!(out, "[{}/{}] {}", position + 1, pending.len(), key)?;
+        writeln!(out, "  source: {}", entry.source)?;
+        if let (Some(date), Some(source)) = (entry_date(&entry), entry.date_source) {
+            writeln!(
+                out,
+                "  current: {} ({:?})",
+                date.format("%Y-%m-%d %H:%M"),
+                source
+            )?;
+        }
+        for (number, candidate) in candidates.iter().enumerate() {
+            writeln!(
+                out,
+                "  {}) {}  {}",
+                number + 1,
+                candidate.date.format("%Y-%m-%d %H:%M"),
+                candidate.label
+            )?;
+        }
+
+        let date = loop {
+            write!(
+                out,
+                "Choose a number, type a date (YYYY-MM-DD [HH:MM]), [s]kip or [q]uit: "
+            )?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 || cancel::is_cancelled() {
+                break 'files;
+            }
+            let answer = line.trim();
+            match answer {
+                "" | "s" => {
+                    report.skipped += 1;
+                    continue 'files;
+                }
+                "q" => break 'files,
+                _ => {}
+            }
+
+            if let Ok(number) = answer.parse::<usize>()
+                && let Some(candidate) = number.checked_sub(1).and_then(|i| candidates.get(i))
+            {
+                break candidate.date;
+            }
+            match parse_override_date(answer) {
+                Ok(date) => break date,
+                Err(_) => writeln!(out, "  Not a candidate number or date: {}", answer)?,
+            }
+        };
+
+        overrides::append_override(overrides_file, &entry.source, date)?;
+        let resolved = ResolvedDate {
+            date,
+            source: DateSource::Override,
+        };
+        match overrides::move_to_date(root, &mut catalog, key, resolved) {
+            Ok(_) => report.resolved += 1,
+            Err(e) => writeln!(out, "  Could not move the file: {}", e)?,
+        }
+        // Save after every answer so quitting never loses work
+        catalog.save(root)?;
+    }
+
+    Ok(report)
+}
+
+fn collect_candidates(
+    root: &Path,
+    key: &str,
+    entry: &CatalogEntry,
+    extractor: &DateExtractor,
+    index: &SequenceIndex,
+) -> Vec<Candidate> {
+    let sidecar = entry
+        .sidecar_date
+        .as_deref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| Candidate {
+            date: date.with_timezone(&Utc),
+            label: "json sidecar".to_string(),
+        });
+    let mut candidates: Vec<Candidate> = sidecar
+        .into_iter()
+        .chain(
+            extractor
+                .candidates(&root.join(key))
+                .into_iter()
+                .map(|c| Candidate {
+                    date: c.date,
+                    label: format!("{:?}", c.source).to_lowercase(),
+                }),
+        )
+        .collect();
+
+    let source = Path::new(&entry.source);
+    if let Some(year) = extractor.folder_hint_year(source)
+        && let Some(date) = naive_to_utc(year, 1, 1)
+    {
+        candidates.push(Candidate {
+            date,
+            label: format!("folder hint (year {} only)", year),
+        });
+    }
+
+    let (before, after) = index.neighbours(source);
+    for (number, date) in before.into_iter().chain(after) {
+        candidates.push(Candidate {
+            date,
+            label: format!("neighbour #{} in sequence", number),
+        });
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::sniff;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn entry(source: &str, date: Option<&str>, date_source: Option<DateSource>) -> CatalogEntry {
+        CatalogEntry {
+            size: 6,
+            source: source.to_string(),
+            date: date.map(str::to_string),
+            date_source,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_triage_records_and_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("unknown")).unwrap();
+        fs::create_dir_all(root.join("2019/July/04")).unwrap();
+        fs::write(
+            root.join("unknown/IMG_4512.JPG"),
+            sniff::fake_jpeg(b"pixels"),
+        )
+        .unwrap();
+        fs::write(
+            root.join("unknown/IMG_9000.JPG"),
+            sniff::fake_jpeg(b"pixels"),
+        )
+        .unwrap();
+        fs::write(
+            root.join("2019/July/04/IMG_4511.JPG"),
+            sniff::fake_jpeg(b"pixels"),
+        )
+        .unwrap();
+
+        let mut catalog = Catalog::default();
+        catalog.insert(
+            "unknown/IMG_4512.JPG".to_string(),
+            entry("Takeout/Trip/IMG_4512.JPG", None, None),
+        );
+        catalog.insert(
+            "unknown/IMG_9000.JPG".to_string(),
+            entry("Takeout/Trip/IMG_9000.JPG", None, None),
+        );
+        catalog.insert(
+            "2019/July/04/IMG_4511.JPG".to_string(),
+            entry(
+                "Takeout/Trip/IMG_4511.JPG",
+                Some("2019-07-04T10:00:00+00:00"),
+                Some(DateSource::Exif),
+            ),
+        );
+        catalog.save(root).unwrap();
+
+        let overrides_file = dir.path().join("overrides.csv");
+        // Accept the neighbour for the first file, type a bad then a good date for the second
+        let mut input = Cursor::new("1\nlater\n2003-05-06\n");
+        let mut out = Vec::new();
+        let report = run_triage(root, &overrides_file, &mut input, &mut out).unwrap();
+        assert_eq!(report.resolved, 2);
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("neighbour #4511 in sequence"));
+        assert!(out.contains("Not a candidate number or date: later"));
+
+        assert!(root.join("2019/July/04/IMG_4512.JPG").exists());
+        assert!(root.join("2003/May/06/IMG_9000.JPG").exists());
+
+        let overrides = overrides::Overrides::load(&overrides_file).unwrap();
+        assert!(
+            overrides
+                .lookup(Path::new("Takeout/Trip/IMG_9000.JPG"), || None)
+                .is_some()
+        );
+
+        // Nothing left afterwards
+        let mut out = Vec::new();
+        let report = run_triage(root, &overrides_file, &mut Cursor::new(""), &mut out).unwrap();
+        assert_eq!(report.resolved, 0);
+    }
+
+    #[test]
+    fn test_triage_organized_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("Trip");
+        let root = dir.path().join("library");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("IMG_4511.jpg"), sniff::fake_jpeg(b"dated")).unwrap();
+        fs::write(
+            input.join("IMG_4511.jpg.json"),
+            r#"{"photoTakenTime": {"timestamp": "1562234400"}}"#,
+        )
+        .unwrap();
+        fs::write(input.join("IMG_4512.jpg"), sniff::fake_jpeg(b"undated")).unwrap();
+        crate::organizer::organize_files(&[input.as_path()], &root, &Default::default()).unwrap();
+
+        // The sidecar stayed in the Takeout, so its date has to come from the catalog
+        let catalog = Catalog::load(&root).unwrap();
+        let dated = &catalog.entries["2019/July/04/IMG_4511.jpg"];
+        assert_eq!(
+            dated.sidecar_date.as_deref(),
+            Some("2019-07-04T10:00:00+00:00")
+        );
+
+        let overrides_file = dir.path().join("overrides.csv");
+        let mut out = Vec::new();
+        let report = run_triage(&root, &overrides_file, &mut Cursor::new("1\n"), &mut out).unwrap();
+        assert_eq!(report.resolved, 1);
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("neighbour #4511 in sequence")
+        );
+        assert!(root.join("2019/July/04/IMG_4512.jpg").exists());
+    }
+}
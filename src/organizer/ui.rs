@@ -1,9 +1,13 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::{LevelFilter, Metadata, Record};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 static PROGRESS_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
 
+/// Cleared when progress is reported another way, e.g. as JSON events.
+static BARS_ENABLED: AtomicBool = AtomicBool::new(true);
+
 struct IndicatifLogger;
 
 impl log::Log for IndicatifLogger {
@@ -15,7 +19,8 @@ impl log::Log for IndicatifLogger {
         if self.enabled(record.metadata()) {
             let msg = format!("[{}] {}", record.level(), record.args());
             if let Ok(pb_opt) = PROGRESS_BAR.lock() {
-                if let Some(pb) = &*pb_opt {
+                // Hidden bars (no terminal, JSON progress) drop what they print
+                if let Some(pb) = pb_opt.as_ref().filter(|pb| !pb.is_hidden()) {
                     pb.println(msg);
                 } else {
                     eprintln!("{}", msg);
@@ -53,8 +58,28 @@ pub fn set_global_progress_bar(pb: ProgressBar) {
     }
 }
 
+/// Stops drawing progress bars for the rest of the run.
+pub fn disable_bars() {
+    BARS_ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// A container for the bars of several phases, hidden when bars are disabled. Indicatif
+/// already hides them when standard error is not a terminal.
+pub fn multi_progress() -> MultiProgress {
+    if BARS_ENABLED.load(Ordering::Relaxed) {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    }
+}
+
 pub fn create_progress_bar(total_files: u64) -> ProgressBar {
-    let progress_bar = ProgressBar::new(total_files);
+    let progress_bar = if BARS_ENABLED.load(Ordering::Relaxed) {
+        ProgressBar::new(total_files)
+    } else {
+        ProgressBar::hidden()
+    };
+    progress_bar.set_length(total_files);
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template(
@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use super::catalog::{CATALOG_FILE, Catalog, catalog_key};
+use super::clock_skew;
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub verified: usize,
+    /// Files whose content no longer matches the stored checksum (or size).
+    pub corrupted: Vec<String>,
+    /// Catalog entries whose file is gone from the library.
+    pub missing: Vec<String>,
+    /// Files in the library that the catalog knows nothing about.
+    pub unexpected: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Computes the SHA-256 of a file as a lowercase hex string.
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open {:?} for hashing", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Re-hashes every file in the library at `root` and compares it with the catalog.
+pub fn verify_library(root: &Path) -> Result<VerifyReport> {
+    let catalog = Catalog::load_existing(root)?;
+    info!(
+        "Verifying {} cataloged files in {:?}",
+        catalog.entries.len(),
+        root
+    );
+
+    let on_disk: HashSet<String> = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| !is_generated(e.path(), root))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| catalog_key(root, e.path()))
+        .collect();
+
+    let mut report = VerifyReport::default();
+
+    let results: Vec<(String, Option<bool>)> = catalog
+        .entries
+        .par_iter()
+        .map(|(key, entry)| {
+            if !on_disk.contains(key) {
+                return (key.clone(), None);
+            }
+            let path = root.join(key);
+            let intact = match &entry.sha256 {
+                Some(expected) => hash_file(&path).map(|h| &h == expected).unwrap_or(false),
+                None => fs::metadata(&path)
+                    .map(|m| m.len() == entry.size)
+                    .unwrap_or(false),
+            };
+            (key.clone(), Some(intact))
+        })
+        .collect();
+
+    for (key, result) in results {
+        match result {
+            Some(true) => report.verified += 1,
+            Some(false) => report.corrupted.push(key),
+            None => report.missing.push(key),
+        }
+    }
+
+    report.unexpected = on_disk
+        .into_iter()
+        .filter(|key| catalog.get(key).is_none())
+        .collect();
+    report.unexpected.sort();
+
+    Ok(report)
+}
+
+/// Logs a verification report in a human readable form.
+pub fn log_report(report: &VerifyReport) {
+    info!("Verified OK: {}", report.verified);
+    for key in &report.corrupted {
+        warn!("  CORRUPTED: {}", key);
+    }
+    for key in &report.missing {
+        warn!("  MISSING:   {}", key);
+    }
+    for key in &report.unexpected {
+        warn!("  UNEXPECTED: {}", key);
+    }
+    info!(
+        "Corrupted: {}, Missing: {}, Unexpected: {}",
+        report.corrupted.len(),
+        report.missing.len(),
+        report.unexpected.len()
+    );
+}
+
+/// Files the tool itself writes into the library (catalog, gallery pages, thumbnails).
+pub fn is_generated(path: &Path, root: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if path.is_dir() {
+        return name == ".thumbnails";
+    }
+    name == CATALOG_FILE
+        || (name == clock_skew::REPORT_FILE && path.parent() == Some(root))
+        || name == "index.html"
+        || (name == "collection.html" && path.parent() == Some(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::catalog::CatalogEntry;
+
+    #[test]
+    fn test_hash_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "hello").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_verify_library_reports_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("2023/May/20")).unwrap();
+        fs::write(root.join("2023/May/20/ok.jpg"), "ok").unwrap();
+        fs::write(root.join("2023/May/20/rot.jpg"), "rotten").unwrap();
+        fs::write(root.join("2023/May/20/extra.jpg"), "extra").unwrap();
+        fs::write(root.join("2023/May/20/index.html"), "<html>").unwrap();
+
+        let mut catalog = Catalog::default();
+        for (key, content) in [
+            ("2023/May/20/ok.jpg", "ok"),
+            ("2023/May/20/rot.jpg", "fresh!"),
+            ("2023/May/20/gone.jpg", "gone"),
+        ] {
+            let tmp = root.join("tmp");
+            fs::write(&tmp, content).unwrap();
+            catalog.insert(
+                key.to_string(),
+                CatalogEntry {
+                    size: content.len() as u64,
+                    sha256: Some(hash_file(&tmp).unwrap()),
+                    source: key.to_string(),
+                    ..Default::default()
+                },
+            );
+            fs::remove_file(&tmp).unwrap();
+        }
+        catalog.save(root).unwrap();
+
+        let report = verify_library(root).unwrap();
+        assert_eq!(report.verified, 1);
+        assert_eq!(report.corrupted, vec!["2023/May/20/rot.jpg"]);
+        assert_eq!(report.missing, vec!["2023/May/20/gone.jpg"]);
+        assert_eq!(report.unexpected, vec!["2023/May/20/extra.jpg"]);
+        assert!(!report.is_clean());
+    }
+}
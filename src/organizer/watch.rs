@@ -0,0 +1,432 @@
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use notify::{RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+use super::OrganizeOptions;
+use super::archive;
+use super::cancel;
+use super::catalog::Catalog;
+use super::organize_files;
+use super::progress::{self, Event};
+use super::report::{self, RunOutcome};
+use super::takeout_parts::parse_part_name;
+use super::titles::numbered;
+
+/// Folder inside each inbox that processed items are moved to.
+pub const DONE_DIR: &str = "done";
+
+/// Extensions browsers and copy tools give files that are still being written.
+const PARTIAL_EXTENSIONS: [&str; 5] = ["crdownload", "part", "partial", "download", "tmp"];
+
+/// How often the inboxes are rescanned when no filesystem event arrives.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Size and age of an inbox item (an archive, or a folder of phone dumps).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    size: u64,
+    files: usize,
+    modified: Option<SystemTime>,
+}
+
+impl Snapshot {
+    pub fn of(path: &Path) -> Option<Self> {
+        let mut snapshot = Self {
+            size: 0,
+            files: 0,
+            modified: None,
+        };
+        for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+            let metadata = entry.metadata().ok()?;
+            if metadata.is_file() {
+                snapshot.size += metadata.len();
+                snapshot.files += 1;
+            }
+            snapshot.modified = snapshot.modified.max(metadata.modified().ok());
+        }
+        Some(snapshot)
+    }
+}
+
+/// Export a Takeout part belongs to (`takeout-20240105T101500Z-002.zip` ->
+/// `20240105T101500Z`), `None` for other archives and folders.
+fn takeout_export(path: &Path) -> Option<String> {
+    parse_part_name(path).map(|(export, _)| export)
+}
+
+/// Inbox items seen so far and since when they have stopped changing.
+#[derive(Debug, Default)]
+pub struct SettleTracker {
+    seen: HashMap<PathBuf, (Snapshot, Instant)>,
+    /// Items that failed to organize, ignored until they or another part of their Takeout
+    /// change again
+    failed: HashMap<PathBuf, Snapshot>,
+}
+
+impl SettleTracker {
+    /// Records the state of the current inbox `items` at `now` and returns the ones that have
+    /// not changed for `settle`, which are taken to be completely written. Failed parts of a
+    /// Takeout come back with a newly settled part of the same export, since they may only
+    /// have failed for lack of it.
+    pub fn update(
+        &mut self,
+        items: Vec<(PathBuf, Snapshot)>,
+        now: Instant,
+        settle: Duration,
+    ) -> Vec<PathBuf> {
+        let present: BTreeSet<PathBuf> = items.iter().map(|(path, _)| path.clone()).collect();
+        self.seen.retain(|path, _| present.contains(path));
+        self.failed.retain(|path, _| present.contains(path));
+
+        let mut ready = Vec::new();
+        for (path, snapshot) in items {
+            match self.failed.get(&path) {
+                Some(failed) if *failed == snapshot => continue,
+                Some(_) => {
+                    self.failed.remove(&path);
+                }
+                None => {}
+            }
+            let since = match self.seen.get(&path) {
+                Some((seen, since)) if *seen == snapshot => *since,
+                _ => now,
+            };
+            self.seen.insert(path.clone(), (snapshot, since));
+            // Empty files are usually placeholders a download is about to fill
+            if snapshot.size > 0 && now.duration_since(since) >= settle {
+                ready.push(path);
+            }
+        }
+
+        let exports: BTreeSet<String> = ready.iter().filter_map(|p| takeout_export(p)).collect();
+        let siblings: Vec<PathBuf> = self
+            .failed
+            .keys()
+            .filter(|path| takeout_export(path).is_some_and(|e| exports.contains(&e)))
+            .cloned()
+            .collect();
+        for path in siblings {
+            if let Some(snapshot) = self.failed.remove(&path) {
+                self.seen.insert(path.clone(), (snapshot, now));
+                ready.push(path);
+            }
+        }
+        ready.sort();
+        ready
+    }
+
+    pub fn mark_failed(&mut self, path: &Path) {
+        if let Some((snapshot, _)) = self.seen.remove(path) {
+            self.failed.insert(path.to_path_buf(), snapshot);
+        }
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.seen.remove(path);
+    }
+}
+
+/// Archives and folders waiting in `inbox`, skipping the done folder, hidden files and
+/// downloads still in progress.
+pub fn pending_items(inbox: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(inbox) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let partial = path.extension().is_some_and(|ext| {
+                PARTIAL_EXTENSIONS
+                    .iter()
+                    .any(|p| ext.eq_ignore_ascii_case(p))
+            });
+            name != DONE_DIR && !name.starts_with('.') && !partial
+        })
+        .collect()
+}
+
+/// Watches `inboxes` and organizes every archive or folder dropped into them into `output`
+/// once it has stopped changing for `settle`, then moves it to the inbox's done folder.
+/// `after_batch` gets the library files each batch added. Runs until Ctrl-C.
+pub fn watch(
+    inboxes: &[PathBuf],
+    output: &Path,
+    options: &OrganizeOptions,
+    settle: Duration,
+    mut after_batch: impl FnMut(&[PathBuf]) -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })?;
+    for inbox in inboxes {
+        fs::create_dir_all(inbox.join(DONE_DIR))
+            .with_context(|| format!("Failed to create done folder in {:?}", inbox))?;
+        watcher
+            .watch(inbox, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {:?}", inbox))?;
+        info!("Watching {:?} for new Takeouts", inbox);
+    }
+
+    let mut tracker = SettleTracker::default();
+    loop {
+        // Events only wake the loop early: readiness is decided by rescanning sizes
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Err(e)) => warn!("Watch error: {}", e),
+            Err(RecvTimeoutError::Disconnected) => bail!("Inbox watcher stopped"),
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+        }
+        while rx.try_recv().is_ok() {}
+        if cancel::is_cancelled() {
+            info!("Stopped watching");
+            return Ok(());
+        }
+
+        let items = inboxes
+            .iter()
+            .flat_map(|inbox| pending_items(inbox))
+            .filter_map(|path| Snapshot::of(&path).map(|snapshot| (path, snapshot)))
+            .collect();
+        let ready = tracker.update(items, Instant::now(), settle);
+        if ready.is_empty() {
+            continue;
+        }
+
+        // Parts of one Takeout are organized together so they are checked as a set; parts of
+        // different exports never share a batch
+        let mut batches: BTreeMap<Option<String>, Vec<PathBuf>> = BTreeMap::new();
+        for item in ready {
+            if !is_organizable(&item) {
+                // Retried only once it changes
+                warn!(
+                    "Skipping {:?}: not an archive or folder; leaving it in the inbox",
+                    item
+                );
+                tracker.mark_failed(&item);
+                continue;
+            }
+            batches.entry(takeout_export(&item)).or_default().push(item);
+        }
+        for batch in batches.into_values() {
+            if cancel::is_cancelled() {
+                break;
+            }
+            info!("Organizing {} new item(s): {:?}", batch.len(), batch);
+            let outcome = organize_batch(&batch, output, options);
+            let changed = match &outcome {
+                Ok((_, _, changed)) => changed.as_slice(),
+                Err(_) => &[],
+            };
+            if !changed.is_empty()
+                && let Err(e) = after_batch(changed)
+            {
+                warn!("Failed to update the gallery: {}", e);
+            }
+            match outcome {
+                Ok((RunOutcome::Success, 0, _)) => {
+                    warn!(
+                        "Batch {:?} held no files to organize; leaving it in the inbox",
+                        batch
+                    );
+                    for item in &batch {
+                        tracker.mark_failed(item);
+                    }
+                }
+                Ok((RunOutcome::Success, _, _)) => {
+                    for item in &batch {
+                        tracker.forget(item);
+                        if let Err(e) = move_to_done(item) {
+                            warn!("{}", e);
+                        }
+                    }
+                }
+                Ok((outcome, _, _)) => {
+                    // Left in the inbox, to be completed by a later run
+                    warn!(
+                        "Batch {:?} ended {:?}; leaving it in the inbox",
+                        batch, outcome
+                    );
+                    for item in &batch {
+                        tracker.mark_failed(item);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to organize {:?}: {:#}", batch, e);
+                    for item in &batch {
+                        tracker.mark_failed(item);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether `item` is something a run can organize: a folder or an archive.
+fn is_organizable(item: &Path) -> bool {
+    item.is_dir() || archive::is_archive(item)
+}
+
+/// Organizes `items` together and returns how it went, how many files it came across and the
+/// library files that were added or updated.
+fn organize_batch(
+    items: &[PathBuf],
+    output: &Path,
+    options: &OrganizeOptions,
+) -> Result<(RunOutcome, usize, Vec<PathBuf>)> {
+    let before = Catalog::load(output)?.entries;
+    let inputs: Vec<&Path> = items.iter().map(PathBuf::as_path).collect();
+    let report = organize_files(&inputs, output, options)?;
+    report::log_summary(&report);
+    progress::emit(&Event::Totals {
+        outcome: report.outcome(),
+        counts: &report.counts,
+    });
+    let after = Catalog::load(output)?;
+    let changed = after
+        .entries
+        .into_iter()
+        .filter(|(key, entry)| before.get(key) != Some(entry))
+        .map(|(key, _)| output.join(key))
+        .collect();
+    Ok((report.outcome(), report.counts.total(), changed))
+}
+
+/// Moves a processed item into `done/` next to it, numbering it if the name is taken.
+fn move_to_done(item: &Path) -> Result<PathBuf> {
+    let (Some(inbox), Some(name)) = (item.parent(), item.file_name()) else {
+        bail!("Invalid inbox item {:?}", item);
+    };
+    let done = inbox.join(DONE_DIR);
+    fs::create_dir_all(&done)?;
+    let name = name.to_string_lossy();
+    let dest = (0..)
+        .map(|n| done.join(numbered(&name, n)))
+        .find(|dest| !dest.exists())
+        .unwrap_or_else(|| done.join(name.as_ref()));
+    fs::rename(item, &dest).with_context(|| format!("Failed to move {:?} to {:?}", item, dest))?;
+    info!("Moved {:?} to {:?}", item, dest);
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(size: u64) -> Snapshot {
+        Snapshot {
+            size,
+            files: 1,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn test_tracker_waits_for_stable_size() {
+        let settle = Duration::from_secs(10);
+        let start = Instant::now();
+        let item = PathBuf::from("inbox/takeout-001.zip");
+        let mut tracker = SettleTracker::default();
+
+        assert!(
+            tracker
+                .update(vec![(item.clone(), snapshot(100))], start, settle)
+                .is_empty()
+        );
+        // Still growing: the clock restarts
+        let later = start + Duration::from_secs(8);
+        assert!(
+            tracker
+                .update(vec![(item.clone(), snapshot(200))], later, settle)
+                .is_empty()
+        );
+        let settled = later + Duration::from_secs(10);
+        assert_eq!(
+            tracker.update(vec![(item.clone(), snapshot(200))], settled, settle),
+            vec![item.clone()]
+        );
+
+        // A failed item is skipped until it changes
+        tracker.mark_failed(&item);
+        let next = settled + Duration::from_secs(30);
+        assert!(
+            tracker
+                .update(vec![(item.clone(), snapshot(200))], next, settle)
+                .is_empty()
+        );
+        assert!(
+            tracker
+                .update(vec![(item.clone(), snapshot(300))], next, settle)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_tracker_retries_failed_part_with_its_sibling() {
+        let settle = Duration::from_secs(10);
+        let start = Instant::now();
+        let first = PathBuf::from("inbox/takeout-20240105T101500Z-001.zip");
+        let second = PathBuf::from("inbox/takeout-20240105T101500Z-002.zip");
+        let other = PathBuf::from("inbox/takeout-20230101T000000Z-001.zip");
+        let mut tracker = SettleTracker::default();
+
+        let items = |second_size| {
+            vec![
+                (first.clone(), snapshot(100)),
+                (second.clone(), snapshot(second_size)),
+                (other.clone(), snapshot(100)),
+            ]
+        };
+        tracker.update(items(10), start, settle);
+        let settled = start + settle;
+        // Part 2 is still downloading: part 1 fails on its own
+        assert_eq!(
+            tracker.update(items(20), settled, settle),
+            vec![other.clone(), first.clone()]
+        );
+        tracker.mark_failed(&first);
+        tracker.mark_failed(&other);
+
+        let done = settled + settle;
+        assert_eq!(
+            tracker.update(items(20), done, settle),
+            vec![first.clone(), second.clone()]
+        );
+    }
+
+    #[test]
+    fn test_pending_items_and_move_to_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = dir.path();
+        fs::create_dir(inbox.join(DONE_DIR)).unwrap();
+        fs::create_dir(inbox.join("phone-dump")).unwrap();
+        fs::write(inbox.join("takeout-001.zip"), "zip").unwrap();
+        fs::write(inbox.join("takeout-002.zip.crdownload"), "zi").unwrap();
+        fs::write(inbox.join(".DS_Store"), "").unwrap();
+
+        let mut pending = pending_items(inbox);
+        pending.sort();
+        assert_eq!(
+            pending,
+            vec![inbox.join("phone-dump"), inbox.join("takeout-001.zip")]
+        );
+
+        // Only folders and real archives get organized
+        assert!(is_organizable(&inbox.join("phone-dump")));
+        assert!(!is_organizable(&inbox.join("takeout-001.zip")));
+        fs::write(inbox.join("IMG_0001.jpg"), b"\xff\xd8\xff photo").unwrap();
+        assert!(!is_organizable(&inbox.join("IMG_0001.jpg")));
+
+        fs::write(inbox.join(DONE_DIR).join("takeout-001.zip"), "old").unwrap();
+        let dest = move_to_done(&inbox.join("takeout-001.zip")).unwrap();
+        assert_eq!(dest, inbox.join(DONE_DIR).join("takeout-001 (1).zip"));
+        assert!(!inbox.join("takeout-001.zip").exists());
+    }
+}